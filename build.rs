@@ -0,0 +1,11 @@
+//!Generates the protocol bindings from the XML descriptions in `xcb-proto`, see `xeagle-codegen`.
+use std::env;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-changed=xcb-proto");
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("cargo sets OUT_DIR for build scripts"));
+    if let Err(e) = xeagle_codegen::generate(Path::new("xcb-proto"), &out_dir) {
+        panic!("generating the protocol bindings failed: {}", e);
+    }
+}
@@ -1,6 +1,8 @@
 //!Provides an interface for interacting with X11 and manages several key elements necessary for using it.
 #[warn(unused_imports)]
+use std::error::Error;
 use std::fmt::{Display, Formatter};
+use crate::setup::Setup;
 use crate::xinterface::{XInterface, XInterfaceError};
 
 #[derive(Debug)]
@@ -10,7 +12,17 @@ pub enum ApplicationError{
 
 impl Display for ApplicationError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ApplicationError[{:?}]", self)
+        match self {
+            ApplicationError::XInterfaceError(_) => write!(f, "failed to set up the X connection")
+        }
+    }
+}
+
+impl Error for ApplicationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ApplicationError::XInterfaceError(e) => Some(e)
+        }
     }
 }
 
@@ -22,13 +34,23 @@ impl From<XInterfaceError> for ApplicationError{
 
 ///Handles initialization of connection to X11 and provides an interface for using it.
 pub struct Application {
-    _x_interface: XInterface
+    x_interface: XInterface
 }
 
 #[warn(missing_docs)]
 impl Application {
-    ///Generates a new application with an X11 connection.
+    ///Generates a new application connected to the display named by `DISPLAY`.
     pub fn new() -> Result<Self, ApplicationError> {
-        Ok(Self {_x_interface: XInterface::new("/tmp/.X11-unix/X0")?})
+        Ok(Self {x_interface: XInterface::new()?})
+    }
+
+    ///Generates a new application connected to the given display, e.g. `:1` or `localhost:10.0`.
+    pub fn with_display(display_name: &str) -> Result<Self, ApplicationError> {
+        Ok(Self {x_interface: XInterface::with_display(display_name)?})
+    }
+
+    ///What the server told about itself, including its screens.
+    pub fn setup(&self) -> &Setup {
+        self.x_interface.setup()
     }
 }
\ No newline at end of file
@@ -0,0 +1,63 @@
+//!Authorization protocols that take part in the connection setup.
+use crate::xinterface::XInterfaceError;
+
+///The client side of an authorization protocol, used while the connection is set up.
+///
+///The name and initial data go into the setup request. Most protocols stop there and the server
+///either accepts or refuses the connection. Challenge based protocols make the server answer with
+///status 2 (Authenticate) instead, in which case [`Authenticator::respond`] is handed the data the
+///server sent and its answer is written back, until the server accepts or refuses.
+///
+///Pass an implementation to [`XInterface::connect_with_authenticator`] or
+///[`XInterface::with_authenticator`] to use a scheme this library does not know.
+///
+///[`XInterface::connect_with_authenticator`]: crate::xinterface::XInterface::connect_with_authenticator
+///[`XInterface::with_authenticator`]: crate::xinterface::XInterface::with_authenticator
+pub trait Authenticator {
+    ///The name of the protocol, e.g. `MIT-MAGIC-COOKIE-1`, or empty for no authorization.
+    fn name(&self) -> &[u8];
+
+    ///The data sent along with the name in the setup request.
+    fn initial_data(&mut self) -> Result<Vec<u8>, XInterfaceError>;
+
+    ///Answers a status 2 reply carrying `challenge`, the server's data without the 8 byte header.
+    ///
+    ///The returned bytes are sent to the server as they are, including any padding the protocol
+    ///asks for. `None` gives up, and the connection fails with
+    ///[`XInterfaceError::AuthRequested`]. The default gives up right away.
+    fn respond(&mut self, challenge: &[u8]) -> Result<Option<Vec<u8>>, XInterfaceError> {
+        let _ = challenge;
+        Ok(None)
+    }
+}
+
+///Sends a fixed name and data and does not take part in any further exchange.
+///
+///This covers `MIT-MAGIC-COOKIE-1`, XDM-AUTHORIZATION-1 once its data has been computed, and
+///connecting without authorization.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CookieAuthenticator {
+    name: Vec<u8>,
+    data: Vec<u8>
+}
+
+impl CookieAuthenticator {
+    pub fn new(name: Vec<u8>, data: Vec<u8>) -> Self {
+        Self {name, data}
+    }
+
+    ///Connects without authorization, relying on the server's host based access control.
+    pub fn none() -> Self {
+        Self::default()
+    }
+}
+
+impl Authenticator for CookieAuthenticator {
+    fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    fn initial_data(&mut self) -> Result<Vec<u8>, XInterfaceError> {
+        Ok(self.data.clone())
+    }
+}
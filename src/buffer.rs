@@ -0,0 +1,386 @@
+//!The receive buffer that splits the server's byte stream into packets.
+use std::cell::UnsafeCell;
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+use crate::event::{GENERIC_EVENT, REPLY};
+use crate::serializable::{ByteOrder, Serializable};
+
+///Initial size of the receive buffer, enough for a few hundred events.
+const DEFAULT_CAPACITY: usize = 16384;
+
+///Everything the server sends after the setup is at least this long.
+const MIN_PACKET_LEN: usize = 32;
+
+///Memory the transport reads into and packets are handed out from.
+///
+///The buffer only writes bytes that are not part of a packet, so packets can be read from any
+///thread while it fills the rest.
+struct Chunk(Box<[UnsafeCell<u8>]>);
+
+//Every byte is either owned by the buffer, which writes it through `&mut`, or part of a packet,
+//which only reads it. No byte is ever both.
+unsafe impl Sync for Chunk {}
+
+impl Chunk {
+    fn new(len: usize) -> Arc<Self> {
+        Arc::new(Self::from(vec![0; len]))
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    ///The bytes in `range`, which nothing may write to while they are borrowed.
+    unsafe fn slice(&self, range: Range<usize>) -> &[u8] {
+        assert!(range.start <= range.end && range.end <= self.len());
+        std::slice::from_raw_parts(UnsafeCell::raw_get(self.0.as_ptr().add(range.start)), range.len())
+    }
+
+    ///The bytes in `range`, which nothing else may refer to while they are borrowed.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn slice_mut(&self, range: Range<usize>) -> &mut [u8] {
+        assert!(range.start <= range.end && range.end <= self.len());
+        std::slice::from_raw_parts_mut(UnsafeCell::raw_get(self.0.as_ptr().add(range.start)), range.len())
+    }
+}
+
+impl From<Vec<u8>> for Chunk {
+    fn from(bytes: Vec<u8>) -> Self {
+        //UnsafeCell<u8> has the same layout as u8.
+        Self(unsafe { Box::from_raw(Box::into_raw(bytes.into_boxed_slice()) as *mut [UnsafeCell<u8>]) })
+    }
+}
+
+///A complete reply, event or error, borrowed from the memory it was received into.
+///
+///Cloning a packet or sending it to another thread never copies its bytes. The memory is shared
+///with the packets received along with it and is freed or reused once the last of them is dropped.
+#[derive(Clone)]
+pub struct Packet {
+    chunk: Arc<Chunk>,
+    start: usize,
+    len: usize
+}
+
+impl Deref for Packet {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        //The buffer handed these bytes out and never writes them again.
+        unsafe { self.chunk.slice(self.start..self.start + self.len) }
+    }
+}
+
+impl AsRef<[u8]> for Packet {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for Packet {
+    ///Wraps bytes that did not come from the server, such as an event to be sent with `SendEvent`.
+    fn from(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        Self {chunk: Arc::new(Chunk::from(bytes)), start: 0, len}
+    }
+}
+
+impl Debug for Packet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl PartialEq for Packet {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Packet {}
+
+impl PartialEq<[u8]> for Packet {
+    fn eq(&self, other: &[u8]) -> bool {
+        **self == *other
+    }
+}
+
+impl PartialEq<Vec<u8>> for Packet {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        **self == **other
+    }
+}
+
+///The receive buffer, the bytes received from the server that have not been handed out yet.
+///
+///Data is read from the transport straight into the free space at the end of the storage, and
+///complete events, errors and replies are handed out as [`Packet`]s that point into it. Once the
+///end is reached, the bytes of the incomplete packet are moved to the start, or to new storage if
+///packets still refer to the old one. The storage only grows when a single reply does not fit.
+pub(crate) struct ReadBuffer {
+    chunk: Arc<Chunk>,
+    ///Where the bytes that have not been handed out start.
+    head: usize,
+    len: usize,
+    ///The byte order of the connection, needed to find the length of replies.
+    order: ByteOrder
+}
+
+impl ReadBuffer {
+    pub(crate) fn new(order: ByteOrder) -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY, order)
+    }
+
+    pub(crate) fn with_capacity(capacity: usize, order: ByteOrder) -> Self {
+        Self {chunk: Chunk::new(capacity.max(MIN_PACKET_LEN)), head: 0, len: 0, order}
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.chunk.len()
+    }
+
+    ///The free space after the received bytes, to be filled by a read from the transport.
+    ///
+    ///Call [`ReadBuffer::commit`] with the number of bytes actually read. Room is made first if the
+    ///packet at the front would not fit otherwise, so this never returns an empty slice.
+    pub(crate) fn free_space(&mut self) -> &mut [u8] {
+        let needed = self.len + self.bytes_missing();
+        if self.head + self.len == self.capacity() || self.head + needed > self.capacity() {
+            self.make_room(needed);
+        }
+
+        let (start, end) = (self.head + self.len, self.capacity());
+        //Packets end at `head` at the latest, and `&mut self` keeps this the only borrow.
+        unsafe { self.chunk.slice_mut(start..end) }
+    }
+
+    ///Marks `count` bytes at the start of [`ReadBuffer::free_space`] as received.
+    pub(crate) fn commit(&mut self, count: usize) {
+        assert!(self.head + self.len + count <= self.capacity(), "committed more bytes than there was free space");
+        self.len += count;
+    }
+
+    ///Copies bytes into the buffer, for data that did not come through [`ReadBuffer::free_space`].
+    #[cfg(test)]
+    pub(crate) fn extend_from_slice(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let free = self.free_space();
+            let count = free.len().min(data.len());
+            free[..count].copy_from_slice(&data[..count]);
+            self.commit(count);
+            data = &data[count..];
+        }
+    }
+
+    ///How many more bytes are needed before the packet at the front is complete.
+    pub(crate) fn bytes_missing(&self) -> usize {
+        if self.len < MIN_PACKET_LEN {
+            return MIN_PACKET_LEN - self.len;
+        }
+        packet_length(self.received(), self.order).saturating_sub(self.len)
+    }
+
+    ///Hands out the packet at the front, if it has been received completely.
+    pub(crate) fn next_packet(&mut self) -> Option<Packet> {
+        if self.len < MIN_PACKET_LEN {
+            return None;
+        }
+
+        let length = packet_length(self.received(), self.order);
+        if self.len < length {
+            return None;
+        }
+
+        let packet = Packet {chunk: Arc::clone(&self.chunk), start: self.head, len: length};
+        self.head += length;
+        self.len -= length;
+        Some(packet)
+    }
+
+    ///The bytes that have been received but not handed out.
+    fn received(&self) -> &[u8] {
+        //Only `free_space` writes, and never to these bytes.
+        unsafe { self.chunk.slice(self.head..self.head + self.len) }
+    }
+
+    ///Moves the bytes that have not been handed out to the start of the storage, so at least
+    ///`needed` bytes fit after them.
+    ///
+    ///The storage is only reused if no packet refers to it any more, otherwise they go to a new
+    ///one, twice as large if they would not fit.
+    fn make_room(&mut self, needed: usize) {
+        let capacity = if needed > self.capacity() || self.len == self.capacity() {
+            needed.max(self.capacity() * 2)
+        }else{
+            self.capacity()
+        };
+        let (head, len) = (self.head, self.len);
+
+        if capacity == self.capacity() && Arc::get_mut(&mut self.chunk).is_some() {
+            //Nothing else holds the storage, so no byte of it is borrowed.
+            unsafe { self.chunk.slice_mut(0..capacity) }.copy_within(head..head + len, 0);
+        }else{
+            let chunk = Chunk::new(capacity);
+            unsafe { chunk.slice_mut(0..len) }.copy_from_slice(self.received());
+            self.chunk = chunk;
+        }
+        self.head = 0;
+    }
+}
+
+///The total size of a packet given at least its first 8 bytes.
+///
+///Everything the server sends is 32 bytes long, except replies and generic events, which carry
+///the number of additional 4 byte units at offset 4.
+pub(crate) fn packet_length(header: &[u8], order: ByteOrder) -> usize {
+    match header[0] & 0x7F {
+        REPLY | GENERIC_EVENT => {
+            let (extra_units, _) = u32::from_bytes(&header[4..8], order).expect("the header is 8 bytes long");
+            MIN_PACKET_LEN + 4 * extra_units as usize
+        }
+        _ => MIN_PACKET_LEN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(code: u8, sequence: u16) -> Vec<u8> {
+        let mut event = vec![0u8; 32];
+        event[0] = code;
+        event[2..4].copy_from_slice(&sequence.to_le_bytes());
+        event
+    }
+
+    fn reply(sequence: u16, extra_units: u32) -> Vec<u8> {
+        let mut reply = vec![0u8; 32 + 4 * extra_units as usize];
+        reply[0] = REPLY;
+        reply[2..4].copy_from_slice(&sequence.to_le_bytes());
+        reply[4..8].copy_from_slice(&extra_units.to_le_bytes());
+        for (i, byte) in reply[32..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        reply
+    }
+
+    ///Feeds `stream` into a buffer `chunk` bytes at a time and collects every packet on the way.
+    fn split(stream: &[u8], capacity: usize, chunk: usize) -> Vec<Vec<u8>> {
+        let mut buffer = ReadBuffer::with_capacity(capacity, ByteOrder::LsbFirst);
+        let mut packets = Vec::new();
+        for piece in stream.chunks(chunk) {
+            buffer.extend_from_slice(piece);
+            while let Some(packet) = buffer.next_packet() {
+                packets.push(packet.to_vec());
+            }
+        }
+        assert_eq!(buffer.len(), 0);
+        packets
+    }
+
+    #[test]
+    fn frames_events_replies_and_generic_events() {
+        let mut generic = event(GENERIC_EVENT, 3);
+        generic[4] = 2;
+        generic.extend_from_slice(&[9; 8]);
+        assert_eq!(packet_length(&event(12, 1), ByteOrder::LsbFirst), 32);
+        assert_eq!(packet_length(&reply(2, 5), ByteOrder::LsbFirst), 52);
+        assert_eq!(packet_length(&generic, ByteOrder::LsbFirst), 40);
+
+        let expected = vec![event(12, 1), reply(2, 5), generic, event(0x80 | 33, 4)];
+        assert_eq!(split(&expected.concat(), 1024, 1024), expected);
+    }
+
+    #[test]
+    fn reads_reply_lengths_in_the_connection_order() {
+        let mut reply = reply(2, 0);
+        reply[4..8].copy_from_slice(&5u32.to_be_bytes());
+        reply.extend_from_slice(&[0; 20]);
+        assert_eq!(packet_length(&reply, ByteOrder::MsbFirst), 52);
+
+        let mut buffer = ReadBuffer::with_capacity(64, ByteOrder::MsbFirst);
+        buffer.extend_from_slice(&reply[..40]);
+        assert_eq!(buffer.bytes_missing(), 12);
+        buffer.extend_from_slice(&reply[40..]);
+        assert_eq!(buffer.next_packet().as_deref(), Some(&reply[..]));
+    }
+
+    #[test]
+    fn reassembles_fragmented_input() {
+        let expected: Vec<Vec<u8>> = (0..50).map(|i| if i % 3 == 0 { reply(i, i as u32 % 7) } else { event(2, i) }).collect();
+        let stream = expected.concat();
+
+        for chunk in [1, 3, 31, 32, 33, 100] {
+            assert_eq!(split(&stream, 64, chunk), expected, "chunk size {}", chunk);
+        }
+    }
+
+    #[test]
+    fn grows_for_replies_larger_than_the_buffer() {
+        let expected = vec![event(2, 1), reply(2, 1000), event(3, 3)];
+        assert_eq!(split(&expected.concat(), 64, 500), expected);
+    }
+
+    #[test]
+    fn reports_missing_bytes() {
+        let mut buffer = ReadBuffer::with_capacity(64, ByteOrder::LsbFirst);
+        assert_eq!(buffer.bytes_missing(), 32);
+        buffer.extend_from_slice(&reply(1, 2)[..20]);
+        assert_eq!(buffer.bytes_missing(), 12);
+        buffer.extend_from_slice(&reply(1, 2)[20..33]);
+        assert_eq!(buffer.bytes_missing(), 7);
+        assert_eq!(buffer.next_packet(), None);
+    }
+
+    #[test]
+    fn moves_incomplete_packets_to_the_start_of_the_storage() {
+        for keep_first in [false, true] {
+            let mut buffer = ReadBuffer::with_capacity(64, ByteOrder::LsbFirst);
+            buffer.extend_from_slice(&[event(2, 1), event(2, 2)[..20].to_vec()].concat());
+            let first = buffer.next_packet().unwrap();
+            let storage = first.as_ptr();
+            let first = keep_first.then_some(first);
+
+            //The end of the storage is used first.
+            assert_eq!(buffer.free_space().len(), 12);
+            buffer.extend_from_slice(&[event(2, 2)[20..].to_vec(), event(2, 3)[..10].to_vec()].concat());
+            assert_eq!(buffer.capacity(), 64);
+            let second = buffer.next_packet().unwrap();
+            assert_eq!(second, event(2, 2));
+            assert_eq!(buffer.len(), 10);
+
+            //The storage is only reused once no packet refers to it.
+            assert_eq!(std::ptr::eq(second.as_ptr(), storage), !keep_first);
+            if let Some(first) = first {
+                assert_eq!(first, event(2, 1));
+            }
+        }
+    }
+
+    #[test]
+    fn hands_out_packets_without_copying_them() {
+        let expected = vec![event(2, 1), reply(2, 1000), event(3, 3)];
+        let mut buffer = ReadBuffer::with_capacity(64, ByteOrder::LsbFirst);
+        let stream = expected.concat();
+        buffer.extend_from_slice(&stream[..64]);
+        let first = buffer.next_packet().unwrap();
+        let free = buffer.free_space().as_ptr();
+        buffer.extend_from_slice(&stream[64..4064]);
+
+        //Only the header of the reply was moved, its data is read straight into the memory it is
+        //handed out from.
+        let reply = buffer.next_packet().unwrap();
+        assert!(std::ptr::eq(reply[32..].as_ptr(), free));
+        buffer.extend_from_slice(&stream[4064..]);
+        let threads: Vec<_> = [first, reply, buffer.next_packet().unwrap()].into_iter()
+            .map(|packet| std::thread::spawn(move || packet.to_vec()))
+            .collect();
+        assert_eq!(threads.into_iter().map(|thread| thread.join().unwrap()).collect::<Vec<_>>(), expected);
+    }
+}
@@ -0,0 +1,91 @@
+//!Sequence numbers of requests and the cookies that stand for their replies.
+//!
+//!The server counts requests in 16 bits and wraps around, so the sequence numbers it sends back
+//!are widened to a 64 bit counter on arrival. That keeps replies of requests sent long ago apart
+//!from those of recent ones, no matter how many requests are in flight.
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+///How many requests can follow the last one with a reply before one with a reply has to be
+///inserted.
+///
+///That one is then 0xFFFF after the last reply and still has a different 16 bit sequence number,
+///so the packets it and the requests after it cause are never mistaken for earlier ones.
+pub(crate) const MAXIMUM_UNANSWERED: u64 = 0xFFFE;
+
+///Response type of `KeymapNotify`, the only event that has no sequence number.
+const KEYMAP_NOTIFY: u8 = 11;
+
+///A request that has been sent, to be exchanged for its reply with
+///[`XInterface::reply`](crate::xinterface::XInterface::reply).
+///
+///`R` is the type of the reply, `()` for requests the server does not answer. Any number of
+///requests can be sent before waiting for the first reply, and their cookies redeemed in any order.
+#[must_use = "the reply or error of a request is kept until its cookie is redeemed"]
+pub struct Cookie<R> {
+    sequence: u64,
+    has_reply: bool,
+    reply: PhantomData<fn() -> R>
+}
+
+impl<R> Cookie<R> {
+    pub(crate) fn new(sequence: u64, has_reply: bool) -> Self {
+        Self {sequence, has_reply, reply: PhantomData}
+    }
+
+    ///The sequence number of the request, counting from 1 for the first request after the setup.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    ///Whether the server answers the request with a reply or an error.
+    pub fn has_reply(&self) -> bool {
+        self.has_reply
+    }
+}
+
+impl<R> Debug for Cookie<R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cookie").field("sequence", &self.sequence).field("has_reply", &self.has_reply).finish()
+    }
+}
+
+///The full sequence number of a packet, given the 16 bits it carries and the full sequence number
+///of the packet received before it.
+///
+///The server answers requests in order, so a packet never refers to an earlier request than the one
+///before it. As long as no more than [`MAXIMUM_UNANSWERED`] requests go by without a packet, the
+///closest sequence number at or after `last` is the right one.
+pub(crate) fn widen(last: u64, wire: u16) -> u64 {
+    let widened = (last & !0xFFFF) | wire as u64;
+    if widened < last { widened + 0x10000 } else { widened }
+}
+
+///Whether the packet with this response type carries a sequence number at all.
+pub(crate) fn has_sequence(response_type: u8) -> bool {
+    response_type & 0x7F != KEYMAP_NOTIFY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widens_across_wraparound() {
+        assert_eq!(widen(0, 1), 1);
+        assert_eq!(widen(5, 5), 5);
+        assert_eq!(widen(0xFFFE, 0xFFFF), 0xFFFF);
+        assert_eq!(widen(0xFFFF, 0), 0x10000);
+        assert_eq!(widen(0xFFFF, 3), 0x10003);
+        assert_eq!(widen(0x3_FFF0, 0x000F), 0x4_000F);
+        assert_eq!(widen(0x4_000F, 0xFFF0), 0x4_FFF0);
+    }
+
+    #[test]
+    fn keymap_notify_has_no_sequence() {
+        assert!(!has_sequence(KEYMAP_NOTIFY));
+        assert!(!has_sequence(KEYMAP_NOTIFY | 0x80));
+        assert!(has_sequence(0));
+        assert!(has_sequence(1));
+    }
+}
@@ -0,0 +1,189 @@
+//!The DES block cipher, as needed by XDM-AUTHORIZATION-1.
+//!
+//!This is a straightforward table driven implementation of FIPS 46-3 that works one bit at a time.
+//!It is only used to encrypt a single 24 byte authorization block per connection, so it favours
+//!being easy to check against the standard over speed. DES is broken, do not use it for anything new.
+
+///Initial permutation of the 64 bit block.
+const IP: [u8; 64] = [
+    58, 50, 42, 34, 26, 18, 10, 2, 60, 52, 44, 36, 28, 20, 12, 4,
+    62, 54, 46, 38, 30, 22, 14, 6, 64, 56, 48, 40, 32, 24, 16, 8,
+    57, 49, 41, 33, 25, 17, 9, 1, 59, 51, 43, 35, 27, 19, 11, 3,
+    61, 53, 45, 37, 29, 21, 13, 5, 63, 55, 47, 39, 31, 23, 15, 7
+];
+
+///Final permutation, the inverse of [`IP`].
+const FP: [u8; 64] = [
+    40, 8, 48, 16, 56, 24, 64, 32, 39, 7, 47, 15, 55, 23, 63, 31,
+    38, 6, 46, 14, 54, 22, 62, 30, 37, 5, 45, 13, 53, 21, 61, 29,
+    36, 4, 44, 12, 52, 20, 60, 28, 35, 3, 43, 11, 51, 19, 59, 27,
+    34, 2, 42, 10, 50, 18, 58, 26, 33, 1, 41, 9, 49, 17, 57, 25
+];
+
+///Expansion of the 32 bit half block to 48 bits.
+const E: [u8; 48] = [
+    32, 1, 2, 3, 4, 5, 4, 5, 6, 7, 8, 9,
+    8, 9, 10, 11, 12, 13, 12, 13, 14, 15, 16, 17,
+    16, 17, 18, 19, 20, 21, 20, 21, 22, 23, 24, 25,
+    24, 25, 26, 27, 28, 29, 28, 29, 30, 31, 32, 1
+];
+
+///Permutation of the S-box outputs.
+const P: [u8; 32] = [
+    16, 7, 20, 21, 29, 12, 28, 17, 1, 15, 23, 26, 5, 18, 31, 10,
+    2, 8, 24, 14, 32, 27, 3, 9, 19, 13, 30, 6, 22, 11, 4, 25
+];
+
+///Selects the 56 key bits, dropping the parity bits.
+const PC1: [u8; 56] = [
+    57, 49, 41, 33, 25, 17, 9, 1, 58, 50, 42, 34, 26, 18,
+    10, 2, 59, 51, 43, 35, 27, 19, 11, 3, 60, 52, 44, 36,
+    63, 55, 47, 39, 31, 23, 15, 7, 62, 54, 46, 38, 30, 22,
+    14, 6, 61, 53, 45, 37, 29, 21, 13, 5, 28, 20, 12, 4
+];
+
+///Selects the 48 bits of a round key.
+const PC2: [u8; 48] = [
+    14, 17, 11, 24, 1, 5, 3, 28, 15, 6, 21, 10,
+    23, 19, 12, 4, 26, 8, 16, 7, 27, 20, 13, 2,
+    41, 52, 31, 37, 47, 55, 30, 40, 51, 45, 33, 48,
+    44, 49, 39, 56, 34, 53, 46, 42, 50, 36, 29, 32
+];
+
+///How far both key halves are rotated before each round.
+const SHIFTS: [u32; 16] = [1, 1, 2, 2, 2, 2, 2, 2, 1, 2, 2, 2, 2, 2, 2, 1];
+
+const S: [[u8; 64]; 8] = [
+    [14, 4, 13, 1, 2, 15, 11, 8, 3, 10, 6, 12, 5, 9, 0, 7,
+     0, 15, 7, 4, 14, 2, 13, 1, 10, 6, 12, 11, 9, 5, 3, 8,
+     4, 1, 14, 8, 13, 6, 2, 11, 15, 12, 9, 7, 3, 10, 5, 0,
+     15, 12, 8, 2, 4, 9, 1, 7, 5, 11, 3, 14, 10, 0, 6, 13],
+    [15, 1, 8, 14, 6, 11, 3, 4, 9, 7, 2, 13, 12, 0, 5, 10,
+     3, 13, 4, 7, 15, 2, 8, 14, 12, 0, 1, 10, 6, 9, 11, 5,
+     0, 14, 7, 11, 10, 4, 13, 1, 5, 8, 12, 6, 9, 3, 2, 15,
+     13, 8, 10, 1, 3, 15, 4, 2, 11, 6, 7, 12, 0, 5, 14, 9],
+    [10, 0, 9, 14, 6, 3, 15, 5, 1, 13, 12, 7, 11, 4, 2, 8,
+     13, 7, 0, 9, 3, 4, 6, 10, 2, 8, 5, 14, 12, 11, 15, 1,
+     13, 6, 4, 9, 8, 15, 3, 0, 11, 1, 2, 12, 5, 10, 14, 7,
+     1, 10, 13, 0, 6, 9, 8, 7, 4, 15, 14, 3, 11, 5, 2, 12],
+    [7, 13, 14, 3, 0, 6, 9, 10, 1, 2, 8, 5, 11, 12, 4, 15,
+     13, 8, 11, 5, 6, 15, 0, 3, 4, 7, 2, 12, 1, 10, 14, 9,
+     10, 6, 9, 0, 12, 11, 7, 13, 15, 1, 3, 14, 5, 2, 8, 4,
+     3, 15, 0, 6, 10, 1, 13, 8, 9, 4, 5, 11, 12, 7, 2, 14],
+    [2, 12, 4, 1, 7, 10, 11, 6, 8, 5, 3, 15, 13, 0, 14, 9,
+     14, 11, 2, 12, 4, 7, 13, 1, 5, 0, 15, 10, 3, 9, 8, 6,
+     4, 2, 1, 11, 10, 13, 7, 8, 15, 9, 12, 5, 6, 3, 0, 14,
+     11, 8, 12, 7, 1, 14, 2, 13, 6, 15, 0, 9, 10, 4, 5, 3],
+    [12, 1, 10, 15, 9, 2, 6, 8, 0, 13, 3, 4, 14, 7, 5, 11,
+     10, 15, 4, 2, 7, 12, 9, 5, 6, 1, 13, 14, 0, 11, 3, 8,
+     9, 14, 15, 5, 2, 8, 12, 3, 7, 0, 4, 10, 1, 13, 11, 6,
+     4, 3, 2, 12, 9, 5, 15, 10, 11, 14, 1, 7, 6, 0, 8, 13],
+    [4, 11, 2, 14, 15, 0, 8, 13, 3, 12, 9, 7, 5, 10, 6, 1,
+     13, 0, 11, 7, 4, 9, 1, 10, 14, 3, 5, 12, 2, 15, 8, 6,
+     1, 4, 11, 13, 12, 3, 7, 14, 10, 15, 6, 8, 0, 5, 9, 2,
+     6, 11, 13, 8, 1, 4, 10, 7, 9, 5, 0, 15, 14, 2, 3, 12],
+    [13, 2, 8, 4, 6, 15, 11, 1, 10, 9, 3, 14, 5, 0, 12, 7,
+     1, 15, 13, 8, 10, 3, 7, 4, 12, 5, 6, 11, 0, 14, 9, 2,
+     7, 11, 4, 1, 9, 12, 14, 2, 0, 6, 10, 13, 15, 3, 5, 8,
+     2, 1, 14, 7, 4, 10, 8, 13, 15, 12, 9, 0, 3, 5, 6, 11]
+];
+
+///Applies a permutation table from the standard, which numbers the `input_bits` bits of `input`
+///from 1 at the most significant end.
+fn permute(input: u64, input_bits: u32, table: &[u8]) -> u64 {
+    table.iter().fold(0, |output, &position| {
+        (output << 1) | ((input >> (input_bits - position as u32)) & 1)
+    })
+}
+
+///A DES key expanded into its sixteen round keys.
+pub(crate) struct Des {
+    round_keys: [u64; 16]
+}
+
+impl Des {
+    ///Expands an 8 byte key. The lowest bit of each byte is a parity bit and ignored.
+    pub(crate) fn new(key: [u8; 8]) -> Self {
+        let key = permute(u64::from_be_bytes(key), 64, &PC1);
+        let mut left = (key >> 28) as u32;
+        let mut right = (key & 0x0FFF_FFFF) as u32;
+
+        let mut round_keys = [0; 16];
+        for (round_key, shift) in round_keys.iter_mut().zip(SHIFTS) {
+            left = ((left << shift) | (left >> (28 - shift))) & 0x0FFF_FFFF;
+            right = ((right << shift) | (right >> (28 - shift))) & 0x0FFF_FFFF;
+            *round_key = permute(((left as u64) << 28) | right as u64, 56, &PC2);
+        }
+        Self {round_keys}
+    }
+
+    ///Encrypts a single block.
+    pub(crate) fn encrypt(&self, block: [u8; 8]) -> [u8; 8] {
+        self.crypt(block, self.round_keys.iter())
+    }
+
+    ///Decrypts a single block.
+    #[cfg(test)]
+    pub(crate) fn decrypt(&self, block: [u8; 8]) -> [u8; 8] {
+        self.crypt(block, self.round_keys.iter().rev())
+    }
+
+    fn crypt<'a>(&self, block: [u8; 8], round_keys: impl Iterator<Item = &'a u64>) -> [u8; 8] {
+        let block = permute(u64::from_be_bytes(block), 64, &IP);
+        let mut left = (block >> 32) as u32;
+        let mut right = block as u32;
+
+        for &round_key in round_keys {
+            let next = left ^ feistel(right, round_key);
+            left = right;
+            right = next;
+        }
+
+        //The halves are swapped once more after the last round.
+        let block = ((right as u64) << 32) | left as u64;
+        permute(block, 64, &FP).to_be_bytes()
+    }
+}
+
+///The round function: expand, mix in the round key, substitute and permute.
+fn feistel(half: u32, round_key: u64) -> u32 {
+    let mixed = permute(half as u64, 32, &E) ^ round_key;
+    let substituted = S.iter().enumerate().fold(0u64, |output, (i, s_box)| {
+        let six = ((mixed >> (42 - 6 * i)) & 0x3F) as usize;
+        let row = ((six & 0x20) >> 4) | (six & 1);
+        let column = (six >> 1) & 0xF;
+        (output << 4) | s_box[row * 16 + column] as u64
+    });
+    permute(substituted, 32, &P) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(hex: u64) -> [u8; 8] {
+        hex.to_be_bytes()
+    }
+
+    #[test]
+    fn matches_published_vectors() {
+        //The worked example from "The DES Algorithm Illustrated" and two NBS validation vectors.
+        let vectors = [
+            (0x1334_5779_9BBC_DFF1, 0x0123_4567_89AB_CDEF, 0x85E8_1354_0F0A_B405),
+            (0x0101_0101_0101_0101, 0x8000_0000_0000_0000, 0x95F8_A5E5_DD31_D900),
+            (0x7CA1_1045_4A1A_6E57, 0x01A1_D6D0_3977_6742, 0x690F_5B0D_9A26_939B)
+        ];
+
+        for (key, plain, cipher) in vectors {
+            let des = Des::new(block(key));
+            assert_eq!(des.encrypt(block(plain)), block(cipher), "key {:016X}", key);
+            assert_eq!(des.decrypt(block(cipher)), block(plain), "key {:016X}", key);
+        }
+    }
+
+    #[test]
+    fn ignores_parity_bits() {
+        let plain = block(0x0123_4567_89AB_CDEF);
+        assert_eq!(Des::new(block(0x1334_5779_9BBC_DFF1)).encrypt(plain), Des::new(block(0x1235_5678_9ABD_DEF0)).encrypt(plain));
+    }
+}
@@ -0,0 +1,213 @@
+//!Parsing of X display names of the form `[protocol/][host]:display[.screen]`.
+use std::env::var;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Directory in which X servers create their local sockets.
+const X_UNIX_SOCKET_DIR: &str = "/tmp/.X11-unix";
+
+/// TCP port of display number 0, display `n` listens on `X_TCP_PORT + n`.
+pub const X_TCP_PORT: u16 = 6000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplayError{
+    NotSet,
+    MissingColon(String),
+    InvalidDisplayNumber(String),
+    InvalidScreenNumber(String),
+    DecnetUnsupported(String),
+    UnknownProtocol(String),
+    NoTcpPort(u16)
+}
+
+impl Display for DisplayError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisplayError::NotSet => write!(f, "DISPLAY is not set"),
+            DisplayError::MissingColon(name) => write!(f, "display name {:?} has no display number", name),
+            DisplayError::InvalidDisplayNumber(name) => write!(f, "display name {:?} has an invalid display number", name),
+            DisplayError::InvalidScreenNumber(name) => write!(f, "display name {:?} has an invalid screen number", name),
+            DisplayError::DecnetUnsupported(name) => write!(f, "display name {:?} uses DECnet, which is not supported", name),
+            DisplayError::UnknownProtocol(protocol) => write!(f, "unknown protocol {:?}", protocol),
+            DisplayError::NoTcpPort(display) => write!(f, "display {} is past the last TCP port", display)
+        }
+    }
+}
+
+impl Error for DisplayError {}
+
+/// The transport used to reach an X server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol{
+    /// A local stream socket in [`X_UNIX_SOCKET_DIR`].
+    Unix,
+    /// TCP over whatever address family the host name resolves to.
+    Tcp,
+    /// TCP restricted to IPv4.
+    Inet,
+    /// TCP restricted to IPv6.
+    Inet6
+}
+
+impl FromStr for Protocol {
+    type Err = DisplayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unix" | "local" => Ok(Protocol::Unix),
+            "tcp" => Ok(Protocol::Tcp),
+            "inet" => Ok(Protocol::Inet),
+            "inet6" => Ok(Protocol::Inet6),
+            _ => Err(DisplayError::UnknownProtocol(s.to_string()))
+        }
+    }
+}
+
+/// Where to connect to, as chosen from a [`DisplayName`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectAddress{
+    /// Path of a local socket.
+    Unix(PathBuf),
+    /// Host name or address and port of a TCP listener, with the protocol used to pick the address family.
+    Tcp{host: String, port: u16, protocol: Protocol}
+}
+
+/// A parsed X display name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayName{
+    /// The protocol given before a `/`, if any.
+    pub protocol: Option<Protocol>,
+    /// The host part, empty for the local machine.
+    pub host: String,
+    /// The display number.
+    pub display: u16,
+    /// The screen number, `0` when no `.screen` suffix was given.
+    pub screen: u16
+}
+
+impl DisplayName {
+    /// Parses a display name in the `[protocol/][host]:display[.screen]` syntax.
+    ///
+    /// IPv6 addresses may be given either bare (`::1:0`) or in brackets (`[::1]:0`). A host that
+    /// ends in a colon (`host::0`) denotes DECnet, which is not supported.
+    pub fn parse(name: &str) -> Result<Self, DisplayError> {
+        let (protocol, rest) = match name.rfind('/') {
+            Some(slash) => (Some(name[..slash].parse::<Protocol>()?), &name[slash + 1..]),
+            None => (None, name)
+        };
+
+        let colon = rest.rfind(':').ok_or_else(|| DisplayError::MissingColon(name.to_string()))?;
+        let mut host = &rest[..colon];
+        let display_and_screen = &rest[colon + 1..];
+
+        if host.starts_with('[') && host.ends_with(']') {
+            host = &host[1..host.len() - 1];
+        }else if host.ends_with(':') && !host[..host.len() - 1].contains(':') {
+            return Err(DisplayError::DecnetUnsupported(name.to_string()));
+        }
+
+        let (display, screen) = match display_and_screen.split_once('.') {
+            Some((display, screen)) => (display, Some(screen)),
+            None => (display_and_screen, None)
+        };
+
+        let display = parse_number(display).ok_or_else(|| DisplayError::InvalidDisplayNumber(name.to_string()))?;
+        let screen = match screen {
+            Some(screen) => parse_number(screen).ok_or_else(|| DisplayError::InvalidScreenNumber(name.to_string()))?,
+            None => 0
+        };
+
+        Ok(Self {protocol, host: host.to_string(), display, screen})
+    }
+
+    /// Parses the display name stored in the `DISPLAY` environment variable.
+    pub fn from_env() -> Result<Self, DisplayError> {
+        match var("DISPLAY") {
+            Ok(name) if !name.is_empty() => Self::parse(&name),
+            _ => Err(DisplayError::NotSet)
+        }
+    }
+
+    /// Chooses the transport and address for this display the same way libxcb does.
+    ///
+    /// An empty host, the host `unix` or the `unix` protocol select the local socket, everything
+    /// else is a TCP connection on port `6000 + display`. Displays above 59535 have no such port
+    /// and can only be reached through the local socket.
+    pub fn address(&self) -> Result<ConnectAddress, DisplayError> {
+        let local = self.host.is_empty() || self.host == "unix";
+        Ok(match self.protocol {
+            Some(Protocol::Unix) => self.unix_address(),
+            None if local => self.unix_address(),
+            protocol => {
+                let host = if local { "localhost".to_string() } else { self.host.clone() };
+                let port = X_TCP_PORT.checked_add(self.display).ok_or(DisplayError::NoTcpPort(self.display))?;
+                ConnectAddress::Tcp {host, port, protocol: protocol.unwrap_or(Protocol::Tcp)}
+            }
+        })
+    }
+
+    fn unix_address(&self) -> ConnectAddress {
+        ConnectAddress::Unix(PathBuf::from(format!("{}/X{}", X_UNIX_SOCKET_DIR, self.display)))
+    }
+}
+
+impl FromStr for DisplayName {
+    type Err = DisplayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Parses a non-empty string of ASCII digits, rejecting signs and whitespace that `u16::from_str` would allow.
+fn parse_number(s: &str) -> Option<u16> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str) -> DisplayName {
+        DisplayName::parse(name).unwrap()
+    }
+
+    #[test]
+    fn parses_local_displays() {
+        assert_eq!(parse(":0"), DisplayName {protocol: None, host: String::new(), display: 0, screen: 0});
+        assert_eq!(parse(":99.2"), DisplayName {protocol: None, host: String::new(), display: 99, screen: 2});
+        assert_eq!(parse("unix:1").address().unwrap(), ConnectAddress::Unix("/tmp/.X11-unix/X1".into()));
+        assert_eq!(parse("unix/:3").address().unwrap(), ConnectAddress::Unix("/tmp/.X11-unix/X3".into()));
+    }
+
+    #[test]
+    fn parses_remote_displays() {
+        assert_eq!(parse("localhost:10.0").address().unwrap(), ConnectAddress::Tcp {host: "localhost".into(), port: 6010, protocol: Protocol::Tcp});
+        assert_eq!(parse("tcp/:1").address().unwrap(), ConnectAddress::Tcp {host: "localhost".into(), port: 6001, protocol: Protocol::Tcp});
+        assert_eq!(parse("inet6/[::1]:2.1"), DisplayName {protocol: Some(Protocol::Inet6), host: "::1".into(), display: 2, screen: 1});
+        assert_eq!(parse("fe80::1:0").host, "fe80::1");
+    }
+
+    #[test]
+    fn rejects_malformed_names() {
+        assert_eq!(DisplayName::parse("localhost"), Err(DisplayError::MissingColon("localhost".into())));
+        assert_eq!(DisplayName::parse(":"), Err(DisplayError::InvalidDisplayNumber(":".into())));
+        assert_eq!(DisplayName::parse(":0."), Err(DisplayError::InvalidScreenNumber(":0.".into())));
+        assert_eq!(DisplayName::parse(":+1"), Err(DisplayError::InvalidDisplayNumber(":+1".into())));
+        assert_eq!(DisplayName::parse("decnet::0"), Err(DisplayError::DecnetUnsupported("decnet::0".into())));
+        assert_eq!(DisplayName::parse("foo/:0"), Err(DisplayError::UnknownProtocol("foo".into())));
+    }
+
+    #[test]
+    fn displays_past_the_last_tcp_port_only_have_a_local_socket() {
+        assert_eq!(parse(":60000").address().unwrap(), ConnectAddress::Unix("/tmp/.X11-unix/X60000".into()));
+        assert_eq!(parse("localhost:59535").address().unwrap(), ConnectAddress::Tcp {host: "localhost".into(), port: 65535, protocol: Protocol::Tcp});
+        assert_eq!(parse("localhost:60000").address(), Err(DisplayError::NoTcpPort(60000)));
+        assert_eq!(parse("tcp/:60000").address(), Err(DisplayError::NoTcpPort(60000)));
+    }
+}
@@ -0,0 +1,72 @@
+//!Events and errors as they arrive from the X server.
+pub use crate::buffer::Packet;
+use crate::serializable::{ByteOrder, Serializable};
+
+/// Response type of protocol errors.
+pub const ERROR: u8 = 0;
+/// Response type of replies.
+pub const REPLY: u8 = 1;
+/// Response type of `GenericEvent`, the only event that can be longer than 32 bytes.
+pub const GENERIC_EVENT: u8 = 35;
+
+/// An event or error that has not been decoded yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawEvent{
+    bytes: Packet,
+    order: ByteOrder,
+    sequence: u64
+}
+
+impl RawEvent {
+    pub(crate) fn new(bytes: Packet, order: ByteOrder) -> Self {
+        let sequence = u16::from_bytes(&bytes[2..4], order).expect("events are at least 32 bytes long").0 as u64;
+        Self {bytes, order, sequence}
+    }
+
+    ///Replaces the 16 bits of the sequence number sent on the wire with the full one.
+    pub(crate) fn with_sequence(self, sequence: u64) -> Self {
+        Self {sequence, ..self}
+    }
+
+    /// The low 16 bits of the sequence number, as sent by the server.
+    pub(crate) fn wire_sequence(&self) -> u16 {
+        self.sequence as u16
+    }
+
+    /// The response type with the `SendEvent` flag masked off.
+    pub fn response_type(&self) -> u8 {
+        self.bytes[0] & 0x7F
+    }
+
+    /// Whether this was generated by a `SendEvent` request instead of by the server.
+    pub fn sent_event(&self) -> bool {
+        self.bytes[0] & 0x80 != 0
+    }
+
+    /// Whether this is a protocol error rather than an event.
+    pub fn is_error(&self) -> bool {
+        self.response_type() == ERROR
+    }
+
+    /// The sequence number of the last request the server processed, widened to 64 bits.
+    ///
+    /// `KeymapNotify` carries no sequence number and gets the one of the packet before it.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// The byte order of the connection, which applies to every field longer than a byte.
+    pub fn byte_order(&self) -> ByteOrder {
+        self.order
+    }
+
+    /// The complete wire representation, at least 32 bytes.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Takes the wire representation out of the event, without copying it.
+    pub fn into_bytes(self) -> Packet {
+        self.bytes
+    }
+}
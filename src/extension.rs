@@ -0,0 +1,63 @@
+//!Looking up protocol extensions with `QueryExtension`.
+use crate::serializable::{ByteOrder, Serializable};
+use crate::xinterface::{padding, XInterfaceError};
+
+///Opcode of the core `QueryExtension` request.
+const QUERY_EXTENSION: u8 = 98;
+
+///Where the requests, events and errors of an extension are found on this server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtensionInfo {
+    ///The major opcode of the extension's requests, which then carry their minor opcode in the second byte.
+    pub major_opcode: u8,
+    ///The first event code of the extension, `0` if it has no events.
+    pub first_event: u8,
+    ///The first error code of the extension, `0` if it has no errors.
+    pub first_error: u8
+}
+
+///Encodes a `QueryExtension` request for the extension called `name` in `order`.
+pub(crate) fn query_extension_request(name: &[u8], order: ByteOrder) -> Vec<u8> {
+    let padded = name.len() + padding(name.len()).len();
+    let mut request = vec![QUERY_EXTENSION, 0];
+    request.extend_from_slice(&((2 + padded / 4) as u16).bytes(order));
+    request.extend_from_slice(&(name.len() as u16).bytes(order));
+    request.extend_from_slice(&[0, 0]);
+    request.extend_from_slice(name);
+    request.extend_from_slice(padding(name.len()));
+    request
+}
+
+///Decodes a `QueryExtension` reply, `None` when the server does not have the extension.
+pub(crate) fn parse_query_extension_reply(reply: &[u8]) -> Result<Option<ExtensionInfo>, XInterfaceError> {
+    if reply.len() < 12 {
+        return Err(XInterfaceError::ProtocolViolation("QueryExtension reply too short".to_string()));
+    }
+    if reply[8] == 0 {
+        return Ok(None);
+    }
+    Ok(Some(ExtensionInfo {major_opcode: reply[9], first_event: reply[10], first_error: reply[11]}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_query_extension() {
+        let request = query_extension_request(b"XC-MISC", ByteOrder::LsbFirst);
+        assert_eq!(request, [98, 0, 4, 0, 7, 0, 0, 0, b'X', b'C', b'-', b'M', b'I', b'S', b'C', 0]);
+        let request = query_extension_request(b"XC-MISC", ByteOrder::MsbFirst);
+        assert_eq!(&request[..8], &[98, 0, 0, 4, 0, 7, 0, 0]);
+    }
+
+    #[test]
+    fn decodes_query_extension_replies() {
+        let mut reply = [0u8; 32];
+        reply[0] = 1;
+        assert_eq!(parse_query_extension_reply(&reply).unwrap(), None);
+        reply[8..12].copy_from_slice(&[1, 130, 0, 0]);
+        assert_eq!(parse_query_extension_reply(&reply).unwrap(), Some(ExtensionInfo {major_opcode: 130, first_event: 0, first_error: 0}));
+        assert!(parse_query_extension_reply(&reply[..8]).is_err());
+    }
+}
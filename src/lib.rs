@@ -1,5 +1,22 @@
+//The derive macro refers to the crate by name, also from inside it.
+extern crate self as xeagle;
+
 pub mod application;
+pub mod auth;
+pub mod cookie;
+pub mod display;
+pub mod event;
+pub mod extension;
+pub mod protocol;
+pub mod transport;
 pub mod window;
+pub mod xauth;
 pub mod xinterface;
-mod sock;
-mod serializable;
\ No newline at end of file
+pub mod sock;
+mod buffer;
+mod des;
+mod request;
+mod resource;
+pub mod serializable;
+pub mod setup;
+pub mod view;
\ No newline at end of file
@@ -0,0 +1,226 @@
+//!Requests, replies, events and errors of the core protocol and its extensions.
+//!
+//!The modules are generated at build time from the XML protocol descriptions of xcb-proto in
+//!`xcb-proto/`, by `xeagle-codegen`. Each request is a struct implementing [`Request`], everything
+//!the server sends is a struct implementing [`Serializable`], which decodes it in the byte order
+//!of the connection.
+use std::os::fd::BorrowedFd;
+use crate::serializable::{list_from_bytes, ByteOrder, ParseError, Serializable};
+
+///A request of the core protocol or of an extension.
+pub trait Request {
+    ///The name of the extension the request belongs to, `None` for the core protocol.
+    const EXTENSION: Option<&'static [u8]>;
+    ///The opcode of a core request, or the minor opcode of an extension request.
+    const OPCODE: u8;
+    ///Whether the server answers the request with a reply.
+    const HAS_REPLY: bool;
+    ///The number of descriptors the server passes along with the reply.
+    const REPLY_FDS: usize = 0;
+    ///The reply, `()` for requests without one.
+    type Reply: Serializable;
+
+    ///Encodes the request, with `major_opcode` in the first byte.
+    ///
+    ///That is [`Request::OPCODE`] for core requests and the opcode the server assigned to the
+    ///extension otherwise. The length in the header is left at zero, as
+    ///[`XInterface::send_request`](crate::xinterface::XInterface::send_request) fills it in.
+    fn encode(&self, major_opcode: u8, order: ByteOrder) -> Vec<u8>;
+
+    ///The descriptors passed to the server along with the request, in the order it expects them.
+    fn fds(&self) -> Vec<BorrowedFd<'_>> {
+        Vec::new()
+    }
+}
+
+///The bytes from `offset` on.
+fn rest(bytes: &[u8], offset: usize) -> Result<&[u8], ParseError> {
+    bytes.get(offset..).ok_or(ParseError::InsufficientData {needed: offset, available: bytes.len()})
+}
+
+///Decodes a value at `offset` and moves past it.
+fn read<T: Serializable>(bytes: &[u8], offset: &mut usize, order: ByteOrder) -> Result<T, ParseError> {
+    let (value, consumed) = T::from_bytes(rest(bytes, *offset)?, order)?;
+    *offset += consumed;
+    Ok(value)
+}
+
+///Takes `len` bytes at `offset` and moves past them.
+fn read_bytes<'a>(bytes: &'a [u8], offset: &mut usize, len: usize) -> Result<&'a [u8], ParseError> {
+    let read = rest(bytes, *offset)?.get(..len).ok_or(ParseError::InsufficientData {needed: *offset + len, available: bytes.len()})?;
+    *offset += len;
+    Ok(read)
+}
+
+///Decodes a list of `count` values at `offset` and moves past it.
+fn read_list<T: Serializable>(bytes: &[u8], offset: &mut usize, count: usize, order: ByteOrder) -> Result<Vec<T>, ParseError> {
+    let (list, consumed) = list_from_bytes(rest(bytes, *offset)?, count, order)?;
+    *offset += consumed;
+    Ok(list)
+}
+
+///Moves past `len` bytes of padding.
+fn skip(bytes: &[u8], offset: &mut usize, len: usize) -> Result<(), ParseError> {
+    read_bytes(bytes, offset, len).map(|_| ())
+}
+
+///Moves past the padding up to the next multiple of `align`.
+fn align(bytes: &[u8], offset: &mut usize, align: usize) -> Result<(), ParseError> {
+    skip(bytes, offset, offset.next_multiple_of(align) - *offset)
+}
+
+///The size of a reply, event or error that takes up `size` bytes, such as 32 for events, of
+///which `offset` have been decoded.
+fn end(bytes: &[u8], offset: usize, size: usize) -> Result<usize, ParseError> {
+    if bytes.len() < size {
+        return Err(ParseError::InsufficientData {needed: size, available: bytes.len()});
+    }
+    Ok(offset.max(size))
+}
+
+include!(concat!(env!("OUT_DIR"), "/protocol.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::query_extension_request;
+    use xproto::*;
+
+    #[test]
+    fn encodes_core_requests() {
+        for order in [ByteOrder::LsbFirst, ByteOrder::MsbFirst] {
+            let request = QueryExtensionRequest {name: b"BIG-REQUESTS".to_vec()};
+            let mut encoded = request.encode(QueryExtensionRequest::OPCODE, order);
+            //The length is filled in when the request is sent.
+            encoded[2..4].copy_from_slice(&5u16.bytes(order));
+            assert_eq!(encoded, query_extension_request(b"BIG-REQUESTS", order));
+        }
+    }
+
+    #[test]
+    fn encodes_switches_from_the_values_that_are_set() {
+        let request = CreateWindowRequest {
+            depth: 24,
+            wid: 0x200001,
+            parent: 0x100,
+            width: 640,
+            height: 480,
+            class: WindowClass::INPUT_OUTPUT,
+            value_list: CreateWindowAux {
+                background_pixel: Some(0xFFFFFF),
+                event_mask: Some(EventMask::EXPOSURE | EventMask::KEY_PRESS),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(request.value_list.mask(), (Cw::BACK_PIXEL | Cw::EVENT_MASK).0);
+        assert_eq!(request.encode(CreateWindowRequest::OPCODE, ByteOrder::LsbFirst), [
+            1, 24, 0, 0, 0x01, 0x00, 0x20, 0x00, 0x00, 0x01, 0x00, 0x00,
+            0, 0, 0, 0, 0x80, 0x02, 0xE0, 0x01, 0, 0, 1, 0, 0, 0, 0, 0,
+            0x02, 0x08, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0x00, 0x01, 0x80, 0x00, 0x00
+        ]);
+    }
+
+    #[test]
+    fn encodes_extension_requests_with_their_minor_opcode() {
+        assert_eq!(shape::QueryVersionRequest::EXTENSION, Some(&b"SHAPE"[..]));
+        assert_eq!(shape::QueryVersionRequest.encode(130, ByteOrder::MsbFirst), [130, 0, 0, 0]);
+        let request = xc_misc::GetXIDListRequest {count: 2};
+        assert_eq!(request.encode(131, ByteOrder::LsbFirst), [131, 2, 0, 0, 2, 0, 0, 0]);
+    }
+
+    #[test]
+    fn decodes_events_in_both_orders() {
+        let event = KeyPressEvent {
+            response_type: KEY_PRESS_EVENT,
+            detail: 38,
+            sequence: 7,
+            time: 0x01020304,
+            root: 0x100,
+            event: 0x200001,
+            root_x: 10,
+            event_y: -1,
+            state: KeyButMask::SHIFT | KeyButMask::BUTTON1,
+            same_screen: true,
+            ..Default::default()
+        };
+        let lsb = [
+            2, 38, 7, 0, 4, 3, 2, 1, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0x20, 0x00,
+            0, 0, 0, 0, 10, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0x01, 0x01, 1, 0
+        ];
+        let msb = [
+            2, 38, 0, 7, 1, 2, 3, 4, 0x00, 0x00, 0x01, 0x00, 0x00, 0x20, 0x00, 0x01,
+            0, 0, 0, 0, 0, 10, 0, 0, 0, 0, 0xFF, 0xFF, 0x01, 0x01, 1, 0
+        ];
+        assert_eq!(KeyPressEvent::from_bytes(&lsb, ByteOrder::LsbFirst), Ok((event.clone(), 32)));
+        assert_eq!(KeyPressEvent::from_bytes(&msb, ByteOrder::MsbFirst), Ok((event.clone(), 32)));
+        assert_eq!(event.bytes(ByteOrder::MsbFirst), msb);
+        assert!(event.state.contains(KeyButMask::SHIFT));
+        assert!(!event.state.contains(KeyButMask::CONTROL));
+        assert!(KeyPressEvent::from_bytes(&lsb[..31], ByteOrder::LsbFirst).is_err());
+    }
+
+    #[test]
+    fn decodes_replies_with_lists() {
+        let mut wire = vec![1, 0, 3, 0, 3, 0, 0, 0, 2, 0];
+        wire.resize(32, 0);
+        wire.extend([5, b'f', b'i', b'x', b'e', b'd', 4, b'c', b'u', b'r', b's', 0]);
+        let reply = ListFontsReply {
+            sequence: 3,
+            length: 3,
+            names: vec![Str {name: b"fixed".to_vec()}, Str {name: b"curs".to_vec()}]
+        };
+        assert_eq!(ListFontsReply::from_bytes(&wire, ByteOrder::LsbFirst), Ok((reply.clone(), 44)));
+        assert_eq!(reply.bytes(ByteOrder::LsbFirst), wire);
+    }
+
+    #[test]
+    fn copies_of_events_and_errors_refer_across_modules() {
+        let error: shm::BadSegError = ValueError {error_code: shm::BAD_SEG_ERROR, bad_value: 7, ..Default::default()};
+        assert_eq!(ValueError::from_bytes(&error.bytes(ByteOrder::LsbFirst), ByteOrder::LsbFirst), Ok((error, 32)));
+    }
+
+    #[test]
+    fn decodes_generic_events_after_their_header() {
+        let event = xinput::HierarchyEvent {
+            response_type: GE_GENERIC_EVENT,
+            extension: 131,
+            sequence: 9,
+            length: 3,
+            event_type: xinput::HIERARCHY_EVENT,
+            deviceid: 2,
+            flags: xinput::HierarchyMask::DEVICE_ENABLED,
+            infos: vec![xinput::HierarchyInfo {deviceid: 4, attachment: 2, enabled: true, ..Default::default()}],
+            ..Default::default()
+        };
+        let wire = event.bytes(ByteOrder::LsbFirst);
+        assert_eq!(wire.len(), 44);
+        assert_eq!(wire[..10], [35, 131, 9, 0, 3, 0, 0, 0, 11, 0]);
+        assert_eq!(wire[32..36], [4, 0, 2, 0]);
+        assert_eq!(xinput::HierarchyEvent::from_bytes(&wire, ByteOrder::LsbFirst), Ok((event, 44)));
+        assert!(xinput::HierarchyEvent::from_bytes(&wire[..40], ByteOrder::LsbFirst).is_err());
+    }
+
+    #[test]
+    fn requests_hand_out_the_descriptors_they_pass() {
+        use std::os::fd::{AsRawFd, OwnedFd};
+        use std::os::unix::net::UnixStream;
+
+        let request = shm::AttachFdRequest {shmseg: 0x200001, shm_fd: OwnedFd::from(UnixStream::pair().unwrap().0), read_only: true};
+        //The descriptor is not part of the bytes.
+        assert_eq!(request.encode(130, ByteOrder::LsbFirst), [130, 6, 0, 0, 0x01, 0x00, 0x20, 0x00, 1, 0, 0, 0]);
+        let fds = request.fds();
+        assert_eq!(fds.len(), 1);
+        assert_eq!(fds[0].as_raw_fd(), request.shm_fd.as_raw_fd());
+        assert_eq!(dri3::OpenRequest::REPLY_FDS, 1);
+        assert_eq!(GetInputFocusRequest::REPLY_FDS, 0);
+    }
+
+    #[test]
+    fn reads_unions_as_each_member() {
+        let data = ClientMessageData::from_data32(&[1, 2, 3, 4, 0x05060708], ByteOrder::MsbFirst);
+        assert_eq!(data.data32(ByteOrder::MsbFirst), [1, 2, 3, 4, 0x05060708]);
+        assert_eq!(data.data8(ByteOrder::MsbFirst)[..4], [0, 0, 0, 1]);
+        assert_eq!(data.data16(ByteOrder::LsbFirst)[1], 0x0100);
+    }
+}
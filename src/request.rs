@@ -0,0 +1,123 @@
+//!The length field of outgoing requests, including the extended encoding of BIG-REQUESTS.
+use std::io::IoSlice;
+use crate::serializable::{ByteOrder, Serializable};
+use crate::xinterface::XInterfaceError;
+
+///Name of the extension that allows requests longer than 65535 units.
+pub(crate) const BIG_REQUESTS: &[u8] = b"BIG-REQUESTS";
+
+///Minor opcode of `BigReqEnable`.
+const BIG_REQ_ENABLE: u8 = 0;
+
+///Encodes a `BigReqEnable` request for the extension with major opcode `major_opcode`.
+pub(crate) fn big_req_enable_request(major_opcode: u8) -> [u8; 4] {
+    [major_opcode, BIG_REQ_ENABLE, 1, 0]
+}
+
+///Decodes a `BigReqEnable` reply into the new maximum request length in 4 byte units.
+pub(crate) fn parse_big_req_enable_reply(reply: &[u8], order: ByteOrder) -> Option<u32> {
+    u32::from_bytes(reply.get(8..)?, order).ok().map(|(maximum, _)| maximum)
+}
+
+///Fills in the length of `request` and splits off its header.
+///
+///The length field in bytes 2 and 3 is computed from the size of the slices, so callers can leave
+///it at zero. Requests of more than 65535 units use the BIG-REQUESTS encoding, where the field is
+///zero and followed by a 32 bit length that counts itself, which needs a `maximum` above 65535.
+///Both lengths are written in `order`. Returns the new header and the rest of the request after
+///the original 4 byte header.
+pub(crate) fn frame<'a>(request: &'a [IoSlice<'_>], maximum: u32, order: ByteOrder) -> Result<(Vec<u8>, Vec<IoSlice<'a>>), XInterfaceError> {
+    let length: usize = request.iter().map(|slice| slice.len()).sum();
+    if length < 4 || !length.is_multiple_of(4) {
+        return Err(XInterfaceError::InvalidRequest(format!("request of {} bytes is not a positive multiple of 4", length)));
+    }
+
+    let mut header = Vec::with_capacity(8);
+    let mut rest = Vec::with_capacity(request.len());
+    for slice in request {
+        let split = (4 - header.len()).min(slice.len());
+        header.extend_from_slice(&slice[..split]);
+        if split < slice.len() {
+            rest.push(IoSlice::new(&slice[split..]));
+        }
+    }
+
+    let units = length / 4;
+    let maximum = maximum as usize;
+    if units <= maximum.min(0xFFFF) {
+        header[2..4].copy_from_slice(&(units as u16).bytes(order));
+    }else if maximum > 0xFFFF && units < maximum {
+        header[2..4].copy_from_slice(&[0, 0]);
+        header.extend_from_slice(&(units as u32 + 1).bytes(order));
+    }else {
+        return Err(XInterfaceError::RequestTooLong {length, maximum: maximum * 4});
+    }
+    Ok((header, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framed(request: &[&[u8]], maximum: u32) -> Result<Vec<u8>, XInterfaceError> {
+        framed_in(request, maximum, ByteOrder::LsbFirst)
+    }
+
+    fn framed_in(request: &[&[u8]], maximum: u32, order: ByteOrder) -> Result<Vec<u8>, XInterfaceError> {
+        let slices: Vec<IoSlice<'_>> = request.iter().map(|part| IoSlice::new(part)).collect();
+        let (header, rest) = frame(&slices, maximum, order)?;
+        Ok([header, rest.iter().flat_map(|slice| slice.to_vec()).collect()].concat())
+    }
+
+    #[test]
+    fn fills_in_the_length() {
+        assert_eq!(framed(&[&[8, 1, 0, 0], &[1, 2, 3, 4]], 65535).unwrap(), [8, 1, 2, 0, 1, 2, 3, 4]);
+        //The header may be split over several slices.
+        assert_eq!(framed(&[&[8], &[1, 0], &[0, 9, 9, 9, 9]], 65535).unwrap(), [8, 1, 2, 0, 9, 9, 9, 9]);
+    }
+
+    #[test]
+    fn uses_the_big_requests_encoding() {
+        let payload = vec![7u8; 65536 * 4];
+        let request = framed(&[&[72, 2, 0, 0], &payload[4..]], 4_194_303).unwrap();
+        assert_eq!(&request[..8], &[72, 2, 0, 0, 0x01, 0x00, 0x01, 0x00]);
+        assert_eq!(request.len(), payload.len() + 4);
+        assert!(request[8..].iter().all(|&b| b == 7));
+    }
+
+    #[test]
+    fn writes_lengths_in_the_connection_order() {
+        assert_eq!(framed_in(&[&[8, 1, 0, 0, 1, 2, 3, 4]], 65535, ByteOrder::MsbFirst).unwrap(), [8, 1, 0, 2, 1, 2, 3, 4]);
+        let payload = vec![0u8; 65536 * 4];
+        assert_eq!(&framed_in(&[&payload], 4_194_303, ByteOrder::MsbFirst).unwrap()[..8], &[0, 0, 0, 0, 0x00, 0x01, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn rejects_requests_over_the_maximum() {
+        let payload = vec![0u8; 65536 * 4];
+        match framed(&[&payload], 65535) {
+            Err(XInterfaceError::RequestTooLong {length, maximum}) => assert_eq!((length, maximum), (262_144, 262_140)),
+            other => panic!("expected RequestTooLong, got {:?}", other)
+        }
+        assert!(matches!(framed(&[&[0; 16]], 3), Err(XInterfaceError::RequestTooLong {length: 16, maximum: 12})));
+        //The extended length counts itself, so a request of exactly the maximum does not fit.
+        assert!(framed(&[&payload], 65536).is_err());
+        assert!(framed(&[&payload], 65537).is_ok());
+    }
+
+    #[test]
+    fn rejects_unpadded_requests() {
+        assert!(matches!(framed(&[&[1, 0, 0]], 65535), Err(XInterfaceError::InvalidRequest(_))));
+        assert!(matches!(framed(&[&[1, 0, 0, 0, 5]], 65535), Err(XInterfaceError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn encodes_big_req_enable() {
+        assert_eq!(big_req_enable_request(133), [133, 0, 1, 0]);
+        let mut reply = [0u8; 32];
+        reply[8..12].copy_from_slice(&4_194_303u32.to_le_bytes());
+        assert_eq!(parse_big_req_enable_reply(&reply, ByteOrder::LsbFirst), Some(4_194_303));
+        reply[8..12].copy_from_slice(&4_194_303u32.to_be_bytes());
+        assert_eq!(parse_big_req_enable_reply(&reply, ByteOrder::MsbFirst), Some(4_194_303));
+    }
+}
@@ -0,0 +1,149 @@
+//!Allocation of the IDs of windows, pixmaps, graphics contexts and other resources.
+//!
+//!The client picks the IDs of the resources it creates itself, from the range the server assigned
+//!to it in the setup. Once that range is used up the XC-MISC extension can tell which IDs have
+//!been freed again in the meantime.
+use crate::serializable::{ByteOrder, Serializable};
+
+///Name of the extension that hands out fresh ID ranges.
+pub(crate) const XC_MISC: &[u8] = b"XC-MISC";
+
+///Minor opcode of XC-MISC `GetXIDRange`.
+const GET_XID_RANGE: u8 = 1;
+
+///Hands out resource IDs from the range the server assigned to this client.
+///
+///Freed IDs are handed out again before new ones, and when the range runs out, a new one can be
+///added with [`IdAllocator::add_range`]. This follows `xcb_generate_id` in libxcb.
+#[derive(Debug)]
+pub(crate) struct IdAllocator {
+    base: u32,
+    ///The distance between two IDs, the lowest bit set in the mask.
+    inc: u32,
+    ///The offset of the last ID handed out from the current range.
+    last: u32,
+    ///The offset of the last ID in the current range.
+    max: u32,
+    freed: Vec<u32>
+}
+
+impl IdAllocator {
+    ///Creates an allocator for the setup's `resource_id_base` and `resource_id_mask`.
+    pub(crate) fn new(base: u32, mask: u32) -> Self {
+        let inc = mask & mask.wrapping_neg();
+        //`last` starts one step before the start of the range, so the first ID has offset 0.
+        Self {base, inc, last: 0u32.wrapping_sub(inc), max: mask, freed: Vec::new()}
+    }
+
+    ///The next free ID, or `None` if the range is used up.
+    pub(crate) fn generate(&mut self) -> Option<u32> {
+        if let Some(id) = self.freed.pop() {
+            return Some(id);
+        }
+        let next = self.last.wrapping_add(self.inc);
+        if self.inc == 0 || next > self.max {
+            return None;
+        }
+        self.last = next;
+        Some(next | self.base)
+    }
+
+    ///Makes `id` available again, after the resource it named has been destroyed.
+    pub(crate) fn free(&mut self, id: u32) {
+        self.freed.push(id);
+    }
+
+    ///Continues with the `count` IDs starting at `start_id`, as returned by `GetXIDRange`.
+    ///
+    ///Returns `false` for the empty range the server sends when it has no IDs left, and for a
+    ///range that does not fit into 32 bits.
+    pub(crate) fn add_range(&mut self, start_id: u32, count: u32) -> bool {
+        if count == 0 || (start_id == 0 && count == 1) {
+            return false;
+        }
+        let offset = start_id & !self.base;
+        let Some(max) = (count - 1).checked_mul(self.inc).and_then(|span| offset.checked_add(span)) else {
+            return false;
+        };
+        self.last = offset.wrapping_sub(self.inc);
+        self.max = max;
+        true
+    }
+}
+
+///Encodes a `GetXIDRange` request for the extension with major opcode `major_opcode`.
+pub(crate) fn get_xid_range_request(major_opcode: u8) -> [u8; 4] {
+    [major_opcode, GET_XID_RANGE, 1, 0]
+}
+
+///The body of a `GetXIDRange` reply, after the reply header.
+#[derive(Serializable)]
+struct GetXidRangeReply {
+    #[serializable(pad = 8)]
+    start_id: u32,
+    count: u32
+}
+
+///Decodes a `GetXIDRange` reply into the first ID and the number of IDs.
+pub(crate) fn parse_get_xid_range_reply(reply: &[u8], order: ByteOrder) -> Option<(u32, u32)> {
+    let (reply, _) = GetXidRangeReply::from_bytes(reply, order).ok()?;
+    Some((reply.start_id, reply.count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hands_out_every_id_of_the_mask() {
+        let mut ids = IdAllocator::new(0x0400_0000, 0x0000_0070);
+        let generated: Vec<u32> = std::iter::from_fn(|| ids.generate()).collect();
+        assert_eq!(generated, (0..8).map(|i| 0x0400_0000 | (i << 4)).collect::<Vec<_>>());
+        assert_eq!(ids.generate(), None);
+    }
+
+    #[test]
+    fn reuses_freed_ids() {
+        let mut ids = IdAllocator::new(0x0020_0000, 0x3);
+        let first = ids.generate().unwrap();
+        let second = ids.generate().unwrap();
+        ids.free(first);
+        assert_eq!(ids.generate(), Some(first));
+        assert_eq!(ids.generate(), Some(second + 1));
+    }
+
+    #[test]
+    fn rejects_ranges_past_the_end_of_the_ids() {
+        let mut ids = IdAllocator::new(0x0020_0000, 0x10);
+        assert!(!ids.add_range(0x0020_0010, u32::MAX));
+        assert!(!ids.add_range(0x0000_FFF0, 0x1000_0000));
+        assert_eq!(ids.generate(), Some(0x0020_0000));
+    }
+
+    #[test]
+    fn continues_with_new_ranges() {
+        let mut ids = IdAllocator::new(0x0020_0000, 0x1);
+        assert_eq!(ids.generate(), Some(0x0020_0000));
+        assert_eq!(ids.generate(), Some(0x0020_0001));
+        assert_eq!(ids.generate(), None);
+
+        assert!(!ids.add_range(0, 1));
+        assert!(ids.add_range(0x0020_0000, 2));
+        assert_eq!(ids.generate(), Some(0x0020_0000));
+        assert_eq!(ids.generate(), Some(0x0020_0001));
+        assert_eq!(ids.generate(), None);
+    }
+
+    #[test]
+    fn encodes_get_xid_range() {
+        assert_eq!(get_xid_range_request(130), [130, 1, 1, 0]);
+        let mut reply = [0u8; 32];
+        reply[8..12].copy_from_slice(&0x0040_0010u32.to_le_bytes());
+        reply[12..16].copy_from_slice(&5u32.to_le_bytes());
+        assert_eq!(parse_get_xid_range_reply(&reply, ByteOrder::LsbFirst), Some((0x0040_0010, 5)));
+        assert_eq!(parse_get_xid_range_reply(&reply[..12], ByteOrder::LsbFirst), None);
+        reply[8..12].copy_from_slice(&0x0040_0010u32.to_be_bytes());
+        reply[12..16].copy_from_slice(&5u32.to_be_bytes());
+        assert_eq!(parse_get_xid_range_reply(&reply, ByteOrder::MsbFirst), Some((0x0040_0010, 5)));
+    }
+}
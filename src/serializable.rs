@@ -1,26 +1,387 @@
-pub trait Serializable{
-    fn bytes(&self) -> Vec<u8>;
-    fn from_bytes(bytes: &Vec<u8>) -> Self;
+//!Conversion of protocol values to and from their wire representation.
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+pub use xeagle_derive::Serializable;
+
+///Order of the bytes in values longer than one byte.
+///
+///The client picks the order of a connection in the first byte of the setup request, and the
+///server then uses it for everything it sends, except image data, whose order the setup reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    ///Least significant byte first, announced with `l` (0x6C).
+    #[default]
+    LsbFirst,
+    ///Most significant byte first, announced with `B` (0x42).
+    MsbFirst
+}
+
+impl ByteOrder {
+    ///The order of this machine, which spares both sides any swapping.
+    pub const fn native() -> Self {
+        if cfg!(target_endian = "big") { ByteOrder::MsbFirst } else { ByteOrder::LsbFirst }
+    }
+
+    ///The first byte of a setup request that asks for this order.
+    pub fn setup_byte(self) -> u8 {
+        match self {
+            ByteOrder::LsbFirst => b'l',
+            ByteOrder::MsbFirst => b'B'
+        }
+    }
+
+    ///The order a setup request starting with `byte` asks for.
+    pub fn from_setup_byte(byte: u8) -> Option<Self> {
+        match byte {
+            b'l' => Some(ByteOrder::LsbFirst),
+            b'B' => Some(ByteOrder::MsbFirst),
+            _ => None
+        }
+    }
+}
+
+///Why bytes could not be decoded into a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    ///The input ended before the value did.
+    InsufficientData {needed: usize, available: usize},
+    ///A field holds a value the protocol does not define.
+    InvalidValue(String)
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::InsufficientData {needed, available} => write!(f, "needed {} bytes but only {} are left", needed, available),
+            ParseError::InvalidValue(reason) => write!(f, "invalid value: {}", reason)
+        }
+    }
 }
 
-impl Serializable for u8 {
-    fn bytes(&self) -> Vec<u8> {self.to_le_bytes().to_vec()}
-    fn from_bytes(bytes: &Vec<u8>) -> u8{ u8::from_le_bytes(bytes[0..=0].try_into().unwrap()) }
+impl Error for ParseError {}
+
+///A value with a wire representation.
+///
+///Structs and enums can derive this, see the `xeagle_derive` crate for the attributes that
+///describe padding, alignment and lists.
+pub trait Serializable: Sized {
+    ///The wire representation in `order`.
+    fn bytes(&self, order: ByteOrder) -> Vec<u8>;
+    ///Decodes a value from the start of `bytes` in `order`.
+    ///
+    ///Returns the value together with the number of bytes it took up, so the next field can be
+    ///decoded from the rest. Any bytes after the value are left alone.
+    fn from_bytes(bytes: &[u8], order: ByteOrder) -> Result<(Self, usize), ParseError>;
 }
-impl Serializable for u16{
-    fn bytes(&self) -> Vec<u8> {self.to_le_bytes().to_vec()}
-    fn from_bytes(bytes: &Vec<u8>) -> u16{ u16::from_le_bytes(bytes[0..=1].try_into().unwrap()) }
+
+///The first `len` bytes of `bytes`, or the error for input that is too short.
+fn take(bytes: &[u8], len: usize) -> Result<&[u8], ParseError> {
+    bytes.get(..len).ok_or(ParseError::InsufficientData {needed: len, available: bytes.len()})
 }
-impl Serializable for u32{
-    fn bytes(&self) -> Vec<u8> {self.to_le_bytes().to_vec()}
-    fn from_bytes(bytes: &Vec<u8>) -> u32{ u32::from_le_bytes(bytes[0..=3].try_into().unwrap()) }
+
+macro_rules! serializable_number {
+    ($($t:ty),*) => {$(
+        impl Serializable for $t {
+            fn bytes(&self, order: ByteOrder) -> Vec<u8> {
+                match order {
+                    ByteOrder::LsbFirst => self.to_le_bytes().to_vec(),
+                    ByteOrder::MsbFirst => self.to_be_bytes().to_vec()
+                }
+            }
+
+            fn from_bytes(bytes: &[u8], order: ByteOrder) -> Result<($t, usize), ParseError> {
+                let bytes = take(bytes, size_of::<$t>())?.try_into().unwrap();
+                let value = match order {
+                    ByteOrder::LsbFirst => <$t>::from_le_bytes(bytes),
+                    ByteOrder::MsbFirst => <$t>::from_be_bytes(bytes)
+                };
+                Ok((value, size_of::<$t>()))
+            }
+        }
+    )*};
 }
-impl Serializable for u64{
-    fn bytes(&self) -> Vec<u8> {self.to_le_bytes().to_vec()}
-    fn from_bytes(bytes: &Vec<u8>) -> u64{ u64::from_le_bytes(bytes[0..=7].try_into().unwrap()) }
+
+serializable_number!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+///Nothing at all, which is what requests without a reply are answered with.
+impl Serializable for () {
+    fn bytes(&self, _: ByteOrder) -> Vec<u8> { Vec::new() }
+    fn from_bytes(_: &[u8], _: ByteOrder) -> Result<(Self, usize), ParseError> {
+        Ok(((), 0))
+    }
 }
 
+///A `BOOL`, a single byte that is 1 for true. Any other value than 0 counts as true.
+impl Serializable for bool {
+    fn bytes(&self, _: ByteOrder) -> Vec<u8> { vec![*self as u8] }
+    fn from_bytes(bytes: &[u8], _: ByteOrder) -> Result<(Self, usize), ParseError> {
+        Ok((take(bytes, 1)?[0] != 0, 1))
+    }
+}
+
+///A fixed number of values one after another, such as the 32 bytes of a `KeymapNotify`.
+impl<T: Serializable, const N: usize> Serializable for [T; N] {
+    fn bytes(&self, order: ByteOrder) -> Vec<u8> {
+        list_bytes(self, order)
+    }
+
+    fn from_bytes(bytes: &[u8], order: ByteOrder) -> Result<(Self, usize), ParseError> {
+        let (items, consumed) = list_from_bytes::<T>(bytes, N, order)?;
+        match items.try_into() {
+            Ok(array) => Ok((array, consumed)),
+            Err(_) => unreachable!("decoded exactly {} items", N)
+        }
+    }
+}
+
+///A Latin-1 string taking up all of the input, `STRING8` with its length given elsewhere.
+///
+///Characters outside of Latin-1 are sent as `?`.
 impl Serializable for String{
-    fn bytes(&self) -> Vec<u8> { self.as_bytes().to_vec() }
-    fn from_bytes(bytes: &Vec<u8>) -> Self { bytes.iter().map(|&b| b as char).collect::<String> () }
-}
\ No newline at end of file
+    fn bytes(&self, _: ByteOrder) -> Vec<u8> {
+        self.chars().map(|c| u8::try_from(c).unwrap_or(b'?')).collect()
+    }
+
+    fn from_bytes(bytes: &[u8], _: ByteOrder) -> Result<(Self, usize), ParseError> {
+        Ok((bytes.iter().map(|&b| b as char).collect::<String>(), bytes.len()))
+    }
+}
+
+///Encodes a `LISTofX`, the values one after another without a count.
+pub fn list_bytes<T: Serializable>(list: &[T], order: ByteOrder) -> Vec<u8> {
+    list.iter().flat_map(|item| item.bytes(order)).collect()
+}
+
+///Decodes a `LISTofX` of `count` values, whose count is given by another field.
+pub fn list_from_bytes<T: Serializable>(bytes: &[u8], count: usize, order: ByteOrder) -> Result<(Vec<T>, usize), ParseError> {
+    let mut items = Vec::with_capacity(count.min(bytes.len()));
+    let mut consumed = 0;
+    for _ in 0..count {
+        let (item, len) = T::from_bytes(&bytes[consumed..], order)?;
+        items.push(item);
+        consumed += len;
+    }
+    Ok((items, consumed))
+}
+
+///The number of bytes needed to pad `len` bytes to a multiple of 4.
+pub fn padding_len(len: usize) -> usize {
+    (4 - (len % 4)) % 4
+}
+
+///Encodes a `LISTofX` followed by zeros up to a multiple of 4 bytes, as requests and replies end.
+pub fn padded_list_bytes<T: Serializable>(list: &[T], order: ByteOrder) -> Vec<u8> {
+    pad_to_4(list_bytes(list, order))
+}
+
+///Decodes a `LISTofX` of `count` values and skips the padding after it.
+///
+///The padding counts towards the consumed bytes and must be present.
+pub fn padded_list_from_bytes<T: Serializable>(bytes: &[u8], count: usize, order: ByteOrder) -> Result<(Vec<T>, usize), ParseError> {
+    let (items, consumed) = list_from_bytes(bytes, count, order)?;
+    Ok((items, skip_padding(bytes, consumed)?))
+}
+
+///Encodes a Latin-1 `STRING8` followed by zeros up to a multiple of 4 bytes.
+pub fn padded_string_bytes(string: &str) -> Vec<u8> {
+    pad_to_4(string.to_string().bytes(ByteOrder::default()))
+}
+
+///Decodes a `STRING8` of `len` bytes and skips the padding after it.
+pub fn padded_string_from_bytes(bytes: &[u8], len: usize) -> Result<(String, usize), ParseError> {
+    let (string, consumed) = String::from_bytes(take(bytes, len)?, ByteOrder::default())?;
+    Ok((string, skip_padding(bytes, consumed)?))
+}
+
+fn pad_to_4(mut bytes: Vec<u8>) -> Vec<u8> {
+    bytes.resize(bytes.len() + padding_len(bytes.len()), 0);
+    bytes
+}
+
+///The offset after the padding that follows `consumed` bytes of `bytes`.
+fn skip_padding(bytes: &[u8], consumed: usize) -> Result<usize, ParseError> {
+    let padded = consumed + padding_len(consumed);
+    take(bytes, padded)?;
+    Ok(padded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: Serializable + PartialEq + std::fmt::Debug>(value: T, order: ByteOrder, wire: &[u8]) {
+        assert_eq!(value.bytes(order), wire);
+        assert_eq!(T::from_bytes(wire, order), Ok((value, wire.len())));
+    }
+
+    #[test]
+    fn round_trips_in_both_orders() {
+        round_trip(0xABu8, ByteOrder::LsbFirst, &[0xAB]);
+        round_trip(0xABu8, ByteOrder::MsbFirst, &[0xAB]);
+        round_trip(0x1234u16, ByteOrder::LsbFirst, &[0x34, 0x12]);
+        round_trip(0x1234u16, ByteOrder::MsbFirst, &[0x12, 0x34]);
+        round_trip(0x0102_0304u32, ByteOrder::LsbFirst, &[4, 3, 2, 1]);
+        round_trip(0x0102_0304u32, ByteOrder::MsbFirst, &[1, 2, 3, 4]);
+        round_trip(0x0102_0304_0506_0708u64, ByteOrder::LsbFirst, &[8, 7, 6, 5, 4, 3, 2, 1]);
+        round_trip(0x0102_0304_0506_0708u64, ByteOrder::MsbFirst, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        round_trip("xeagle".to_string(), ByteOrder::MsbFirst, b"xeagle");
+    }
+
+    #[test]
+    fn round_trips_signed_integers() {
+        round_trip(-2i8, ByteOrder::LsbFirst, &[0xFE]);
+        round_trip(-2i16, ByteOrder::LsbFirst, &[0xFE, 0xFF]);
+        round_trip(-2i16, ByteOrder::MsbFirst, &[0xFF, 0xFE]);
+        round_trip(-0x0102_0304i32, ByteOrder::MsbFirst, &[0xFE, 0xFD, 0xFC, 0xFC]);
+        round_trip(i64::MIN, ByteOrder::LsbFirst, &[0, 0, 0, 0, 0, 0, 0, 0x80]);
+    }
+
+    #[test]
+    fn round_trips_bools_and_arrays() {
+        round_trip(true, ByteOrder::LsbFirst, &[1]);
+        round_trip(false, ByteOrder::MsbFirst, &[0]);
+        assert_eq!(bool::from_bytes(&[7], ByteOrder::LsbFirst), Ok((true, 1)));
+        round_trip([1u16, 2, 3], ByteOrder::LsbFirst, &[1, 0, 2, 0, 3, 0]);
+        round_trip([1u16, 2, 3], ByteOrder::MsbFirst, &[0, 1, 0, 2, 0, 3]);
+        round_trip([[1u8, 2], [3, 4]], ByteOrder::MsbFirst, &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn reports_the_bytes_consumed() {
+        assert_eq!(u16::from_bytes(&[1, 2, 3, 4], ByteOrder::MsbFirst), Ok((0x0102, 2)));
+        let (items, consumed) = list_from_bytes::<u32>(&[1, 0, 0, 0, 2, 0, 0, 0, 9], 2, ByteOrder::LsbFirst).unwrap();
+        assert_eq!((items, consumed), (vec![1, 2], 8));
+    }
+
+    #[test]
+    fn fails_on_short_input() {
+        assert_eq!(u32::from_bytes(&[1, 2, 3], ByteOrder::LsbFirst), Err(ParseError::InsufficientData {needed: 4, available: 3}));
+        assert_eq!(bool::from_bytes(&[], ByteOrder::LsbFirst), Err(ParseError::InsufficientData {needed: 1, available: 0}));
+        assert!(<[u16; 2]>::from_bytes(&[1, 2, 3], ByteOrder::LsbFirst).is_err());
+        assert_eq!(list_from_bytes::<u16>(&[1, 0, 2], 2, ByteOrder::LsbFirst), Err(ParseError::InsufficientData {needed: 2, available: 1}));
+    }
+
+    #[test]
+    fn pads_strings_and_lists() {
+        assert_eq!(padded_string_bytes("XC-MISC"), b"XC-MISC\0");
+        assert_eq!(padded_string_bytes("RANDR").len(), 8);
+        assert_eq!(padded_string_bytes("GLX\u{263A}"), b"GLX?");
+        assert_eq!(padded_string_from_bytes(b"XC-MISC\0rest", 7), Ok(("XC-MISC".to_string(), 8)));
+        assert_eq!(padded_string_from_bytes(b"ABCD", 4), Ok(("ABCD".to_string(), 4)));
+        //The padding has to be there.
+        assert!(padded_string_from_bytes(b"XC-MISC", 7).is_err());
+
+        for order in [ByteOrder::LsbFirst, ByteOrder::MsbFirst] {
+            let wire = padded_list_bytes(&[0x1234u16, 0x5678, 0x9ABC], order);
+            assert_eq!(wire.len(), 8);
+            assert_eq!(padded_list_from_bytes::<u16>(&wire, 3, order), Ok((vec![0x1234, 0x5678, 0x9ABC], 8)));
+        }
+        assert_eq!(padded_list_from_bytes::<u32>(&[], 0, ByteOrder::LsbFirst), Ok((vec![], 0)));
+    }
+
+    #[derive(Serializable, Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(u8)]
+    enum BackingStore {
+        Never = 0,
+        WhenMapped = 1,
+        Always = 2
+    }
+
+    #[derive(Serializable, Debug, Clone, PartialEq, Eq)]
+    #[repr(i16)]
+    enum Gravity {
+        Below = -1,
+        Above = 0x100
+    }
+
+    ///A `QueryExtension` request.
+    #[derive(Serializable, Debug, PartialEq, Eq)]
+    #[serializable(align = 4)]
+    struct QueryExtension {
+        opcode: u8,
+        #[serializable(pad = 1)]
+        length: u16,
+        name_len: u16,
+        #[serializable(pad = 2, len = name_len)]
+        name: String
+    }
+
+    #[derive(Serializable, Debug, Clone, PartialEq, Eq)]
+    #[serializable(align = 32)]
+    struct Event {
+        code: u8,
+        store: BackingStore,
+        sequence: u16,
+        count: u8,
+        #[serializable(align = 4)]
+        window: u32,
+        gravity: Gravity,
+        #[serializable(len = count)]
+        points: Vec<[i16; 2]>,
+        flag: bool
+    }
+
+    fn query_extension(name: &str) -> QueryExtension {
+        QueryExtension {opcode: 98, length: 2 + name.len().div_ceil(4) as u16, name_len: 0, name: name.to_string()}
+    }
+
+    #[test]
+    fn derives_structs_with_padding_and_lists() {
+        let request = query_extension("XC-MISC");
+        let wire = request.bytes(ByteOrder::LsbFirst);
+        //The length of the name is written from the name itself.
+        assert_eq!(wire, [98, 0, 4, 0, 7, 0, 0, 0, b'X', b'C', b'-', b'M', b'I', b'S', b'C', 0]);
+        assert_eq!(QueryExtension::from_bytes(&wire, ByteOrder::LsbFirst), Ok((QueryExtension {name_len: 7, ..request}, 16)));
+
+        let wire = query_extension("BIG-REQUESTS").bytes(ByteOrder::MsbFirst);
+        assert_eq!(&wire[..8], &[98, 0, 0, 5, 0, 12, 0, 0]);
+        assert_eq!(wire.len(), 20);
+    }
+
+    #[test]
+    fn derives_alignment_and_enums_in_both_orders() {
+        let event = Event {
+            code: 2, store: BackingStore::Always, sequence: 0x0102, count: 2, window: 0x0400_0001,
+            gravity: Gravity::Below, points: vec![[1, -1], [0x0203, 4]], flag: true
+        };
+        let little = event.bytes(ByteOrder::LsbFirst);
+        assert_eq!(little.len(), 32);
+        assert_eq!(&little[..23], &[
+            2, 2, 0x02, 0x01, 2, 0, 0, 0, 0x01, 0, 0, 0x04, 0xFF, 0xFF,
+            1, 0, 0xFF, 0xFF, 0x03, 0x02, 4, 0, 1
+        ]);
+        assert!(little[23..].iter().all(|&b| b == 0));
+
+        let big = event.bytes(ByteOrder::MsbFirst);
+        assert_eq!(&big[2..4], &[0x01, 0x02]);
+        assert_eq!(&big[8..12], &[0x04, 0, 0, 0x01]);
+        for (order, wire) in [(ByteOrder::LsbFirst, &little), (ByteOrder::MsbFirst, &big)] {
+            assert_eq!(Event::from_bytes(wire, order), Ok((event.clone(), 32)));
+        }
+        assert_eq!(Event::from_bytes(&little[..31], ByteOrder::LsbFirst), Err(ParseError::InsufficientData {needed: 32, available: 31}));
+        round_trip(Gravity::Above, ByteOrder::MsbFirst, &[0x01, 0x00]);
+    }
+
+    #[test]
+    fn rejects_unknown_discriminants() {
+        round_trip(BackingStore::WhenMapped, ByteOrder::LsbFirst, &[1]);
+        assert_eq!(BackingStore::from_bytes(&[3], ByteOrder::LsbFirst), Err(ParseError::InvalidValue("unknown BackingStore 3".to_string())));
+        let mut wire = Event {
+            code: 2, store: BackingStore::Never, sequence: 1, count: 0, window: 0,
+            gravity: Gravity::Above, points: Vec::new(), flag: false
+        }.bytes(ByteOrder::LsbFirst);
+        wire[1] = 9;
+        assert!(matches!(Event::from_bytes(&wire, ByteOrder::LsbFirst), Err(ParseError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn names_the_setup_byte() {
+        assert_eq!(ByteOrder::LsbFirst.setup_byte(), 0x6C);
+        assert_eq!(ByteOrder::MsbFirst.setup_byte(), 0x42);
+        for order in [ByteOrder::LsbFirst, ByteOrder::MsbFirst] {
+            assert_eq!(ByteOrder::from_setup_byte(order.setup_byte()), Some(order));
+        }
+        assert_eq!(ByteOrder::from_setup_byte(0), None);
+        assert_eq!(0x1234u16.bytes(ByteOrder::native()), 0x1234u16.to_ne_bytes());
+    }
+}
@@ -0,0 +1,535 @@
+//!The description of the server that is sent once the connection has been accepted.
+use crate::serializable::{ByteOrder, Serializable};
+use crate::xinterface::XInterfaceError;
+
+///Order of the bytes in image data, and of the bits in bitmaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageOrder {
+    LsbFirst,
+    MsbFirst
+}
+
+impl From<ImageOrder> for ByteOrder {
+    ///The order of the bytes of pixels and scanline units in image data.
+    fn from(order: ImageOrder) -> Self {
+        match order {
+            ImageOrder::LsbFirst => ByteOrder::LsbFirst,
+            ImageOrder::MsbFirst => ByteOrder::MsbFirst
+        }
+    }
+}
+
+///How a visual maps pixel values to colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisualClass {
+    StaticGray,
+    GrayScale,
+    StaticColor,
+    PseudoColor,
+    TrueColor,
+    DirectColor
+}
+
+///Whether a screen keeps the contents of obscured windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackingStore {
+    Never,
+    WhenMapped,
+    Always
+}
+
+///A pixmap format the server supports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Format {
+    pub depth: u8,
+    pub bits_per_pixel: u8,
+    pub scanline_pad: u8
+}
+
+///A way of interpreting pixel values as colors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VisualType {
+    pub visual_id: u32,
+    pub class: VisualClass,
+    pub bits_per_rgb_value: u8,
+    pub colormap_entries: u16,
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32
+}
+
+///A depth windows can have on a screen, with the visuals available at that depth.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Depth {
+    pub depth: u8,
+    pub visuals: Vec<VisualType>
+}
+
+///A screen and its root window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screen {
+    pub root: u32,
+    pub default_colormap: u32,
+    pub white_pixel: u32,
+    pub black_pixel: u32,
+    pub current_input_masks: u32,
+    pub width_in_pixels: u16,
+    pub height_in_pixels: u16,
+    pub width_in_millimeters: u16,
+    pub height_in_millimeters: u16,
+    pub min_installed_maps: u16,
+    pub max_installed_maps: u16,
+    pub root_visual: u32,
+    pub backing_stores: BackingStore,
+    pub save_unders: bool,
+    pub root_depth: u8,
+    pub allowed_depths: Vec<Depth>
+}
+
+impl Screen {
+    ///The description of the root window's visual.
+    pub fn root_visual_type(&self) -> Option<&VisualType> {
+        self.allowed_depths.iter()
+            .flat_map(|depth| depth.visuals.iter())
+            .find(|visual| visual.visual_id == self.root_visual)
+    }
+}
+
+///Everything the server tells about itself when it accepts a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setup {
+    pub protocol_major_version: u16,
+    pub protocol_minor_version: u16,
+    pub release_number: u32,
+    ///Together with the mask, describes the resource IDs this client may allocate.
+    pub resource_id_base: u32,
+    pub resource_id_mask: u32,
+    pub motion_buffer_size: u32,
+    pub vendor: String,
+    ///The longest request the server accepts, in 4 byte units.
+    pub maximum_request_length: u16,
+    pub image_byte_order: ImageOrder,
+    pub bitmap_format_bit_order: ImageOrder,
+    pub bitmap_format_scanline_unit: u8,
+    pub bitmap_format_scanline_pad: u8,
+    pub min_keycode: u8,
+    pub max_keycode: u8,
+    pub pixmap_formats: Vec<Format>,
+    pub roots: Vec<Screen>
+}
+
+impl Setup {
+    ///Parses a complete success reply, including its 8 byte header, sent in `order`.
+    pub fn parse(reply: &[u8], order: ByteOrder) -> Result<Self, XInterfaceError> {
+        let mut reader = Reader {bytes: reply, offset: 0, order};
+        if reader.u8()? != 1 {
+            return Err(violation("setup reply is not a success"));
+        }
+        reader.skip(1)?;
+        let protocol_major_version = reader.u16()?;
+        let protocol_minor_version = reader.u16()?;
+        let length = reader.u16()? as usize * 4;
+        if reply.len() < 8 + length {
+            return Err(violation("setup reply is shorter than its length"));
+        }
+        reader.bytes = &reply[..8 + length];
+
+        let release_number = reader.u32()?;
+        let resource_id_base = reader.u32()?;
+        let resource_id_mask = reader.u32()?;
+        let motion_buffer_size = reader.u32()?;
+        let vendor_len = reader.u16()? as usize;
+        let maximum_request_length = reader.u16()?;
+        let screen_count = reader.u8()?;
+        let format_count = reader.u8()?;
+        let image_byte_order = image_order(reader.u8()?)?;
+        let bitmap_format_bit_order = image_order(reader.u8()?)?;
+        let bitmap_format_scanline_unit = reader.u8()?;
+        let bitmap_format_scanline_pad = reader.u8()?;
+        let min_keycode = reader.u8()?;
+        let max_keycode = reader.u8()?;
+        reader.skip(4)?;
+        let vendor = String::from_utf8_lossy(reader.take(vendor_len)?).into_owned();
+        reader.skip(crate::xinterface::padding(vendor_len).len())?;
+
+        let pixmap_formats = (0..format_count).map(|_| {
+            let format = Format {depth: reader.u8()?, bits_per_pixel: reader.u8()?, scanline_pad: reader.u8()?};
+            reader.skip(5)?;
+            Ok(format)
+        }).collect::<Result<_, XInterfaceError>>()?;
+
+        let roots = (0..screen_count).map(|_| reader.screen()).collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            protocol_major_version, protocol_minor_version, release_number, resource_id_base, resource_id_mask,
+            motion_buffer_size, vendor, maximum_request_length, image_byte_order, bitmap_format_bit_order,
+            bitmap_format_scanline_unit, bitmap_format_scanline_pad, min_keycode, max_keycode, pixmap_formats, roots
+        })
+    }
+
+    ///Packs one row of a bitmap, `true` for set pixels, the way the server expects it in image data.
+    ///
+    ///Pixels are grouped into scanline units whose bits are filled in `bitmap_format_bit_order`
+    ///and whose bytes are laid out in `image_byte_order`, and the row is padded to the scanline pad.
+    pub fn bitmap_scanline(&self, pixels: &[bool]) -> Vec<u8> {
+        let unit = match self.bitmap_format_scanline_unit {
+            8 | 16 => self.bitmap_format_scanline_unit as usize,
+            _ => 32
+        };
+        let pad = (self.bitmap_format_scanline_pad as usize).max(unit);
+        let units = pixels.len().div_ceil(pad) * pad / unit;
+        let order = ByteOrder::from(self.image_byte_order);
+
+        (0..units).flat_map(|index| {
+            let mut value = 0u32;
+            for (bit, _) in pixels.iter().skip(index * unit).take(unit).enumerate().filter(|(_, &set)| set) {
+                value |= match self.bitmap_format_bit_order {
+                    ImageOrder::LsbFirst => 1 << bit,
+                    ImageOrder::MsbFirst => 1 << (unit - 1 - bit)
+                };
+            }
+            match unit {
+                8 => (value as u8).bytes(order),
+                16 => (value as u16).bytes(order),
+                _ => value.bytes(order)
+            }
+        }).collect()
+    }
+}
+
+fn violation(reason: &str) -> XInterfaceError {
+    XInterfaceError::ProtocolViolation(reason.to_string())
+}
+
+fn image_order(value: u8) -> Result<ImageOrder, XInterfaceError> {
+    match value {
+        0 => Ok(ImageOrder::LsbFirst),
+        1 => Ok(ImageOrder::MsbFirst),
+        _ => Err(violation("unknown image byte order"))
+    }
+}
+
+///Reads the fields of the setup reply one after another.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    order: ByteOrder
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], XInterfaceError> {
+        let bytes = self.bytes.get(self.offset..self.offset + len).ok_or_else(|| violation("setup reply ends early"))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), XInterfaceError> {
+        self.take(len).map(|_| ())
+    }
+
+    fn read<T: Serializable>(&mut self) -> Result<T, XInterfaceError> {
+        let (value, len) = T::from_bytes(&self.bytes[self.offset..], self.order).map_err(|_| violation("setup reply ends early"))?;
+        self.offset += len;
+        Ok(value)
+    }
+
+    fn u8(&mut self) -> Result<u8, XInterfaceError> {
+        self.read()
+    }
+
+    fn u16(&mut self) -> Result<u16, XInterfaceError> {
+        self.read()
+    }
+
+    fn u32(&mut self) -> Result<u32, XInterfaceError> {
+        self.read()
+    }
+
+    fn screen(&mut self) -> Result<Screen, XInterfaceError> {
+        let root = self.u32()?;
+        let default_colormap = self.u32()?;
+        let white_pixel = self.u32()?;
+        let black_pixel = self.u32()?;
+        let current_input_masks = self.u32()?;
+        let width_in_pixels = self.u16()?;
+        let height_in_pixels = self.u16()?;
+        let width_in_millimeters = self.u16()?;
+        let height_in_millimeters = self.u16()?;
+        let min_installed_maps = self.u16()?;
+        let max_installed_maps = self.u16()?;
+        let root_visual = self.u32()?;
+        let backing_stores = match self.u8()? {
+            0 => BackingStore::Never,
+            1 => BackingStore::WhenMapped,
+            2 => BackingStore::Always,
+            _ => return Err(violation("unknown backing store"))
+        };
+        let save_unders = self.u8()? != 0;
+        let root_depth = self.u8()?;
+        let depth_count = self.u8()?;
+        let allowed_depths = (0..depth_count).map(|_| self.depth()).collect::<Result<_, _>>()?;
+
+        Ok(Screen {
+            root, default_colormap, white_pixel, black_pixel, current_input_masks, width_in_pixels, height_in_pixels,
+            width_in_millimeters, height_in_millimeters, min_installed_maps, max_installed_maps, root_visual,
+            backing_stores, save_unders, root_depth, allowed_depths
+        })
+    }
+
+    fn depth(&mut self) -> Result<Depth, XInterfaceError> {
+        let depth = self.u8()?;
+        self.skip(1)?;
+        let visual_count = self.u16()?;
+        self.skip(4)?;
+        let visuals = (0..visual_count).map(|_| self.visual_type()).collect::<Result<_, _>>()?;
+        Ok(Depth {depth, visuals})
+    }
+
+    fn visual_type(&mut self) -> Result<VisualType, XInterfaceError> {
+        let visual_id = self.u32()?;
+        let class = match self.u8()? {
+            0 => VisualClass::StaticGray,
+            1 => VisualClass::GrayScale,
+            2 => VisualClass::StaticColor,
+            3 => VisualClass::PseudoColor,
+            4 => VisualClass::TrueColor,
+            5 => VisualClass::DirectColor,
+            _ => return Err(violation("unknown visual class"))
+        };
+        let bits_per_rgb_value = self.u8()?;
+        let colormap_entries = self.u16()?;
+        let red_mask = self.u32()?;
+        let green_mask = self.u32()?;
+        let blue_mask = self.u32()?;
+        self.skip(4)?;
+        Ok(VisualType {visual_id, class, bits_per_rgb_value, colormap_entries, red_mask, green_mask, blue_mask})
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    ///A setup reply laid out like the one `Xvfb :99 -screen 0 1280x1024x24` of X.Org 21.1.4 sends
+    ///on x86-64: seven pixmap formats and one screen with TrueColor and DirectColor visuals at depth
+    ///24 and a single TrueColor visual at depth 32.
+    ///
+    ///The bytes are written out by hand, not recorded from the server. Run
+    ///`records_the_setup_of_a_running_server` against one to check them, or to print a capture that
+    ///replaces them.
+    pub(crate) const XVFB_SETUP: &[u8] = &[
+        //Success, protocol 11.0, 69 units of data
+        1, 0, 11, 0, 0, 0, 69, 0,
+        //Release 12101004, resource ids 0x00400000/0x001FFFFF, motion buffer 256
+        0x8C, 0xA5, 0xB8, 0x00, 0x00, 0x00, 0x40, 0x00, 0xFF, 0xFF, 0x1F, 0x00, 0x00, 0x01, 0x00, 0x00,
+        //Vendor length 20, maximum request length 65535, 1 screen, 7 formats, LSB first, LSB first,
+        //scanline unit and pad 32, keycodes 8 to 255
+        20, 0, 0xFF, 0xFF, 1, 7, 0, 0, 32, 32, 8, 255, 0, 0, 0, 0,
+        b'T', b'h', b'e', b' ', b'X', b'.', b'O', b'r', b'g', b' ', b'F', b'o', b'u', b'n', b'd', b'a', b't', b'i', b'o', b'n',
+        //Pixmap formats
+        1, 1, 32, 0, 0, 0, 0, 0,
+        4, 8, 32, 0, 0, 0, 0, 0,
+        8, 8, 32, 0, 0, 0, 0, 0,
+        15, 16, 32, 0, 0, 0, 0, 0,
+        16, 16, 32, 0, 0, 0, 0, 0,
+        24, 32, 32, 0, 0, 0, 0, 0,
+        32, 32, 32, 0, 0, 0, 0, 0,
+        //Screen 0: root 0x3E8, colormap 0x21, white 0xFFFFFF, black 0, input masks 0xFA8000
+        0xE8, 0x03, 0, 0, 0x21, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0x00, 0, 0, 0, 0, 0x00, 0x80, 0xFA, 0x00,
+        //1280x1024 pixels, 361x289 mm, 1 installed map, root visual 0x21, WhenMapped, no save unders,
+        //root depth 24, 7 depths
+        0x00, 0x05, 0x00, 0x04, 0x69, 0x01, 0x21, 0x01, 1, 0, 1, 0, 0x21, 0, 0, 0, 1, 0, 24, 7,
+        //Depth 24 with 2 visuals
+        24, 0, 2, 0, 0, 0, 0, 0,
+        0x21, 0, 0, 0, 4, 8, 0, 1, 0, 0, 0xFF, 0, 0, 0xFF, 0, 0, 0xFF, 0, 0, 0, 0, 0, 0, 0,
+        0x22, 0, 0, 0, 5, 8, 0, 1, 0, 0, 0xFF, 0, 0, 0xFF, 0, 0, 0xFF, 0, 0, 0, 0, 0, 0, 0,
+        //Depths 1, 4, 8, 15 and 16 without visuals
+        1, 0, 0, 0, 0, 0, 0, 0,
+        4, 0, 0, 0, 0, 0, 0, 0,
+        8, 0, 0, 0, 0, 0, 0, 0,
+        15, 0, 0, 0, 0, 0, 0, 0,
+        16, 0, 0, 0, 0, 0, 0, 0,
+        //Depth 32 with 1 visual
+        32, 0, 1, 0, 0, 0, 0, 0,
+        0x5C, 0, 0, 0, 4, 8, 0, 1, 0, 0, 0xFF, 0, 0, 0xFF, 0, 0, 0xFF, 0, 0, 0, 0, 0, 0, 0
+    ];
+
+    ///[`XVFB_SETUP`] as the same server sends it to a client that asked for MSB first. Only the
+    ///byte order of the connection changes, the server keeps its LSB first images.
+    pub(crate) const XVFB_SETUP_MSB: &[u8] = &[
+
+        //Success, protocol 11.0, 69 units of data
+        1, 0, 0, 11, 0, 0, 0, 69,
+        //Release 12101004, resource ids 0x00400000/0x001FFFFF, motion buffer 256
+        0x00, 0xB8, 0xA5, 0x8C, 0x00, 0x40, 0x00, 0x00, 0x00, 0x1F, 0xFF, 0xFF, 0x00, 0x00, 0x01, 0x00,
+        //Vendor length 20, maximum request length 65535, 1 screen, 7 formats, LSB first, LSB first,
+        //scanline unit and pad 32, keycodes 8 to 255
+        0, 20, 0xFF, 0xFF, 1, 7, 0, 0, 32, 32, 8, 255, 0, 0, 0, 0,
+        b'T', b'h', b'e', b' ', b'X', b'.', b'O', b'r', b'g', b' ', b'F', b'o', b'u', b'n', b'd', b'a', b't', b'i', b'o', b'n',
+        //Pixmap formats
+        1, 1, 32, 0, 0, 0, 0, 0,
+        4, 8, 32, 0, 0, 0, 0, 0,
+        8, 8, 32, 0, 0, 0, 0, 0,
+        15, 16, 32, 0, 0, 0, 0, 0,
+        16, 16, 32, 0, 0, 0, 0, 0,
+        24, 32, 32, 0, 0, 0, 0, 0,
+        32, 32, 32, 0, 0, 0, 0, 0,
+        //Screen 0: root 0x3E8, colormap 0x21, white 0xFFFFFF, black 0, input masks 0xFA8000
+        0x00, 0x00, 0x03, 0xE8, 0, 0, 0, 0x21, 0x00, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0x00, 0xFA, 0x80, 0x00,
+        //1280x1024 pixels, 361x289 mm, 1 installed map, root visual 0x21, WhenMapped, no save unders,
+        //root depth 24, 7 depths
+        0x05, 0x00, 0x04, 0x00, 0x01, 0x69, 0x01, 0x21, 0, 1, 0, 1, 0, 0, 0, 0x21, 1, 0, 24, 7,
+        //Depth 24 with 2 visuals
+        24, 0, 0, 2, 0, 0, 0, 0,
+        0, 0, 0, 0x21, 4, 8, 1, 0, 0, 0xFF, 0, 0, 0, 0, 0xFF, 0, 0, 0, 0, 0xFF, 0, 0, 0, 0,
+        0, 0, 0, 0x22, 5, 8, 1, 0, 0, 0xFF, 0, 0, 0, 0, 0xFF, 0, 0, 0, 0, 0xFF, 0, 0, 0, 0,
+        //Depths 1, 4, 8, 15 and 16 without visuals
+        1, 0, 0, 0, 0, 0, 0, 0,
+        4, 0, 0, 0, 0, 0, 0, 0,
+        8, 0, 0, 0, 0, 0, 0, 0,
+        15, 0, 0, 0, 0, 0, 0, 0,
+        16, 0, 0, 0, 0, 0, 0, 0,
+        //Depth 32 with 1 visual
+        32, 0, 0, 1, 0, 0, 0, 0,
+        0, 0, 0, 0x5C, 4, 8, 1, 0, 0, 0xFF, 0, 0, 0, 0, 0xFF, 0, 0, 0, 0, 0xFF, 0, 0, 0, 0
+    
+    ];
+
+    ///The smallest valid success reply: no vendor, no formats and no screens.
+    pub(crate) const MINIMAL_SETUP: &[u8] = &[
+        1, 0, 11, 0, 0, 0, 8, 0,
+        0, 0, 0, 0, 0, 0, 0x20, 0, 0xFF, 0xFF, 0x1F, 0, 0, 0, 0, 0,
+        0, 0, 0xFF, 0xFF, 0, 0, 0, 0, 32, 32, 8, 255, 0, 0, 0, 0
+    ];
+
+    #[test]
+    fn parses_an_xvfb_setup() {
+        let setup = Setup::parse(XVFB_SETUP, ByteOrder::LsbFirst).unwrap();
+        assert_eq!((setup.protocol_major_version, setup.protocol_minor_version), (11, 0));
+        assert_eq!(setup.release_number, 12_101_004);
+        assert_eq!((setup.resource_id_base, setup.resource_id_mask), (0x0040_0000, 0x001F_FFFF));
+        assert_eq!(setup.vendor, "The X.Org Foundation");
+        assert_eq!(setup.maximum_request_length, 65535);
+        assert_eq!((setup.image_byte_order, setup.bitmap_format_bit_order), (ImageOrder::LsbFirst, ImageOrder::LsbFirst));
+        assert_eq!((setup.min_keycode, setup.max_keycode), (8, 255));
+        assert_eq!(setup.pixmap_formats.len(), 7);
+        assert_eq!(setup.pixmap_formats[5], Format {depth: 24, bits_per_pixel: 32, scanline_pad: 32});
+
+        let screen = &setup.roots[0];
+        assert_eq!(setup.roots.len(), 1);
+        assert_eq!((screen.root, screen.default_colormap), (0x3E8, 0x21));
+        assert_eq!((screen.white_pixel, screen.black_pixel), (0xFF_FFFF, 0));
+        assert_eq!((screen.width_in_pixels, screen.height_in_pixels), (1280, 1024));
+        assert_eq!((screen.width_in_millimeters, screen.height_in_millimeters), (361, 289));
+        assert_eq!(screen.backing_stores, BackingStore::WhenMapped);
+        assert!(!screen.save_unders);
+        assert_eq!(screen.root_depth, 24);
+        let depths: Vec<(u8, usize)> = screen.allowed_depths.iter().map(|depth| (depth.depth, depth.visuals.len())).collect();
+        assert_eq!(depths, [(24, 2), (1, 0), (4, 0), (8, 0), (15, 0), (16, 0), (32, 1)]);
+
+        let visual = screen.root_visual_type().unwrap();
+        assert_eq!(visual.class, VisualClass::TrueColor);
+        assert_eq!((visual.red_mask, visual.green_mask, visual.blue_mask), (0xFF_0000, 0xFF00, 0xFF));
+        assert_eq!((visual.bits_per_rgb_value, visual.colormap_entries), (8, 256));
+        assert_eq!(screen.allowed_depths[0].visuals[1].class, VisualClass::DirectColor);
+    }
+
+    #[test]
+    fn parses_a_minimal_setup() {
+        let setup = Setup::parse(MINIMAL_SETUP, ByteOrder::LsbFirst).unwrap();
+        assert_eq!(setup.vendor, "");
+        assert!(setup.pixmap_formats.is_empty() && setup.roots.is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_setups() {
+        for len in [4, 8, 40, 100, XVFB_SETUP.len() - 1] {
+            assert!(matches!(Setup::parse(&XVFB_SETUP[..len], ByteOrder::LsbFirst), Err(XInterfaceError::ProtocolViolation(_))), "length {}", len);
+        }
+
+        //A screen that claims more depths than the length leaves room for.
+        let mut lying = XVFB_SETUP.to_vec();
+        lying[8 + 32 + 20 + 56 + 39] = 8;
+        assert!(Setup::parse(&lying, ByteOrder::LsbFirst).is_err());
+    }
+
+    #[test]
+    fn parses_big_endian_setups() {
+        //MINIMAL_SETUP as a server sends it on a 'B' connection, with MSB first image data.
+        let big_endian: &[u8] = &[
+            1, 0, 0, 11, 0, 0, 0, 8,
+            0, 0, 0, 0, 0, 0x20, 0, 0, 0, 0x1F, 0xFF, 0xFF, 0, 0, 0, 0,
+            0, 0, 0xFF, 0xFF, 0, 0, 1, 1, 32, 32, 8, 255, 0, 0, 0, 0
+        ];
+        let setup = Setup::parse(big_endian, ByteOrder::MsbFirst).unwrap();
+        let little_endian = Setup::parse(MINIMAL_SETUP, ByteOrder::LsbFirst).unwrap();
+        assert_eq!((setup.image_byte_order, setup.bitmap_format_bit_order), (ImageOrder::MsbFirst, ImageOrder::MsbFirst));
+        assert_eq!(Setup {image_byte_order: ImageOrder::LsbFirst, bitmap_format_bit_order: ImageOrder::LsbFirst, ..setup}, little_endian);
+        assert_eq!(ByteOrder::from(ImageOrder::MsbFirst), ByteOrder::MsbFirst);
+
+        //Read in the wrong order, the length runs past the end of the reply.
+        assert!(Setup::parse(big_endian, ByteOrder::LsbFirst).is_err());
+    }
+
+    #[test]
+    fn parses_the_xvfb_setup_in_either_byte_order() {
+        assert_eq!(XVFB_SETUP_MSB.len(), XVFB_SETUP.len());
+        assert_eq!(Setup::parse(XVFB_SETUP_MSB, ByteOrder::MsbFirst).unwrap(), Setup::parse(XVFB_SETUP, ByteOrder::LsbFirst).unwrap());
+        assert!(Setup::parse(XVFB_SETUP_MSB, ByteOrder::LsbFirst).is_err());
+    }
+
+    ///Connects to the server in `$DISPLAY` over its local socket without authorization, as Xvfb
+    ///allows by default, and prints the setup it sends in either byte order.
+    #[test]
+    #[ignore = "needs a running X server, such as `Xvfb :99 -screen 0 1280x1024x24`"]
+    fn records_the_setup_of_a_running_server() {
+        use crate::display::{ConnectAddress, DisplayName};
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixStream;
+
+        let ConnectAddress::Unix(path) = DisplayName::from_env().unwrap().address().unwrap() else { panic!("the display is not local") };
+        let mut setups = Vec::new();
+        for (order, byte) in [(ByteOrder::LsbFirst, b'l'), (ByteOrder::MsbFirst, b'B')] {
+            let mut stream = UnixStream::connect(&path).unwrap();
+            let mut request = vec![byte, 0];
+            request.extend(11u16.bytes(order));
+            request.resize(12, 0);
+            stream.write_all(&request).unwrap();
+            let mut reply = vec![0; 8];
+            stream.read_exact(&mut reply).unwrap();
+            let (units, _) = u16::from_bytes(&reply[6..], order).unwrap();
+            reply.resize(8 + 4 * units as usize, 0);
+            stream.read_exact(&mut reply[8..]).unwrap();
+            println!("{:?}: {:?}", order, reply);
+            setups.push(Setup::parse(&reply, order).unwrap());
+        }
+        assert_eq!(setups[0], setups[1]);
+    }
+
+    #[test]
+    fn packs_bitmaps_in_the_server_order() {
+        let mut setup = Setup::parse(MINIMAL_SETUP, ByteOrder::LsbFirst).unwrap();
+        let mut pixels = [false; 9];
+        pixels[0] = true;
+        pixels[8] = true;
+
+        let cases = [
+            (ImageOrder::LsbFirst, ImageOrder::LsbFirst, [0x01, 0x01, 0, 0]),
+            (ImageOrder::MsbFirst, ImageOrder::MsbFirst, [0x80, 0x80, 0, 0]),
+            (ImageOrder::LsbFirst, ImageOrder::MsbFirst, [0, 0, 0x80, 0x80]),
+            (ImageOrder::MsbFirst, ImageOrder::LsbFirst, [0, 0, 0x01, 0x01])
+        ];
+        for (byte_order, bit_order, expected) in cases {
+            setup.image_byte_order = byte_order;
+            setup.bitmap_format_bit_order = bit_order;
+            assert_eq!(setup.bitmap_scanline(&pixels), expected, "{:?} bytes, {:?} bits", byte_order, bit_order);
+        }
+
+        //Units of 8 bits are unaffected by the byte order, and rows are padded to the scanline pad.
+        setup.bitmap_format_scanline_unit = 8;
+        setup.bitmap_format_scanline_pad = 16;
+        assert_eq!(setup.bitmap_scanline(&pixels), [0x01, 0x01]);
+        assert_eq!(setup.bitmap_scanline(&[true; 17]), [0xFF, 0xFF, 0x01, 0]);
+        assert!(setup.bitmap_scanline(&[]).is_empty());
+    }
+}
@@ -1,133 +1,140 @@
 #[warn(unused_imports)]
-use std::cell::Cell;
+use std::error::Error;
 use std::fmt::{Display, Formatter};
-use std::thread;
-use std::mem::transmute;
-use std::io::{Error as CErr, ErrorKind, Read, Write};
-use std::rc::Rc;
+use std::io::{Error as CErr, ErrorKind, IoSlice, Read, Write};
+use std::mem::offset_of;
 use std::time::{Duration, Instant};
-use libc::{c_int, close, connect, fcntl, read, send, sockaddr_un, socket, AF_UNIX, F_GETFL, F_SETFL, O_NONBLOCK, SOCK_STREAM};
-use crate::serializable::Serializable;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::mem::{take, zeroed};
+use libc::{c_int, c_short, c_void, close, connect, fcntl, getpeername, getsockname, sockaddr_storage, socklen_t, in6_addr, in_addr, iovec, msghdr, poll, pollfd, recvmsg, sendmsg, setsockopt, sockaddr, sockaddr_in, sockaddr_in6, sockaddr_un, socket, AF_INET, AF_INET6, AF_UNIX, F_GETFL, F_SETFL, IPPROTO_TCP, MSG_CMSG_CLOEXEC, MSG_CTRUNC, MSG_NOSIGNAL, O_NONBLOCK, POLLIN, POLLOUT, SCM_RIGHTS, SOCK_STREAM, SOL_SOCKET, TCP_NODELAY, CMSG_DATA, CMSG_FIRSTHDR, CMSG_LEN, CMSG_NXTHDR, CMSG_SPACE};
+use crate::display::Protocol;
+use crate::serializable::{ByteOrder, ParseError, Serializable};
 
 #[macro_export]
 macro_rules! sock_write {
-    ($sock:expr; _:$len:expr, $($rest:tt)*) =>{
+    ($sock:expr, $order:expr; _:$len:expr, $($rest:tt)*) =>{
         $sock.clear_write_buf();
         $sock.write_discard_bytes($len);
-        sock_write!(@noclear $sock; $($rest)*);
+        sock_write!(@noclear $sock, $order; $($rest)*);
     };
 
-    ($sock:expr; $varname:ident, $($rest:tt)*) =>{
+    ($sock:expr, $order:expr; $varname:ident, $($rest:tt)*) =>{
         $sock.clear_write_buf();
-        $sock.write_serializable($varname)
-        sock_write!(@noclear $sock; $($rest)*);
+        $sock.write_serializable($varname, $order)
+        sock_write!(@noclear $sock, $order; $($rest)*);
     };
 
-    ($sock:expr; _:$len:expr) => {
+    ($sock:expr, $order:expr; _:$len:expr) => {
         $sock.clear_write_buf();
         $sock.write_discard_bytes($len);
     };
 
-    ($sock:expr; $varname:ident) => {
+    ($sock:expr, $order:expr; $varname:ident) => {
         $sock.clear_write_buf();
-        $sock.write_serializable($varname)?;
+        $sock.write_serializable($varname, $order)?;
     };
 
-    (@noclear $sock:expr; _:$len:expr, $($rest:tt)*) =>{
+    (@noclear $sock:expr, $order:expr; _:$len:expr, $($rest:tt)*) =>{
         $sock.write_discard_bytes($len);
-        sock_write!(@noclear $sock; $($rest)*);
+        sock_write!(@noclear $sock, $order; $($rest)*);
     };
 
-    (@noclear $sock:expr; $varname:ident, $($rest:tt)*) =>{
-        $sock.write_serializable($varname)
-        sock_write!(@noclear $sock; $($rest)*);
+    (@noclear $sock:expr, $order:expr; $varname:ident, $($rest:tt)*) =>{
+        $sock.write_serializable($varname, $order)
+        sock_write!(@noclear $sock, $order; $($rest)*);
     };
 
-    (@noclear $sock:expr; _:$len:expr) => {
+    (@noclear $sock:expr, $order:expr; _:$len:expr) => {
         $sock.write_discard_bytes($len);
     };
 
-    (@noclear $sock:expr; $varname:ident) => {
-        $sock.write_serializable($varname)?;
+    (@noclear $sock:expr, $order:expr; $varname:ident) => {
+        $sock.write_serializable($varname, $order)?;
     };
 }
 
 #[macro_export]
 macro_rules! sock_read {
-    ($sock:expr; _:$len:expr, $($rest:tt)*) =>{
+    ($sock:expr, $order:expr; _:$len:expr, $($rest:tt)*) =>{
         $sock.clear_read_buf();
         $sock.read_discard_bytes($len)?;
-        sock_read!(@noclear $sock; $($rest)*);
+        sock_read!(@noclear $sock, $order; $($rest)*);
     };
 
-    ($sock:expr; $varname:ident[$t:ty:$len:expr], $($rest:tt)*) =>{
+    ($sock:expr, $order:expr; $varname:ident[$t:ty:$len:expr], $($rest:tt)*) =>{
         $sock.clear_read_buf();
-        let $varname = $sock.read_serializable::<$t>($len)?;
-        sock_read!(@noclear $sock; $($rest)*);
+        let $varname = $sock.read_serializable::<$t>($len, $order)?;
+        sock_read!(@noclear $sock, $order; $($rest)*);
     };
 
-    ($sock:expr; _:$len:expr) =>{
+    ($sock:expr, $order:expr; _:$len:expr) =>{
         $sock.clear_read_buf();
         $sock.read_discard_bytes($len)?;
     };
 
-    ($sock:expr; $varname:ident[$t:ty:$len:expr]) =>{
+    ($sock:expr, $order:expr; $varname:ident[$t:ty:$len:expr]) =>{
         $sock.clear_read_buf();
-        let $varname = $sock.read_serializable::<$t>($len)?;
+        let $varname = $sock.read_serializable::<$t>($len, $order)?;
     };
 
-    (@noclear $sock:expr; _:$len:expr, $($rest:tt)*) =>{
+    (@noclear $sock:expr, $order:expr; _:$len:expr, $($rest:tt)*) =>{
         $sock.read_discard_bytes($len)?;
-        sock_read!(@noclear $sock; $($rest)*);
+        sock_read!(@noclear $sock, $order; $($rest)*);
     };
 
-    (@noclear $sock:expr; $varname:ident[$t:ty:$len:expr], $($rest:tt)*) =>{
-        let $varname = $sock.read_serializable::<$t>($len)?;
-        sock_read!(@noclear $sock; $($rest)*);
+    (@noclear $sock:expr, $order:expr; $varname:ident[$t:ty:$len:expr], $($rest:tt)*) =>{
+        let $varname = $sock.read_serializable::<$t>($len, $order)?;
+        sock_read!(@noclear $sock, $order; $($rest)*);
     };
 
-    (@noclear $sock:expr; _:$len:expr) =>{
+    (@noclear $sock:expr, $order:expr; _:$len:expr) =>{
         $sock.read_discard_bytes($len)?;
     };
 
-    (@noclear $sock:expr; $varname:ident[$t:ty:$len:expr]) =>{
-        let $varname = $sock.read_serializable::<$t>($len)?;
+    (@noclear $sock:expr, $order:expr; $varname:ident[$t:ty:$len:expr]) =>{
+        let $varname = $sock.read_serializable::<$t>($len, $order)?;
     };
 }
 
 pub struct Socket{
-    socket_file_descriptor: Rc<Cell<Option<i32>>>,
+    socket_file_descriptor: OwnedFd,
     write_buf: Vec<u8>,
-    read_buf: Vec<u8>,
-    retry: bool,
-    retry_frequency: u32,
-    retry_timeout: u32
+    write_fds: Vec<OwnedFd>,
+    read_count: usize,
+    read_fds: Vec<OwnedFd>,
+    timeout: Option<Duration>
 }
 
+///How many slices a single `sendmsg` accepts on every platform we care about.
+const MAX_IOV: usize = 1024;
+
+///How many descriptors a single read can receive, the same limit libxcb uses.
+const MAX_FDS_PER_READ: usize = 16;
+
 #[allow(dead_code)]
 impl Socket{
-    pub fn set_retry(&mut self, retry: bool){
-        self.retry = retry;
-    }
-
-    pub fn set_retry_frequency(&mut self, frequency: u32){
-        self.retry_frequency = frequency;
+    ///Sets how long a single blocking read may wait for the server in total, `None` waits forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>){
+        self.timeout = timeout;
     }
 
-    pub fn set_retry_timeout(&mut self, timeout: u32){
-        self.retry_timeout = timeout;
+    pub fn timeout(&self) -> Option<Duration>{
+        self.timeout
     }
 
+    ///Resets the count of bytes read that `read_pad` pads up from.
     pub fn clear_read_buf(&mut self){
-        self.read_buf.clear();
+        self.read_count = 0;
     }
 
     pub fn clear_write_buf(&mut self){
         self.write_buf.clear();
     }
 
+    ///The number of bytes read since the last `clear_read_buf`.
     pub fn len_read_buf(&self) -> usize{
-        self.read_buf.len()
+        self.read_count
     }
 
     pub fn len_write_buf(&self) -> usize{
@@ -136,21 +143,22 @@ impl Socket{
 
     pub fn write_pad(&mut self, mod_bytes: usize) -> Result<usize, SockError>{
         let len = (mod_bytes - (self.len_write_buf() % mod_bytes)) % mod_bytes;
-        self.write_all(vec![0; len])?;
+        self.write_discard_bytes(len)?;
         Ok(len)
     }
 
     pub fn write_discard_bytes(&mut self, len: usize) -> Result<(), SockError>{
-        self.write_all(vec![0; len])
+        self.write_buf.resize(self.write_buf.len() + len, 0);
+        Ok(())
     }
 
-    pub fn write_serializable<T>(&mut self, to_write: T) -> Result<(), SockError> where T: Serializable{
-        self.write_all(to_write.bytes())?;
+    pub fn write_serializable<T>(&mut self, to_write: T, order: ByteOrder) -> Result<(), SockError> where T: Serializable{
+        self.write_all(&to_write.bytes(order))?;
         Ok(())
     }
 
-    pub fn write_all(&mut self, data: Vec<u8>) -> Result<(), SockError> {
-        let bytes_written = self.write(data.as_slice())?;
+    pub fn write_all(&mut self, data: &[u8]) -> Result<(), SockError> {
+        let bytes_written = self.write(data)?;
         if bytes_written != data.len() {
             Err(SockError::IncompleteWrite)
         }else {
@@ -158,13 +166,67 @@ impl Socket{
         }
     }
 
+    ///Sends the buffered data followed by `bufs` without copying them into the buffer first.
+    ///
+    ///Meant for large requests such as image uploads, which can be passed as header, payload and
+    ///padding slices. Returns once every byte has been handed to the kernel.
+    pub fn write_vectored_all(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), SockError> {
+        let mut buffered = take(&mut self.write_buf);
+        let mut slices: Vec<IoSlice<'_>> = Vec::with_capacity(bufs.len() + 1);
+        slices.push(IoSlice::new(&buffered));
+        slices.extend_from_slice(bufs);
+
+        self.send_all(&mut slices)?;
+
+        //Keep the allocation for the next request.
+        buffered.clear();
+        self.write_buf = buffered;
+        Ok(())
+    }
+
+    ///Sends every byte in `slices`, resuming after partial writes and waiting out a full socket buffer.
+    fn send_all(&mut self, slices: &mut [IoSlice<'_>]) -> Result<(), CErr> {
+        let socket_fd = self.as_raw_fd();
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut slices = slices;
+        IoSlice::advance_slices(&mut slices, 0);
+
+        while !slices.is_empty() {
+            let iov_count = slices.len().min(MAX_IOV);
+            let c_result = send_vectored(socket_fd, &slices[..iov_count], &self.write_fds);
+
+            if c_result >= 0 {
+                //The descriptors went out with the first byte, the peer holds its own copies now.
+                self.write_fds.clear();
+                IoSlice::advance_slices(&mut slices, c_result as usize);
+                continue;
+            }
+
+            let error = CErr::last_os_error();
+            match error.kind() {
+                ErrorKind::Interrupted => continue,
+                ErrorKind::WouldBlock => wait_ready(socket_fd, POLLOUT, deadline)?,
+                _ => return Err(error)
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn flush_all(&mut self) -> Result<(), SockError> {
         self.flush()?;
         Ok(())
     }
 
-    pub fn read_serializable<T>(&mut self, num_bytes: usize) -> Result<T, SockError> where T: Serializable{
-        Ok(T::from_bytes(&self.read_bytes(num_bytes)?))
+    pub fn read_serializable<T>(&mut self, num_bytes: usize, order: ByteOrder) -> Result<T, SockError> where T: Serializable{
+        //Fields are a few bytes long, only the odd long one needs the heap.
+        let mut field = [0u8; 64];
+        if num_bytes <= field.len() {
+            self.read_exact_into(&mut field[..num_bytes])?;
+            Ok(T::from_bytes(&field[..num_bytes], order)?.0)
+        }else{
+            Ok(T::from_bytes(&self.read_bytes(num_bytes)?, order)?.0)
+        }
     }
 
     pub fn read_pad(&mut self, mod_bytes: usize) -> Result<usize, SockError>{
@@ -176,130 +238,423 @@ impl Socket{
     }
 
     pub fn read_discard_bytes(&mut self, num_bytes: usize) -> Result<(), SockError> {
-        self.read_bytes(num_bytes)?;
+        let mut sink = [0u8; 64];
+        let mut remaining = num_bytes;
+        while remaining > 0 {
+            let chunk = remaining.min(sink.len());
+            self.read_exact_into(&mut sink[..chunk])?;
+            remaining -= chunk;
+        }
         Ok(())
     }
 
     pub fn read_bytes(&mut self, num_bytes: usize) -> Result<Vec<u8>, SockError> {
         let mut buf = vec![0u8; num_bytes];
-        let bytes_read = self.read(&mut buf)?;
-        if bytes_read == num_bytes {
-            Ok(buf)
-        }else{
-            Err(SockError::IncompleteRead)
-        }
+        self.read_exact_into(&mut buf)?;
+        Ok(buf)
+    }
+
+    ///Fills `buf` completely, waiting for the server for at most the configured timeout.
+    fn read_exact_into(&mut self, buf: &mut [u8]) -> Result<(), SockError> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let (filled, result) = fill(self.as_raw_fd(), buf, &mut self.read_fds, deadline);
+        //Bytes read before a failure are gone from the stream all the same.
+        self.read_count += filled;
+        Ok(result?)
     }
 
     pub fn read_bytes_raw<const N: usize>(&mut self) -> Result<[u8; N], SockError> {
         let mut ret: [u8; N] = [0; N];
-        let bytes_read = self.read(&mut ret)?;
-        if bytes_read == N {
-            Ok(ret)
-        }else{
-            Err(SockError::IncompleteRead)
-        }
+        self.read_exact_into(&mut ret)?;
+        Ok(ret)
     }
 
+    ///Reads whatever the server has already sent without waiting for more.
     pub fn read_all(&mut self) -> Result<Vec<u8>, SockError> {
-        let mut pre: Vec<u8> = vec![0; 16384];
-        let previous_retry = self.retry;
-        self.retry = false;
-        let my_read = self.read(pre.as_mut_slice());
-        self.retry = previous_retry;
-        match my_read{
-            Ok(bytes_read) => {
-                if bytes_read < pre.len() - 1{
-                    Ok(Vec::from(pre[0..bytes_read].to_vec()))
-                }else{
-                    let next_read_all = self.read_all()?;
-                    pre.extend(next_read_all);
-                    Ok(pre)
+        let socket_fd = self.as_raw_fd();
+        let mut ret: Vec<u8> = Vec::new();
+        let mut chunk = [0u8; 16384];
+
+        loop {
+            match read_some(socket_fd, &mut chunk, &mut self.read_fds) {
+                Ok(Some(bytes_read)) => ret.extend_from_slice(&chunk[..bytes_read]),
+                Ok(None) => break,
+                //Hand out what arrived before the server hung up, the next call reports the closed connection.
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof && !ret.is_empty() => break,
+                Err(e) => return Err(e.into())
+            }
+        }
+
+        self.read_count += ret.len();
+        Ok(ret)
+    }
+
+    ///Reads into `buf` and returns the number of bytes read.
+    ///
+    ///When `block` is set this waits for at least one byte for at most the configured timeout,
+    ///otherwise it returns `0` if nothing has arrived yet.
+    pub fn read_into(&mut self, buf: &mut [u8], block: bool) -> Result<usize, SockError> {
+        let socket_fd = self.as_raw_fd();
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            match read_some(socket_fd, buf, &mut self.read_fds)? {
+                Some(bytes_read) => {
+                    self.read_count += bytes_read;
+                    return Ok(bytes_read);
                 }
+                None if block => wait_ready(socket_fd, POLLIN, deadline)?,
+                None => return Ok(0)
             }
-            Err(e) => {println!("Error reading: {}", e); Err(SockError::from(e))}
         }
     }
 
+    ///Opens a second handle on the same connection with its own, empty buffers.
+    ///
+    ///This lets one handle read while another one writes, each from its own thread.
+    pub fn try_clone(&self) -> Result<Self, SockError> {
+        let file_descriptor = self.socket_file_descriptor.try_clone()?;
+        Ok(Self {socket_file_descriptor: file_descriptor, write_buf: Vec::new(), write_fds: Vec::new(), read_count: 0, read_fds: Vec::new(), timeout: self.timeout})
+    }
+
+    ///Queues descriptors to be passed to the peer along with the next flush.
+    ///
+    ///Only works on local sockets, and the descriptors are closed here once they have been sent.
+    pub fn write_fds(&mut self, fds: Vec<OwnedFd>){
+        self.write_fds.extend(fds);
+    }
+
+    ///Takes every descriptor received so far, in the order they arrived.
+    pub fn take_fds(&mut self) -> Vec<OwnedFd>{
+        std::mem::take(&mut self.read_fds)
+    }
+
+    ///Connects to the local socket at `path`.
+    ///
+    ///On Linux the abstract socket `@path` is tried first, since that is the only endpoint a sandboxed
+    ///client may be able to reach, and the filesystem path is used as a fallback the same way libxcb does it.
     pub fn new(path: &str) -> Result<Self, SockError> {
+        #[cfg(target_os = "linux")]
+        if let Ok(socket) = Self::connect_unix(path, true) {
+            return Ok(socket);
+        }
+
+        Self::connect_unix(path, false)
+    }
+
+    fn connect_unix(path: &str, abstract_name: bool) -> Result<Self, SockError> {
         let mut sock_addr: sockaddr_un = sockaddr_un{ sun_family: 0, sun_path: [0; 108] };
-        if path.len() >= sock_addr.sun_path.len() {
+        //Abstract names start with a NUL byte, filesystem paths need room for a terminating one.
+        if path.len() + 1 > sock_addr.sun_path.len() {
             return Err(SockError::PathTooLong);
         }
 
         sock_addr.sun_family = AF_UNIX as u16;
 
-        let sun_path_slice = &mut sock_addr.sun_path[0..path.len()];
-        let path_bytes = path.as_bytes();
-        for i in 0..path.len() {
-            sun_path_slice[i] = path_bytes[i] as i8;
+        let offset = if abstract_name { 1 } else { 0 };
+        let sun_path_slice = &mut sock_addr.sun_path[offset..offset + path.len()];
+        for (dest, &src) in sun_path_slice.iter_mut().zip(path.as_bytes()) {
+            *dest = src as i8;
+        }
+
+        //The kernel takes every byte of an abstract name as significant, so the length has to be exact.
+        let address_len = offset_of!(sockaddr_un, sun_path) + offset + path.len();
+        let file_descriptor = Self::connect_raw(AF_UNIX, &sock_addr as *const sockaddr_un as *const _, address_len as u32)?;
+        Ok(Self::from_fd(file_descriptor))
+    }
+
+    ///Connects to `port` on `host` over TCP, trying every address the host resolves to in order.
+    ///
+    ///`Protocol::Inet` and `Protocol::Inet6` restrict the attempts to IPv4 and IPv6 addresses respectively.
+    pub fn connect_tcp(host: &str, port: u16, protocol: Protocol) -> Result<Self, SockError> {
+        let addresses = (host, port).to_socket_addrs().map_err(SockError::ResolveError)?;
+        let mut last_error = SockError::NoAddress;
+
+        for address in addresses {
+            let connected = match (address, protocol) {
+                (SocketAddr::V4(v4), Protocol::Tcp | Protocol::Inet) => {
+                    let sock_addr = sockaddr_in{
+                        sin_family: AF_INET as u16,
+                        sin_port: v4.port().to_be(),
+                        sin_addr: in_addr{ s_addr: u32::from_ne_bytes(v4.ip().octets()) },
+                        sin_zero: [0; 8]
+                    };
+                    Self::connect_raw(AF_INET, &sock_addr as *const sockaddr_in as *const _, size_of::<sockaddr_in>() as u32)
+                }
+                (SocketAddr::V6(v6), Protocol::Tcp | Protocol::Inet6) => {
+                    let sock_addr = sockaddr_in6{
+                        sin6_family: AF_INET6 as u16,
+                        sin6_port: v6.port().to_be(),
+                        sin6_flowinfo: v6.flowinfo(),
+                        sin6_addr: in6_addr{ s6_addr: v6.ip().octets() },
+                        sin6_scope_id: v6.scope_id()
+                    };
+                    Self::connect_raw(AF_INET6, &sock_addr as *const sockaddr_in6 as *const _, size_of::<sockaddr_in6>() as u32)
+                }
+                _ => continue
+            };
+
+            match connected {
+                Ok(file_descriptor) => {
+                    //X requests are small and latency bound, so don't let Nagle hold them back.
+                    let no_delay: c_int = 1;
+                    unsafe {
+                        setsockopt(file_descriptor, IPPROTO_TCP, TCP_NODELAY, &no_delay as *const c_int as *const _, size_of::<c_int>() as u32);
+                    }
+                    return Ok(Self::from_fd(file_descriptor));
+                }
+                Err(e) => last_error = e
+            }
+        }
+
+        Err(last_error)
+    }
+
+    ///The address of the server end of a TCP connection, `None` for local sockets.
+    pub fn peer_address(&self) -> Result<Option<SocketAddr>, SockError> {
+        self.address_of(getpeername)
+    }
+
+    ///The address of our end of a TCP connection, `None` for local sockets.
+    pub fn local_address(&self) -> Result<Option<SocketAddr>, SockError> {
+        self.address_of(getsockname)
+    }
+
+    ///Asks `query`, either `getpeername` or `getsockname`, for an address of the socket.
+    fn address_of(&self, query: unsafe extern "C" fn(c_int, *mut sockaddr, *mut socklen_t) -> c_int) -> Result<Option<SocketAddr>, SockError> {
+        let mut storage: sockaddr_storage = unsafe { zeroed() };
+        let mut len = size_of::<sockaddr_storage>() as socklen_t;
+        if unsafe { query(self.socket_file_descriptor.as_raw_fd(), &mut storage as *mut sockaddr_storage as *mut _, &mut len) } < 0 {
+            return Err(CErr::last_os_error().into());
         }
 
-        let file_descriptor: i32;
+        match storage.ss_family as c_int {
+            AF_INET => {
+                let v4 = unsafe { *(&storage as *const sockaddr_storage as *const sockaddr_in) };
+                let ip = std::net::Ipv4Addr::from(v4.sin_addr.s_addr.to_ne_bytes());
+                Ok(Some(SocketAddr::from((ip, u16::from_be(v4.sin_port)))))
+            }
+            AF_INET6 => {
+                let v6 = unsafe { *(&storage as *const sockaddr_storage as *const sockaddr_in6) };
+                let ip = std::net::Ipv6Addr::from(v6.sin6_addr.s6_addr);
+                Ok(Some(SocketAddr::V6(std::net::SocketAddrV6::new(ip, u16::from_be(v6.sin6_port), v6.sin6_flowinfo, v6.sin6_scope_id))))
+            }
+            _ => Ok(None)
+        }
+    }
+
+    ///Creates a stream socket in `domain` and connects it to `address`, closing it again if the connect fails.
+    fn connect_raw(domain: c_int, address: *const sockaddr, address_len: u32) -> Result<c_int, SockError> {
         unsafe {
-            file_descriptor = socket(AF_UNIX, SOCK_STREAM, 0);
+            let file_descriptor = socket(domain, SOCK_STREAM, 0);
 
             if file_descriptor == -1{
-                return Err(SockError::InitializeError(CErr::last_os_error().raw_os_error().unwrap()));
+                return Err(SockError::InitializeError(CErr::last_os_error()));
             }
 
-            let connect_ret = connect(file_descriptor, transmute::<&sockaddr_un, *const _>(&sock_addr), size_of::<sockaddr_un>() as u32);
+            let connect_ret = connect(file_descriptor, address, address_len);
             if connect_ret == -1{
-                return Err(SockError::ConnectError(CErr::last_os_error().raw_os_error().unwrap()));
+                let error = CErr::last_os_error();
+                close(file_descriptor);
+                return Err(match error.kind() {
+                    ErrorKind::ConnectionRefused => SockError::ConnectionRefused(error),
+                    _ => SockError::ConnectError(error)
+                });
             }
+
+            Ok(file_descriptor)
         }
+    }
 
-        Ok(Self {socket_file_descriptor: Rc::new(Cell::new(Some(file_descriptor))), write_buf: Vec::new(), read_buf: Vec::new(), retry: true, retry_frequency: 0, retry_timeout: 10000000})
+    pub(crate) fn from_fd(file_descriptor: c_int) -> Self {
+        //Reads never block in the kernel, waiting happens in poll so that the timeout can be honored.
+        unsafe{
+            let flags = fcntl(file_descriptor, F_GETFL, 0);
+            if (flags & O_NONBLOCK as c_int) == 0 {
+                fcntl(file_descriptor, F_SETFL, flags | O_NONBLOCK);
+            }
+        }
+
+        //connect_raw hands over a freshly created descriptor that nothing else owns.
+        let socket_file_descriptor = unsafe { OwnedFd::from_raw_fd(file_descriptor) };
+        Self {socket_file_descriptor, write_buf: Vec::new(), write_fds: Vec::new(), read_count: 0, read_fds: Vec::new(), timeout: None}
     }
 }
 
-impl Read for Socket{
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize, CErr> {
-        let socket_fd = match self.socket_file_descriptor.get(){
-            None => return Err(CErr::new(ErrorKind::InvalidInput, "Socket file descriptor not present.")),
-            Some(fd) => fd
-        };
-        let mut start_time = Instant::now();
-        let mut c_result: i32;
-        let mut read_buf: Vec<u8> = Vec::new();
-        let mut done = false;
+///Reads once from a non-blocking `socket_fd`, returning `None` when nothing is available yet.
+///
+///Descriptors passed along with the data are appended to `fds`. End of file is reported as an
+///`UnexpectedEof` error since the server never closes a healthy connection.
+fn read_some(socket_fd: c_int, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> Result<Option<usize>, CErr> {
+    let mut iov = iovec{ iov_base: buf.as_mut_ptr() as _, iov_len: buf.len() };
+    let mut control = ControlBuffer::new(MAX_FDS_PER_READ);
 
-        unsafe{
-            let flags = fcntl(socket_fd.clone(), F_GETFL, 0);
-            if (flags & O_NONBLOCK as c_int) == 0 {
-                fcntl(socket_fd.clone(), F_SETFL, flags | O_NONBLOCK);
+    loop {
+        let mut msg: msghdr = unsafe { zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr();
+        msg.msg_controllen = control.len() as _;
+
+        let c_result = unsafe{ recvmsg(socket_fd, &mut msg, MSG_CMSG_CLOEXEC) };
+
+        if c_result > 0 {
+            unsafe { control.take_fds(&msg, fds) };
+            if msg.msg_flags & MSG_CTRUNC != 0 {
+                //The kernel has closed the descriptors that did not fit, and the bytes they came
+                //with are gone from the stream, so there is no way to carry on.
+                fds.clear();
+                return Err(CErr::new(ErrorKind::InvalidData, format!("Received more than {MAX_FDS_PER_READ} file descriptors at once.")));
+            }
+            return Ok(Some(c_result as usize));
+        }
+        if c_result == 0 {
+            return Err(CErr::new(ErrorKind::UnexpectedEof, "Connection closed by peer."));
+        }
+
+        let error = CErr::last_os_error();
+        match error.kind() {
+            ErrorKind::Interrupted => continue,
+            ErrorKind::WouldBlock => return Ok(None),
+            _ => return Err(error)
+        }
+    }
+}
+
+///Reads until `buf` is full, waiting for more for as long as `deadline` allows.
+///
+///Returns how many bytes were read along with the error that stopped it early, if any.
+fn fill(socket_fd: c_int, buf: &mut [u8], fds: &mut Vec<OwnedFd>, deadline: Option<Instant>) -> (usize, Result<(), CErr>) {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let step = match read_some(socket_fd, &mut buf[filled..], fds) {
+            Ok(Some(bytes_read)) => {
+                filled += bytes_read;
+                continue;
             }
+            Ok(None) => wait_ready(socket_fd, POLLIN, deadline),
+            Err(e) => Err(e)
+        };
+        if let Err(e) = step {
+            return (filled, Err(e));
         }
+    }
+    (filled, Ok(()))
+}
 
-        while !done {
-            c_result = unsafe{ read(socket_fd.clone(), buf.as_mut_ptr() as _, buf.len()) as i32 };
+///Sends as much of `bufs` as the socket takes with a single `sendmsg`, with `fds` attached.
+///
+///A closed connection is reported as `EPIPE` without raising `SIGPIPE`, which would kill a host
+///process that has not ignored it.
+fn send_vectored(socket_fd: c_int, bufs: &[IoSlice<'_>], fds: &[OwnedFd]) -> isize {
+    let mut msg: msghdr = unsafe { zeroed() };
+    //IoSlice is guaranteed to have the same layout as iovec.
+    msg.msg_iov = bufs.as_ptr() as *mut iovec;
+    msg.msg_iovlen = bufs.len() as _;
+
+    if fds.is_empty() {
+        return unsafe { sendmsg(socket_fd, &msg, MSG_NOSIGNAL) };
+    }
 
-            if c_result > 0{
-                start_time = Instant::now();
+    let mut control = ControlBuffer::new(fds.len());
+    msg.msg_control = control.as_mut_ptr();
+    msg.msg_controllen = control.len() as _;
 
-                for i in 0..c_result{
-                    read_buf.push(buf[i as usize]);
+    unsafe {
+        let cmsg = CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = SOL_SOCKET;
+        (*cmsg).cmsg_type = SCM_RIGHTS;
+        (*cmsg).cmsg_len = CMSG_LEN((fds.len() * size_of::<c_int>()) as u32) as _;
+        let data = CMSG_DATA(cmsg) as *mut c_int;
+        for (i, fd) in fds.iter().enumerate() {
+            data.add(i).write_unaligned(fd.as_raw_fd());
+        }
+
+        sendmsg(socket_fd, &msg, MSG_NOSIGNAL)
+    }
+}
+
+///Storage for control messages, kept in `u64`s so that the headers inside are properly aligned.
+struct ControlBuffer(Vec<u64>);
+
+impl ControlBuffer {
+    fn new(max_fds: usize) -> Self {
+        let len = unsafe { CMSG_SPACE((max_fds * size_of::<c_int>()) as u32) } as usize;
+        Self(vec![0; len.div_ceil(size_of::<u64>())])
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut c_void {
+        self.0.as_mut_ptr() as _
+    }
+
+    fn len(&self) -> usize {
+        self.0.len() * size_of::<u64>()
+    }
+
+    ///Takes ownership of every descriptor in the `SCM_RIGHTS` messages `msg` received into this buffer.
+    unsafe fn take_fds(&self, msg: &msghdr, fds: &mut Vec<OwnedFd>) {
+        let mut cmsg = CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == SOL_SOCKET && (*cmsg).cmsg_type == SCM_RIGHTS {
+                let data = CMSG_DATA(cmsg) as *const c_int;
+                let count = ((*cmsg).cmsg_len as usize - CMSG_LEN(0) as usize) / size_of::<c_int>();
+                for i in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
                 }
             }
+            cmsg = CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+}
 
-            if read_buf.len() as i32 != buf.len() as i32 {
-                if self.retry && self.retry_timeout >= (start_time.elapsed().as_nanos() as u32){
-                    thread::sleep(Duration::from_nanos(self.retry_frequency as u64));
-                    continue;
-                }else{
-                    if self.retry {
-                        return Err(CErr::new(ErrorKind::TimedOut, "Socket read timeout."));
-                    }
-
-                    done = true;
+///Blocks until `socket_fd` is ready for `events` (`POLLIN` or `POLLOUT`) or `deadline` has passed.
+fn wait_ready(socket_fd: c_int, events: c_short, deadline: Option<Instant>) -> Result<(), CErr> {
+    loop {
+        let timeout_ms = match deadline {
+            None => -1,
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    let message = if events == POLLOUT { "Socket write timeout." } else { "Socket read timeout." };
+                    return Err(CErr::new(ErrorKind::TimedOut, message));
                 }
-            } else {
-                done = true;
+                //Round up so that a sub-millisecond remainder still waits instead of spinning.
+                remaining.as_nanos().div_ceil(1_000_000).min(c_int::MAX as u128) as c_int
+            }
+        };
+
+        let mut poll_fd = pollfd{ fd: socket_fd, events, revents: 0 };
+        let c_result = unsafe{ poll(&mut poll_fd, 1, timeout_ms) };
+
+        if c_result > 0 {
+            return Ok(());
+        }
+        if c_result == -1 {
+            let error = CErr::last_os_error();
+            if error.kind() != ErrorKind::Interrupted {
+                return Err(error);
             }
         }
+    }
+}
 
-        let len = read_buf.len();
-        self.read_buf.extend(read_buf);
-        Ok(len)
+impl Read for Socket{
+    ///Reads what has arrived into `buf`, waiting for the first byte for at most the configured
+    ///timeout.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, CErr> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let socket_fd = self.as_raw_fd();
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            if let Some(bytes_read) = read_some(socket_fd, buf, &mut self.read_fds)? {
+                self.read_count += bytes_read;
+                return Ok(bytes_read);
+            }
+            wait_ready(socket_fd, POLLIN, deadline)?;
+        }
     }
 }
 
@@ -312,87 +667,392 @@ impl Write for Socket{
         }
     }
 
+    ///Sends the buffered data, passing any queued descriptors along with its first byte.
     fn flush(&mut self) -> Result<(), CErr> {
-        let c_result: i32;
-        unsafe {
-            match self.socket_file_descriptor.get(){
-                None => return Err(CErr::new(ErrorKind::InvalidData, "Socket file descriptor not present.")),
-                Some(socket_fd) => c_result = send(socket_fd.clone(), self.write_buf.as_ptr() as _, self.write_buf.len(), 0) as i32
-            }
-        }
-
-        if c_result != self.write_buf.len() as i32{
-            return Err(CErr::new(ErrorKind::InvalidData, "WriteError"));
-        }
+        let buffered = take(&mut self.write_buf);
+        let result = self.send_all(&mut [IoSlice::new(&buffered)]);
+        self.write_buf = buffered;
+        result?;
 
         self.write_buf.clear();
-
         Ok(())
     }
 }
 
-impl Clone for Socket{
-    fn clone(&self) -> Self {
-        Socket{
-            socket_file_descriptor: Rc::clone(&self.socket_file_descriptor),
-            write_buf: Vec::new(),
-            read_buf: Vec::new(),
-            retry: self.retry,
-            retry_frequency: self.retry_frequency,
-            retry_timeout: self.retry_timeout
-        }
+impl AsRawFd for Socket{
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket_file_descriptor.as_raw_fd()
     }
 }
-impl Drop for Socket {
-    fn drop(&mut self) {
-        unsafe {
-            match self.socket_file_descriptor.get() {
-                None => println!("Socket file descriptor already dropped."),
-                Some(socket_fd) => {
-                    if Rc::strong_count(&self.socket_file_descriptor) == 1 {
-                        let close_ret = close(socket_fd);
-                        if close_ret == -1 {
-                            //The close documentation suggests it should not be retried after an error.
-                            println!("Socket close on drop encountered error: {}, sock_fd: {}", CErr::last_os_error().raw_os_error().unwrap(), socket_fd);
-                        }
-                    }
-                }
-            }
 
-            self.socket_file_descriptor.replace(None);
-        }
+impl AsFd for Socket{
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.socket_file_descriptor.as_fd()
     }
 }
 
 #[derive(Debug)]
 pub enum SockError{
-    InitializeError(i32),
+    ///Creating the socket failed.
+    InitializeError(CErr),
+    ///The socket path does not fit into `sockaddr_un`.
     PathTooLong,
-    ConnectError(i32),
-    SendError(i32),
-    SendIncomplete,
-    RecvError(i32),
-    BufError,
-    TimedOutRead,
+    ///The host name could not be resolved.
+    ResolveError(CErr),
+    ///The host name resolved, but not to an address of the requested family.
+    NoAddress,
+    ///Nothing is listening at the address.
+    ConnectionRefused(CErr),
+    ///Connecting failed for another reason.
+    ConnectError(CErr),
+    ///The server closed the connection or it was reset.
+    ConnectionClosed(CErr),
+    ///The server did not answer within the configured timeout.
+    TimedOut(CErr),
+    ///Descriptors were to be sent over a transport that cannot carry them.
+    FdPassingUnsupported,
     IncompleteRead,
     IncompleteWrite,
-    UnknownError
+    ///The bytes read do not decode as the value asked for.
+    InvalidData(ParseError),
+    ///Any other error reported by the operating system.
+    Io(CErr)
 }
 
 impl Display for SockError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "SockError[{:?}]", self)
+        match self {
+            SockError::InitializeError(_) => write!(f, "failed to create socket"),
+            SockError::PathTooLong => write!(f, "socket path is too long"),
+            SockError::ResolveError(_) => write!(f, "failed to resolve host name"),
+            SockError::NoAddress => write!(f, "host has no address of the requested family"),
+            SockError::ConnectionRefused(_) => write!(f, "connection refused"),
+            SockError::ConnectError(_) => write!(f, "failed to connect"),
+            SockError::ConnectionClosed(_) => write!(f, "connection closed"),
+            SockError::TimedOut(_) => write!(f, "timed out"),
+            SockError::FdPassingUnsupported => write!(f, "transport cannot pass file descriptors"),
+            SockError::IncompleteRead => write!(f, "incomplete read"),
+            SockError::IncompleteWrite => write!(f, "incomplete write"),
+            SockError::InvalidData(_) => write!(f, "received data could not be decoded"),
+            SockError::Io(_) => write!(f, "socket I/O failed")
+        }
+    }
+}
+
+impl Error for SockError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SockError::InitializeError(e) | SockError::ResolveError(e) | SockError::ConnectionRefused(e) |
+            SockError::ConnectError(e) | SockError::ConnectionClosed(e) | SockError::TimedOut(e) | SockError::Io(e) => Some(e),
+            SockError::InvalidData(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<ParseError> for SockError {
+    fn from(e: ParseError) -> Self {
+        SockError::InvalidData(e)
     }
 }
 
 impl From<CErr> for SockError {
+    ///Keeps the error and sorts it into the cases callers usually want to handle differently.
     fn from(e: CErr) -> Self {
         match e.kind() {
-            ErrorKind::InvalidInput => SockError::RecvError(CErr::last_os_error().raw_os_error().unwrap()),
-            ErrorKind::InvalidData => SockError::SendError(CErr::last_os_error().raw_os_error().unwrap()),
-            ErrorKind::OutOfMemory => SockError::BufError,
-            ErrorKind::TimedOut => SockError::TimedOutRead,
-            _ => SockError::UnknownError
+            ErrorKind::ConnectionRefused => SockError::ConnectionRefused(e),
+            ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => SockError::ConnectionClosed(e),
+            ErrorKind::TimedOut => SockError::TimedOut(e),
+            _ => SockError::Io(e)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::os::unix::net::UnixListener;
+
+    fn exchange<S: std::io::Read + std::io::Write>(socket: &mut Socket, peer: &mut S) {
+        socket.write_all(b"ping").unwrap();
+        socket.flush_all().unwrap();
+        let mut received = [0u8; 4];
+        peer.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"ping");
+
+        peer.write_all(b"pong").unwrap();
+        assert_eq!(socket.read_bytes(4).unwrap(), b"pong");
+    }
+
+    #[test]
+    fn unix_round_trip() {
+        let path = std::env::temp_dir().join(format!("xeagle-sock-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let mut socket = Socket::new(path.to_str().unwrap()).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        exchange(&mut socket, &mut peer);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn unix_abstract_round_trip() {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr as UnixAddr;
+
+        //Nothing exists at this path on disk, so only the abstract socket can answer.
+        let name = format!("/tmp/.xeagle-abstract-test-{}", std::process::id());
+        let listener = UnixListener::bind_addr(&UnixAddr::from_abstract_name(name.as_bytes()).unwrap()).unwrap();
+
+        let mut socket = Socket::new(&name).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        exchange(&mut socket, &mut peer);
+    }
+
+    #[test]
+    fn unix_missing_path() {
+        assert!(matches!(Socket::new("/nonexistent/xeagle/X0"), Err(SockError::ConnectError(e)) if e.raw_os_error() == Some(libc::ENOENT)));
+    }
+
+    fn unix_pair() -> (Socket, std::os::unix::net::UnixStream) {
+        let path = std::env::temp_dir().join(format!("xeagle-sock-pair-{}-{:?}", std::process::id(), std::thread::current().id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let socket = Socket::new(path.to_str().unwrap()).unwrap();
+        let (peer, _) = listener.accept().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (socket, peer)
+    }
+
+    #[test]
+    fn passes_fds_with_data() {
+        use std::os::fd::IntoRawFd;
+
+        let (mut socket, peer) = unix_pair();
+        let mut peer = Socket::from_fd(peer.into_raw_fd());
+        let (passed, mut kept) = std::os::unix::net::UnixStream::pair().unwrap();
+
+        socket.write_fds(vec![OwnedFd::from(passed)]);
+        socket.write_all(b"ping").unwrap();
+        socket.flush_all().unwrap();
+
+        assert_eq!(peer.read_bytes(4).unwrap(), b"ping");
+        let mut fds = peer.take_fds();
+        assert_eq!(fds.len(), 1);
+        assert!(peer.take_fds().is_empty());
+
+        //The received descriptor is the other end of `kept`.
+        let mut received = std::os::unix::net::UnixStream::from(fds.remove(0));
+        received.write_all(b"pong").unwrap();
+        let mut buf = [0u8; 4];
+        kept.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[test]
+    fn too_many_fds_break_the_read() {
+        use std::os::fd::IntoRawFd;
+
+        let (mut socket, peer) = unix_pair();
+        let mut peer = Socket::from_fd(peer.into_raw_fd());
+        let (passed, _kept) = std::os::unix::net::UnixStream::pair().unwrap();
+        let passed = OwnedFd::from(passed);
+
+        socket.write_fds((0..=MAX_FDS_PER_READ).map(|_| passed.try_clone().unwrap()).collect());
+        socket.write_all(b"ping").unwrap();
+        socket.flush_all().unwrap();
+
+        assert!(matches!(peer.read_bytes(4), Err(SockError::Io(e)) if e.kind() == ErrorKind::InvalidData));
+        assert!(peer.take_fds().is_empty());
+    }
+
+    #[test]
+    fn vectored_write_survives_full_socket_buffer() {
+        let (mut socket, mut peer) = unix_pair();
+        let header = [1u8, 2, 3];
+        let payload: Vec<u8> = (0..8 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let expected: Vec<u8> = b"buffered".iter().chain(&header).chain(&payload).chain(&[0u8; 1]).copied().collect();
+
+        let reader = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            peer.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        socket.write_all(b"buffered").unwrap();
+        socket.write_vectored_all(&[IoSlice::new(&header), IoSlice::new(&payload), IoSlice::new(&[0])]).unwrap();
+        assert_eq!(socket.len_write_buf(), 0);
+        drop(socket);
+
+        assert!(reader.join().unwrap() == expected);
+    }
+
+    #[test]
+    fn flush_survives_full_socket_buffer() {
+        let (mut socket, mut peer) = unix_pair();
+        let payload = vec![7u8; 4 * 1024 * 1024];
+
+        let reader = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            peer.read_to_end(&mut received).unwrap();
+            received.len()
+        });
+
+        socket.write_all(&payload).unwrap();
+        socket.flush_all().unwrap();
+        drop(socket);
+
+        assert_eq!(reader.join().unwrap(), payload.len());
+    }
+
+    #[test]
+    fn read_times_out_at_deadline() {
+        let (mut socket, _peer) = unix_pair();
+        socket.set_timeout(Some(Duration::from_millis(50)));
+
+        let start = Instant::now();
+        assert!(matches!(socket.read_bytes(4), Err(SockError::TimedOut(_))));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn read_waits_for_split_writes() {
+        let (mut socket, mut peer) = unix_pair();
+        socket.set_timeout(Some(Duration::from_secs(5)));
+
+        let writer = std::thread::spawn(move || {
+            peer.write_all(b"po").unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            peer.write_all(b"ng").unwrap();
+            peer
+        });
+
+        assert_eq!(socket.read_bytes(4).unwrap(), b"pong");
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn read_returns_what_has_arrived() {
+        let (mut socket, mut peer) = unix_pair();
+        socket.set_timeout(Some(Duration::from_millis(50)));
+        peer.write_all(b"po").unwrap();
+
+        let mut buf = [0u8; 4];
+        assert_eq!(Read::read(&mut socket, &mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"po");
+
+        //A field cut short still counts the bytes it took off the stream.
+        peer.write_all(b"n").unwrap();
+        assert!(matches!(socket.read_bytes(2), Err(SockError::TimedOut(_))));
+        assert_eq!(socket.len_read_buf(), 3);
+    }
+
+    #[test]
+    fn reads_fields_and_padding_of_any_length() {
+        let (mut socket, mut peer) = unix_pair();
+        peer.write_all(&7u32.to_le_bytes()).unwrap();
+        peer.write_all(&[0xAA; 149]).unwrap();
+        peer.write_all(&9u16.to_le_bytes()).unwrap();
+        peer.write_all(&[0; 1]).unwrap();
+
+        assert_eq!(socket.read_serializable::<u32>(4, ByteOrder::LsbFirst).unwrap(), 7);
+        socket.read_discard_bytes(149).unwrap();
+        assert_eq!(socket.read_serializable::<u16>(2, ByteOrder::LsbFirst).unwrap(), 9);
+        assert_eq!(socket.read_pad(4).unwrap(), 1);
+    }
+
+    #[test]
+    fn read_reports_closed_connection() {
+        let (mut socket, mut peer) = unix_pair();
+        peer.write_all(b"p").unwrap();
+        drop(peer);
+
+        assert!(matches!(socket.read_bytes(4), Err(SockError::ConnectionClosed(_))));
+    }
+
+    #[test]
+    fn write_reports_closed_connection() {
+        let (mut socket, peer) = unix_pair();
+        drop(peer);
+
+        socket.write_all(&[0; 64]).unwrap();
+        match socket.flush_all() {
+            Err(error @ SockError::ConnectionClosed(_)) => {
+                let source = error.source().unwrap().downcast_ref::<CErr>().unwrap();
+                assert_eq!(source.raw_os_error(), Some(libc::EPIPE));
+            }
+            other => panic!("expected a closed connection, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn writing_to_a_closed_connection_does_not_raise_sigpipe() {
+        let (mut socket, peer) = unix_pair();
+        drop(peer);
+
+        //The test harness ignores SIGPIPE, so the write happens in a child that does not.
+        match unsafe { libc::fork() } {
+            0 => unsafe {
+                libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+                let _ = socket.write_all(&[0; 64]);
+                let closed = matches!(socket.flush_all(), Err(SockError::ConnectionClosed(_)));
+                libc::_exit(if closed { 0 } else { 1 });
+            },
+            child => {
+                let mut status = 0;
+                assert_eq!(unsafe { libc::waitpid(child, &mut status, 0) }, child);
+                assert!(libc::WIFEXITED(status), "the child was killed by signal {}", libc::WTERMSIG(status));
+                assert_eq!(libc::WEXITSTATUS(status), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn read_all_does_not_wait() {
+        let (mut socket, mut peer) = unix_pair();
+        assert_eq!(socket.read_all().unwrap(), b"");
+
+        peer.write_all(b"pong").unwrap();
+        assert_eq!(socket.read_all().unwrap(), b"pong");
+    }
+
+    #[test]
+    fn tcp_round_trip_ipv4() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut socket = Socket::connect_tcp("127.0.0.1", port, Protocol::Inet).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        exchange(&mut socket, &mut peer);
+    }
+
+    #[test]
+    fn tcp_round_trip_ipv6() {
+        //Some build hosts have no IPv6 loopback at all, there is nothing to test there.
+        let Ok(listener) = TcpListener::bind("[::1]:0") else { return };
+        let port = listener.local_addr().unwrap().port();
+
+        let mut socket = Socket::connect_tcp("::1", port, Protocol::Tcp).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        exchange(&mut socket, &mut peer);
+    }
+
+    #[test]
+    fn tcp_protocol_filters_address_family() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(matches!(Socket::connect_tcp("127.0.0.1", port, Protocol::Inet6), Err(SockError::NoAddress)));
+    }
+
+    #[test]
+    fn tcp_connect_refused() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        assert!(matches!(Socket::connect_tcp("127.0.0.1", port, Protocol::Tcp), Err(SockError::ConnectionRefused(_))));
+    }
+}
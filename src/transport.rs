@@ -0,0 +1,198 @@
+//!The byte stream the X protocol runs over.
+use std::io::IoSlice;
+use std::net::SocketAddr;
+use std::os::fd::{OwnedFd, RawFd};
+use crate::serializable::{ByteOrder, Serializable};
+use crate::sock::{SockError, Socket};
+
+///A buffered, bidirectional byte stream to an X server.
+///
+///[`Socket`] is the implementation used for local and TCP displays. Anything else, such as an
+///in-memory pipe in tests or a custom tunnel, can be plugged into
+///[`XInterface::with_transport`](crate::xinterface::XInterface::with_transport) by implementing the
+///required methods. The `sock_read!` and `sock_write!` macros work on any transport.
+pub trait Transport: Send + Sized {
+    ///Appends `data` to the write buffer.
+    fn write_all(&mut self, data: &[u8]) -> Result<(), SockError>;
+
+    ///Sends everything in the write buffer.
+    fn flush_all(&mut self) -> Result<(), SockError>;
+
+    ///Reads into `buf` and returns the number of bytes read.
+    ///
+    ///When `block` is set this waits until at least one byte has arrived, otherwise it returns `0`
+    ///right away if there is nothing to read.
+    fn read_into(&mut self, buf: &mut [u8], block: bool) -> Result<usize, SockError>;
+
+    ///Opens a second handle on the same stream with its own buffers.
+    ///
+    ///One handle is used for writing and the other for reading, possibly from different threads.
+    fn try_clone(&self) -> Result<Self, SockError>;
+
+    ///Sends the write buffer followed by `bufs`, resuming after partial writes until everything is out.
+    ///
+    ///The default copies the slices into the write buffer and flushes it, transports that can
+    ///write from borrowed memory directly should override it.
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), SockError>{
+        for buf in bufs {
+            self.write_all(buf)?;
+        }
+        self.flush_all()
+    }
+
+    ///Forgets the bytes the `sock_read!` macro has read so far.
+    fn clear_read_buf(&mut self){}
+
+    ///Forgets the bytes the `sock_write!` macro has buffered so far.
+    fn clear_write_buf(&mut self){}
+
+    ///Queues descriptors to be passed along with the next flush.
+    ///
+    ///Transports that cannot carry descriptors keep this default, which refuses any.
+    fn write_fds(&mut self, fds: Vec<OwnedFd>) -> Result<(), SockError>{
+        if fds.is_empty() { Ok(()) } else { Err(SockError::FdPassingUnsupported) }
+    }
+
+    ///Takes every descriptor received so far, in the order they arrived.
+    fn take_fds(&mut self) -> Vec<OwnedFd>{
+        Vec::new()
+    }
+
+    ///The descriptor an outside event loop should watch for readability, if there is one.
+    fn poll_fd(&self) -> Option<RawFd>{
+        None
+    }
+
+    ///The address of the server, used to pick the `.Xauthority` entry.
+    ///
+    ///`None`, the default, stands for a connection to the local machine.
+    fn peer_address(&self) -> Result<Option<SocketAddr>, SockError>{
+        Ok(None)
+    }
+
+    ///The address of our end of the connection, which some authorization protocols encrypt.
+    ///
+    ///`None`, the default, stands for a local connection.
+    fn local_address(&self) -> Result<Option<SocketAddr>, SockError>{
+        Ok(None)
+    }
+
+    fn write_serializable<T>(&mut self, to_write: T, order: ByteOrder) -> Result<(), SockError> where T: Serializable{
+        self.write_all(&to_write.bytes(order))
+    }
+
+    fn write_discard_bytes(&mut self, len: usize) -> Result<(), SockError>{
+        let zeros = [0u8; 64];
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk = remaining.min(zeros.len());
+            self.write_all(&zeros[..chunk])?;
+            remaining -= chunk;
+        }
+        Ok(())
+    }
+
+    ///Blocks until exactly `num_bytes` bytes have been read.
+    fn read_bytes(&mut self, num_bytes: usize) -> Result<Vec<u8>, SockError>{
+        let mut ret = vec![0; num_bytes];
+        fill(self, &mut ret)?;
+        Ok(ret)
+    }
+
+    fn read_serializable<T>(&mut self, num_bytes: usize, order: ByteOrder) -> Result<T, SockError> where T: Serializable{
+        let mut field = [0u8; 64];
+        if num_bytes <= field.len() {
+            fill(self, &mut field[..num_bytes])?;
+            Ok(T::from_bytes(&field[..num_bytes], order)?.0)
+        }else{
+            Ok(T::from_bytes(&self.read_bytes(num_bytes)?, order)?.0)
+        }
+    }
+
+    fn read_discard_bytes(&mut self, num_bytes: usize) -> Result<(), SockError>{
+        let mut sink = [0u8; 64];
+        let mut remaining = num_bytes;
+        while remaining > 0 {
+            let chunk = remaining.min(sink.len());
+            fill(self, &mut sink[..chunk])?;
+            remaining -= chunk;
+        }
+        Ok(())
+    }
+}
+
+///Blocks until `buf` is full.
+fn fill<T: Transport>(transport: &mut T, buf: &mut [u8]) -> Result<(), SockError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        filled += transport.read_into(&mut buf[filled..], true)?;
+    }
+    Ok(())
+}
+
+impl Transport for Socket {
+    fn write_all(&mut self, data: &[u8]) -> Result<(), SockError> {
+        Socket::write_all(self, data)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<(), SockError> {
+        Socket::write_vectored_all(self, bufs)
+    }
+
+    fn write_discard_bytes(&mut self, len: usize) -> Result<(), SockError> {
+        Socket::write_discard_bytes(self, len)
+    }
+
+    fn flush_all(&mut self) -> Result<(), SockError> {
+        Socket::flush_all(self)
+    }
+
+    fn read_bytes(&mut self, num_bytes: usize) -> Result<Vec<u8>, SockError> {
+        Socket::read_bytes(self, num_bytes)
+    }
+
+    fn read_serializable<T>(&mut self, num_bytes: usize, order: ByteOrder) -> Result<T, SockError> where T: Serializable {
+        Socket::read_serializable(self, num_bytes, order)
+    }
+
+    fn read_discard_bytes(&mut self, num_bytes: usize) -> Result<(), SockError> {
+        Socket::read_discard_bytes(self, num_bytes)
+    }
+
+    fn read_into(&mut self, buf: &mut [u8], block: bool) -> Result<usize, SockError> {
+        Socket::read_into(self, buf, block)
+    }
+
+    fn try_clone(&self) -> Result<Self, SockError> {
+        Socket::try_clone(self)
+    }
+
+    fn clear_read_buf(&mut self) {
+        Socket::clear_read_buf(self)
+    }
+
+    fn clear_write_buf(&mut self) {
+        Socket::clear_write_buf(self)
+    }
+
+    fn write_fds(&mut self, fds: Vec<OwnedFd>) -> Result<(), SockError> {
+        Socket::write_fds(self, fds);
+        Ok(())
+    }
+
+    fn take_fds(&mut self) -> Vec<OwnedFd> {
+        Socket::take_fds(self)
+    }
+
+    fn poll_fd(&self) -> Option<RawFd> {
+        Some(std::os::fd::AsRawFd::as_raw_fd(self))
+    }
+
+    fn peer_address(&self) -> Result<Option<SocketAddr>, SockError> {
+        Socket::peer_address(self)
+    }
+
+    fn local_address(&self) -> Result<Option<SocketAddr>, SockError> {
+        Socket::local_address(self)
+    }
+}
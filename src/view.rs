@@ -0,0 +1,361 @@
+//!Borrowed views of replies and events that decode fields only when they are asked for.
+//!
+//!Replies and events are handed out as [`Packet`](crate::event::Packet)s that point into the
+//!receive buffer they were read into. The views here read their fields straight from those bytes,
+//!hand out large payloads such as image data as slices of them and walk lists one element at a
+//!time, so reading a property of several megabytes neither copies nor allocates.
+//!
+//!The names end in `View` to keep them apart from the owned types in
+//![`protocol`](crate::protocol), which decode everything up front.
+use std::marker::PhantomData;
+use crate::event::RawEvent;
+use crate::serializable::{ByteOrder, ParseError, Serializable};
+
+///Size of the fixed part of every reply and event.
+const HEADER_LEN: usize = 32;
+
+///Something that can be decoded from bytes it keeps borrowing.
+///
+///Every [`Serializable`] value is a view as well, copied out since it is small. Types like
+///[`StrView`] refer back to the bytes instead.
+pub trait View<'a>: Sized {
+    ///Decodes a value from the start of `bytes`, returning it with the number of bytes it took up.
+    fn view(bytes: &'a [u8], order: ByteOrder) -> Result<(Self, usize), ParseError>;
+}
+
+impl<'a, T: Serializable> View<'a> for T {
+    fn view(bytes: &'a [u8], order: ByteOrder) -> Result<(Self, usize), ParseError> {
+        T::from_bytes(bytes, order)
+    }
+}
+
+///A `STR`, a string of up to 255 bytes preceded by its length, as in the font and extension lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StrView<'a>(&'a [u8]);
+
+impl<'a> StrView<'a> {
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    ///The string decoded as Latin-1.
+    pub fn to_latin1(&self) -> String {
+        self.0.iter().map(|&b| b as char).collect()
+    }
+}
+
+impl<'a> View<'a> for StrView<'a> {
+    fn view(bytes: &'a [u8], _: ByteOrder) -> Result<(Self, usize), ParseError> {
+        let len = *bytes.first().ok_or(ParseError::InsufficientData {needed: 1, available: 0})? as usize;
+        let string = bytes.get(1..1 + len).ok_or(ParseError::InsufficientData {needed: 1 + len, available: bytes.len()})?;
+        Ok((StrView(string), 1 + len))
+    }
+}
+
+///A `LISTofX` whose elements are decoded one at a time while iterating.
+///
+///The list is checked once when it is created, so iterating never fails.
+pub struct ListView<'a, T> {
+    bytes: &'a [u8],
+    len: usize,
+    order: ByteOrder,
+    items: PhantomData<fn() -> T>
+}
+
+impl<'a, T: View<'a>> ListView<'a, T> {
+    ///Views the `len` elements at the start of `bytes`, returning the list and the bytes it covers.
+    pub fn new(bytes: &'a [u8], len: usize, order: ByteOrder) -> Result<(Self, usize), ParseError> {
+        let mut consumed = 0;
+        for _ in 0..len {
+            consumed += T::view(&bytes[consumed..], order)?.1;
+        }
+        Ok((ListView {bytes: &bytes[..consumed], len, order, items: PhantomData}, consumed))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    ///The bytes the elements are decoded from.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn iter(&self) -> ListIter<'a, T> {
+        ListIter {bytes: self.bytes, remaining: self.len, order: self.order, items: PhantomData}
+    }
+}
+
+impl<'a, T: View<'a>> IntoIterator for &ListView<'a, T> {
+    type Item = T;
+    type IntoIter = ListIter<'a, T>;
+
+    fn into_iter(self) -> ListIter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<'a, T: View<'a> + std::fmt::Debug> std::fmt::Debug for ListView<'a, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+///Iterator over the elements of a [`ListView`].
+pub struct ListIter<'a, T> {
+    bytes: &'a [u8],
+    remaining: usize,
+    order: ByteOrder,
+    items: PhantomData<fn() -> T>
+}
+
+impl<'a, T: View<'a>> Iterator for ListIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (item, consumed) = T::view(self.bytes, self.order).expect("the list was checked when it was created");
+        self.bytes = &self.bytes[consumed..];
+        self.remaining -= 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T: View<'a>> ExactSizeIterator for ListIter<'a, T> {}
+
+///Typed access to the fields of a reply or event by their offset.
+#[derive(Debug, Clone, Copy)]
+pub struct Fields<'a> {
+    bytes: &'a [u8],
+    order: ByteOrder
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(bytes: &'a [u8], order: ByteOrder) -> Self {
+        Self {bytes, order}
+    }
+
+    ///Decodes the value at `offset`.
+    pub fn get<T: View<'a>>(&self, offset: usize) -> Result<T, ParseError> {
+        let bytes = self.bytes.get(offset..).ok_or(ParseError::InsufficientData {needed: offset, available: self.bytes.len()})?;
+        Ok(T::view(bytes, self.order)?.0)
+    }
+
+    ///The `len` bytes at `offset`.
+    pub fn slice(&self, offset: usize, len: usize) -> Result<&'a [u8], ParseError> {
+        self.bytes.get(offset..offset + len).ok_or(ParseError::InsufficientData {needed: offset + len, available: self.bytes.len()})
+    }
+
+    ///The list of `len` elements at `offset`.
+    pub fn list<T: View<'a>>(&self, offset: usize, len: usize) -> Result<ListView<'a, T>, ParseError> {
+        let bytes = self.bytes.get(offset..).ok_or(ParseError::InsufficientData {needed: offset, available: self.bytes.len()})?;
+        Ok(ListView::new(bytes, len, self.order)?.0)
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn byte_order(&self) -> ByteOrder {
+        self.order
+    }
+}
+
+impl RawEvent {
+    ///Typed access to the fields of this event or error, without copying it.
+    pub fn fields(&self) -> Fields<'_> {
+        Fields::new(self.bytes(), self.byte_order())
+    }
+}
+
+///Checks the header of a reply and that it is as long as it says, and cuts off anything after it.
+fn reply_fields(reply: &[u8], order: ByteOrder) -> Result<Fields<'_>, ParseError> {
+    let fields = Fields::new(reply, order);
+    let length: u32 = fields.get(4)?;
+    Ok(Fields::new(fields.slice(0, HEADER_LEN + length as usize * 4)?, order))
+}
+
+///A `GetImage` reply, with the image data borrowed from the reply.
+#[derive(Debug, Clone, Copy)]
+pub struct GetImageView<'a> {
+    fields: Fields<'a>
+}
+
+impl<'a> GetImageView<'a> {
+    pub fn parse(reply: &'a [u8], order: ByteOrder) -> Result<Self, ParseError> {
+        Ok(Self {fields: reply_fields(reply, order)?})
+    }
+
+    pub fn depth(&self) -> u8 {
+        self.fields.as_bytes()[1]
+    }
+
+    ///The visual of the window, 0 for a pixmap.
+    pub fn visual(&self) -> u32 {
+        self.fields.get(8).expect("the header was checked")
+    }
+
+    ///The pixels, laid out as described by the setup and the requested format.
+    pub fn data(&self) -> &'a [u8] {
+        &self.fields.as_bytes()[HEADER_LEN..]
+    }
+}
+
+///A `GetProperty` reply, with the value borrowed from the reply.
+#[derive(Debug, Clone, Copy)]
+pub struct GetPropertyView<'a> {
+    fields: Fields<'a>,
+    value: &'a [u8]
+}
+
+impl<'a> GetPropertyView<'a> {
+    pub fn parse(reply: &'a [u8], order: ByteOrder) -> Result<Self, ParseError> {
+        let fields = reply_fields(reply, order)?;
+        let format = fields.as_bytes()[1];
+        if !matches!(format, 0 | 8 | 16 | 32) {
+            return Err(ParseError::InvalidValue(format!("property format {}", format)));
+        }
+        let value_len: u32 = fields.get(16)?;
+        let value = fields.slice(HEADER_LEN, value_len as usize * (format as usize / 8))?;
+        Ok(Self {fields, value})
+    }
+
+    ///Bits per element of the value, 8, 16 or 32, or 0 if the property does not exist.
+    pub fn format(&self) -> u8 {
+        self.fields.as_bytes()[1]
+    }
+
+    ///The atom naming the type of the value, `None` (0) if the property does not exist.
+    pub fn property_type(&self) -> u32 {
+        self.fields.get(8).expect("the header was checked")
+    }
+
+    ///How many bytes of the value are left beyond the part in this reply.
+    pub fn bytes_after(&self) -> u32 {
+        self.fields.get(12).expect("the header was checked")
+    }
+
+    ///The value as raw bytes, in the connection's byte order for formats 16 and 32.
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    ///The value as a list of elements of `T`, if its format matches their size.
+    pub fn value_as<T: Serializable>(&self) -> Option<ListView<'a, T>> {
+        if size_of::<T>() * 8 != self.format() as usize {
+            return None;
+        }
+        let len = self.value.len() / size_of::<T>();
+        ListView::new(self.value, len, self.fields.byte_order()).ok().map(|(list, _)| list)
+    }
+}
+
+///A `ListFonts` reply, whose names are read as they are iterated.
+#[derive(Debug)]
+pub struct ListFontsView<'a> {
+    names: ListView<'a, StrView<'a>>
+}
+
+impl<'a> ListFontsView<'a> {
+    pub fn parse(reply: &'a [u8], order: ByteOrder) -> Result<Self, ParseError> {
+        let fields = reply_fields(reply, order)?;
+        let count: u16 = fields.get(8)?;
+        Ok(Self {names: fields.list(HEADER_LEN, count as usize)?})
+    }
+
+    pub fn names(&self) -> &ListView<'a, StrView<'a>> {
+        &self.names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_reply(order: ByteOrder, byte1: u8, words: [u32; 5], data: &[u8]) -> Vec<u8> {
+        let mut reply = vec![1, byte1];
+        reply.extend(1u16.bytes(order));
+        reply.extend(((data.len() / 4) as u32).bytes(order));
+        for word in words {
+            reply.extend(word.bytes(order));
+        }
+        reply.resize(HEADER_LEN, 0);
+        reply.extend_from_slice(data);
+        reply
+    }
+
+    #[test]
+    fn views_property_values_in_both_orders() {
+        for order in [ByteOrder::LsbFirst, ByteOrder::MsbFirst] {
+            let value: Vec<u8> = [1u32, 2, 0xFFFF_FFFF].iter().flat_map(|v| v.bytes(order)).collect();
+            let reply = encode_reply(order, 32, [6, 0, 3, 0, 0], &value);
+            let property = GetPropertyView::parse(&reply, order).unwrap();
+            assert_eq!((property.format(), property.property_type(), property.bytes_after()), (32, 6, 0));
+            //The value is a slice of the reply itself.
+            assert!(std::ptr::eq(property.value().as_ptr(), reply[32..].as_ptr()));
+            let values = property.value_as::<u32>().unwrap();
+            assert_eq!(values.iter().collect::<Vec<_>>(), [1, 2, 0xFFFF_FFFF]);
+            assert_eq!(values.iter().len(), 3);
+            assert!(property.value_as::<u16>().is_none());
+        }
+    }
+
+    #[test]
+    fn views_padded_string_properties() {
+        let reply = encode_reply(ByteOrder::LsbFirst, 8, [31, 0, 5, 0, 0], b"xterm\0\0\0");
+        let property = GetPropertyView::parse(&reply, ByteOrder::LsbFirst).unwrap();
+        assert_eq!(property.value(), b"xterm");
+        assert_eq!(property.value_as::<u8>().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn views_image_data() {
+        let data = [0xAAu8; 64];
+        let reply = encode_reply(ByteOrder::MsbFirst, 24, [0x21, 0, 0, 0, 0], &data);
+        let image = GetImageView::parse(&reply, ByteOrder::MsbFirst).unwrap();
+        assert_eq!((image.depth(), image.visual(), image.data().len()), (24, 0x21, 64));
+        //The length says there is more data than arrived.
+        assert!(GetImageView::parse(&reply[..90], ByteOrder::MsbFirst).is_err());
+    }
+
+    #[test]
+    fn iterates_font_names_lazily() {
+        let names = b"\x05fixed\x06cursor\x00\x00\x00";
+        let reply = encode_reply(ByteOrder::LsbFirst, 0, [2, 0, 0, 0, 0], names);
+        let fonts = ListFontsView::parse(&reply, ByteOrder::LsbFirst).unwrap();
+        assert_eq!(fonts.names().len(), 2);
+        let mut iter = fonts.names().iter();
+        assert_eq!(iter.next().unwrap().as_bytes(), b"fixed");
+        assert_eq!(iter.next().unwrap().to_latin1(), "cursor");
+        assert!(iter.next().is_none());
+
+        //A count the data does not back up fails up front rather than while iterating.
+        let reply = encode_reply(ByteOrder::LsbFirst, 0, [3, 0, 0, 0, 0], b"\x05fixed\x02\x00");
+        assert!(ListFontsView::parse(&reply, ByteOrder::LsbFirst).is_err());
+    }
+
+    #[test]
+    fn reads_event_fields() {
+        let mut bytes = vec![0u8; 32];
+        bytes[0] = 12;
+        bytes[4..8].copy_from_slice(&0x0040_0001u32.to_be_bytes());
+        bytes[8..10].copy_from_slice(&640u16.to_be_bytes());
+        let event = RawEvent::new(bytes.into(), ByteOrder::MsbFirst);
+        let fields = event.fields();
+        assert_eq!(fields.get::<u32>(4), Ok(0x0040_0001));
+        assert_eq!(fields.get::<u16>(8), Ok(640));
+        assert_eq!(fields.list::<u16>(8, 2).unwrap().iter().collect::<Vec<_>>(), [640, 0]);
+        assert!(fields.get::<u32>(30).is_err());
+        assert!(fields.get::<u8>(40).is_err());
+    }
+}
@@ -0,0 +1,778 @@
+//! Reading and writing `~/.Xauthority` and choosing the entry that applies to a connection.
+//!
+//! The matching follows `XauGetBestAuthByAddr` from libXau, so the same cookie is picked as by
+//! libxcb and Xlib. Files are modified the way the `xauth` tool does it, under libXau's lock files
+//! and by atomically replacing the whole file, so both can safely be used side by side.
+use std::fs::{remove_file, rename, File, OpenOptions};
+use std::io::{BufWriter, Error, ErrorKind, Read};
+use std::net::SocketAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::des::Des;
+
+/// Name of the authorization protocol that sends a shared secret in the clear.
+pub const MIT_MAGIC_COOKIE_1: &[u8] = b"MIT-MAGIC-COOKIE-1";
+
+/// Name of the authorization protocol that proves knowledge of a DES key by encrypting a timestamp.
+pub const XDM_AUTHORIZATION_1: &[u8] = b"XDM-AUTHORIZATION-1";
+
+/// Length of the cookies generated by [`AuthEntry::generate_cookie`], the same as `xauth` uses.
+const COOKIE_LEN: usize = 16;
+
+/// The authorization protocols we can answer, most preferred first, in the same order as libxcb.
+pub(crate) const AUTH_NAMES: &[&[u8]] = &[XDM_AUTHORIZATION_1, MIT_MAGIC_COOKIE_1];
+
+/// Size of the XDM-AUTHORIZATION-1 block sent to the server, three DES blocks.
+const XDM_AUTH_LEN: usize = 24;
+
+/// Name and data of an authorization protocol, as sent in the connection setup.
+pub(crate) type AuthInfo = (Vec<u8>, Vec<u8>);
+
+
+/// A family describes how to interpret some bytes as an address in an `AuthEntry`.
+///
+/// Compared to the family used in X requests, this is a `u16` and not an `u8` since that's what
+/// is used in `~/.Xauthority` files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Family(u16);
+
+impl Family {
+    /// IPv4 connection to the server
+    pub const INTERNET: Self = Self(0);
+    /// DECnet
+    pub const DEC_NET: Self = Self(1);
+    /// Chaosnet connection
+    pub const CHAOS: Self = Self(2);
+    /// Family without predefined meaning, but interpreted by the server, for example a user name
+    pub const SERVER_INTERPRETED: Self = Self(5);
+    /// IPv6 connection to the server
+    pub const INTERNET6: Self = Self(6);
+    /// Wildcard matching any protocol family
+    pub const WILD: Self = Self(65535);
+    /// For local non-net authentication
+    pub const LOCAL: Self = Self(256);
+    /// Secure RPC netname of a user, such as `unix.1000@example.com`, used by `SUN-DES-1`
+    /// authentication
+    pub const NETNAME: Self = Self(254);
+    /// Kerberos 5 principal name
+    pub const KRB5_PRINCIPAL: Self = Self(253);
+    /// For local non-net authentication
+    pub const LOCAL_HOST: Self = Self(252);
+}
+
+impl From<u16> for Family {
+    fn from(value: u16) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Family> for u16 {
+    fn from(value: Family) -> Self {
+        value.0
+    }
+}
+
+/// A single entry of an `.Xauthority` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthEntry {
+    /// The protocol family to which the entry applies
+    pub family: Family,
+    /// The address of the peer in a family-specific format
+    pub address: Vec<u8>,
+    /// The display number
+    pub number: Vec<u8>,
+    /// The name of the authentication method to use for the X11 server described by the previous
+    /// fields.
+    pub name: Vec<u8>,
+    /// Extra data for the authentication method.
+    pub data: Vec<u8>,
+}
+
+impl AuthEntry {
+    /// Creates an `MIT-MAGIC-COOKIE-1` entry with a fresh random cookie for display `display`.
+    ///
+    /// For a server on this machine pass [`Family::LOCAL`] and [`hostname`] as the address.
+    pub fn generate_cookie(family: Family, address: &[u8], display: u16) -> Result<Self, Error> {
+        let mut data = vec![0; COOKIE_LEN];
+        File::open("/dev/urandom")?.read_exact(&mut data)?;
+        Ok(Self {family, address: address.to_vec(), number: display.to_string().into_bytes(), name: MIT_MAGIC_COOKIE_1.to_vec(), data})
+    }
+
+    /// The entry in the big-endian format of the file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        file::write_entry(&mut bytes, self).expect("writing to a Vec cannot fail");
+        bytes
+    }
+
+    /// Whether `self` and `other` describe the same display, regardless of the protocol.
+    fn same_display(&self, other: &AuthEntry) -> bool {
+        self.family == other.family && self.address == other.address && self.number == other.number
+    }
+}
+
+/// The contents of an `.Xauthority` file.
+///
+/// To modify a file that other programs may use at the same time, use [`XAuthority::update`],
+/// which holds the lock while it reads, changes and writes the file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XAuthority {
+    /// The entries in file order, which is also the order in which they are searched.
+    pub entries: Vec<AuthEntry>
+}
+
+impl XAuthority {
+    /// Reads the file at `path`. A file that does not exist has no entries.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match file::XAuthorityEntries::open(path) {
+            Ok(entries) => Ok(Self {entries: entries.collect::<Result<_, _>>()?}),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Replaces the file at `path` with these entries.
+    ///
+    /// The entries are written to a temporary file next to it, which is then renamed over `path`,
+    /// so readers see either the old or the new contents. A new file is only readable by its owner.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let temporary = with_suffix(path, "-n");
+        let result = (|| {
+            let file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&temporary)?;
+            let mut writer = BufWriter::new(file);
+            for entry in &self.entries {
+                file::write_entry(&mut writer, entry)?;
+            }
+            writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            rename(&temporary, path)
+        })();
+
+        if result.is_err() {
+            let _ = remove_file(&temporary);
+        }
+        result
+    }
+
+    /// Locks the file at `path`, loads it, lets `change` modify the entries and saves the result.
+    ///
+    /// Nothing is written if `change` fails.
+    pub fn update<R>(path: &Path, change: impl FnOnce(&mut XAuthority) -> Result<R, Error>) -> Result<R, Error> {
+        let _lock = XAuthorityLock::acquire(path, LOCK_RETRIES, LOCK_DEAD)?;
+        let mut authority = Self::load(path)?;
+        let result = change(&mut authority)?;
+        authority.save(path)?;
+        Ok(result)
+    }
+
+    /// Adds `entry`, replacing an entry for the same display and protocol.
+    ///
+    /// New entries go to the front, so they are preferred over any wildcard entries already there.
+    pub fn add(&mut self, entry: AuthEntry) {
+        match self.entries.iter_mut().find(|old| old.same_display(&entry) && old.name == entry.name) {
+            Some(old) => *old = entry,
+            None => self.entries.insert(0, entry)
+        }
+    }
+
+    /// Removes every entry for display `number` at `family` and `address` and returns how many there were.
+    pub fn remove(&mut self, family: Family, address: &[u8], number: &[u8]) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|entry| !(entry.family == family && entry.address == address && entry.number == number));
+        before - self.entries.len()
+    }
+
+    /// Adds all entries of `other`, which win over existing entries for the same display and protocol.
+    pub fn merge(&mut self, other: XAuthority) {
+        for entry in other.entries.into_iter().rev() {
+            self.add(entry);
+        }
+    }
+}
+
+/// How often [`XAuthority::update`] tries to take the lock, once per second, like `xauth`.
+const LOCK_RETRIES: u32 = 20;
+
+/// Lock files older than this are left over from a crashed process and are removed.
+const LOCK_DEAD: Duration = Duration::from_secs(2);
+
+/// Holds libXau's lock on an `.Xauthority` file and releases it when dropped.
+///
+/// Like `XauLockAuth`, the lock is taken by creating `<file>-c` and hard linking it to
+/// `<file>-l`. The link fails while another process holds the lock, even on file systems where
+/// exclusive creation is not reliable.
+#[derive(Debug)]
+pub struct XAuthorityLock {
+    create_name: PathBuf,
+    link_name: PathBuf
+}
+
+impl XAuthorityLock {
+    /// Locks the file at `path`, trying `retries` times a second apart.
+    ///
+    /// Lock files older than `dead` are considered stale and removed first. Fails with
+    /// [`ErrorKind::TimedOut`] if the lock is still held after the last try.
+    pub fn acquire(path: &Path, retries: u32, dead: Duration) -> Result<Self, Error> {
+        let create_name = with_suffix(path, "-c");
+        let link_name = with_suffix(path, "-l");
+
+        let stale = match create_name.metadata().and_then(|metadata| metadata.modified()) {
+            Ok(modified) => SystemTime::now().duration_since(modified).is_ok_and(|age| age >= dead),
+            Err(_) => false
+        };
+        if stale {
+            let _ = remove_file(&create_name);
+            let _ = remove_file(&link_name);
+        }
+
+        let mut created = false;
+        for attempt in 0..retries.max(1) {
+            if attempt > 0 {
+                sleep(Duration::from_secs(1));
+            }
+
+            if !created {
+                match OpenOptions::new().write(true).create_new(true).mode(0o600).open(&create_name) {
+                    Ok(_) => created = true,
+                    Err(e) if matches!(e.kind(), ErrorKind::AlreadyExists | ErrorKind::PermissionDenied) => continue,
+                    Err(e) => return Err(e)
+                }
+            }
+
+            match std::fs::hard_link(&create_name, &link_name) {
+                Ok(()) => return Ok(Self {create_name, link_name}),
+                //Someone removed our file as stale, start over.
+                Err(e) if e.kind() == ErrorKind::NotFound => created = false,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e)
+            }
+        }
+
+        Err(Error::new(ErrorKind::TimedOut, format!("{} is locked", path.display())))
+    }
+}
+
+impl Drop for XAuthorityLock {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.create_name);
+        let _ = remove_file(&self.link_name);
+    }
+}
+
+/// `path` with `suffix` appended to the file name, as libXau names its lock files.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    name.into()
+}
+
+/// The default location of the `.Xauthority` file, from `XAUTHORITY` or `HOME`.
+pub fn default_path() -> Option<PathBuf> {
+    file::get_xauthority_file_name()
+}
+
+mod file {
+    //! Code for actually reading and writing `~/.Xauthority`.
+
+    // use alloc::{vec, vec::Vec};
+    use std::env::var_os;
+    use std::fs::File;
+    use std::io::{BufReader, Error, ErrorKind, Read, Write};
+    use std::path::{Path, PathBuf};
+
+    use super::AuthEntry;
+
+    /// Read a single `u16` from an `~/.Xauthority` file.
+    ///
+    /// The file stores these entries in big endian.
+    fn read_u16<R: Read>(read: &mut R) -> Result<u16, Error> {
+        let mut buffer = [0; 2];
+        read.read_exact(&mut buffer)?;
+        Ok(u16::from_be_bytes(buffer))
+    }
+
+    /// Read a single "byte array" from an `~/.Xauthority` file.
+    ///
+    /// The file stores these as a length field followed by a number of bytes that contain the
+    /// actual data.
+    fn read_string<R: Read>(read: &mut R) -> Result<Vec<u8>, Error> {
+        let length = read_u16(read)?;
+        let mut result = vec![0; length.into()];
+        read.read_exact(&mut result[..])?;
+        Ok(result)
+    }
+
+    /// Read a single entry from an `~/.Xauthority` file.
+    ///
+    /// This function tries to return `Ok(None)` when the end of the file is reached. However, the
+    /// code also treats a single byte as 'end of file', because things were simpler to implement
+    /// like this.
+    fn read_entry<R: Read>(read: &mut R) -> Result<Option<AuthEntry>, Error> {
+        let family = match read_u16(read) {
+            Ok(family) => family,
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+            .into();
+        let address = read_string(read)?;
+        let number = read_string(read)?;
+        let name = read_string(read)?;
+        let data = read_string(read)?;
+        Ok(Some(AuthEntry {
+            family,
+            address,
+            number,
+            name,
+            data,
+        }))
+    }
+
+    /// Write a single `u16` to an `~/.Xauthority` file, in big endian.
+    fn write_u16<W: Write>(write: &mut W, value: u16) -> Result<(), Error> {
+        write.write_all(&value.to_be_bytes())
+    }
+
+    /// Write a single "byte array" to an `~/.Xauthority` file, prefixed by its length.
+    fn write_string<W: Write>(write: &mut W, string: &[u8]) -> Result<(), Error> {
+        let length = u16::try_from(string.len())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "Xauthority field longer than 65535 bytes"))?;
+        write_u16(write, length)?;
+        write.write_all(string)
+    }
+
+    /// Write a single entry to an `~/.Xauthority` file.
+    pub(crate) fn write_entry<W: Write>(write: &mut W, entry: &AuthEntry) -> Result<(), Error> {
+        write_u16(write, entry.family.into())?;
+        write_string(write, &entry.address)?;
+        write_string(write, &entry.number)?;
+        write_string(write, &entry.name)?;
+        write_string(write, &entry.data)
+    }
+
+    /// Get the file name for `~/.Xauthority` based on environment variables.
+    ///
+    /// The code in libXau contains a special case for Windows (looks like cygwin) that is not
+    /// handled here (yet?).
+    pub(crate) fn get_xauthority_file_name() -> Option<PathBuf> {
+        if let Some(name) = var_os("XAUTHORITY") {
+            return Some(name.into());
+        }
+        var_os("HOME").map(|prefix| {
+            let mut result = PathBuf::new();
+            result.push(prefix);
+            result.push(".Xauthority");
+            result
+        })
+    }
+
+    /// An iterator over the entries of an `.Xauthority` file
+    #[derive(Debug)]
+    pub(crate) struct XAuthorityEntries(BufReader<File>);
+
+    impl XAuthorityEntries {
+        /// Open the `.Xauthority` file at `path` for reading.
+        pub(crate) fn open(path: &Path) -> Result<XAuthorityEntries, Error> {
+            Ok(XAuthorityEntries(BufReader::new(File::open(path)?)))
+        }
+    }
+
+    impl Iterator for XAuthorityEntries {
+        type Item = Result<AuthEntry, Error>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            read_entry(&mut self.0).transpose()
+        }
+    }
+}
+
+/// Describes the client end of a connection the way the server does when it looks up an entry.
+///
+/// Local connections, including TCP over the loopback interface, are identified by the host name
+/// of this machine. IPv4 addresses mapped into IPv6 are reduced to plain IPv4, like libxcb does.
+pub(crate) fn peer_family_address(peer: Option<SocketAddr>) -> (Family, Vec<u8>) {
+    let ip = match peer {
+        Some(SocketAddr::V6(v6)) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => v4.into(),
+            None => v6.ip().to_owned().into()
+        },
+        Some(SocketAddr::V4(v4)) => v4.ip().to_owned().into(),
+        None => return (Family::LOCAL, hostname())
+    };
+
+    match ip {
+        ip if ip.is_loopback() => (Family::LOCAL, hostname()),
+        std::net::IpAddr::V4(v4) => (Family::INTERNET, v4.octets().to_vec()),
+        std::net::IpAddr::V6(v6) => (Family::INTERNET6, v6.octets().to_vec())
+    }
+}
+
+/// The host name of this machine, as stored in the address of `FamilyLocal` entries.
+pub fn hostname() -> Vec<u8> {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut _, name.len()) } != 0 {
+        return Vec::new();
+    }
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    name[..len].to_vec()
+}
+
+/// Looks up the authorization for display number `display` at `address` in `~/.Xauthority`.
+///
+/// Of the matching entries the one whose protocol comes first in `names` wins. `Ok(None)` means
+/// that no entry matched, that the file does not exist or that its location could not be
+/// determined, in which case the connection is attempted without authorization.
+pub(crate) fn get_auth(family: Family, address: &[u8], display: u16, names: &[&[u8]]) -> Result<Option<AuthInfo>, Error> {
+    match file::get_xauthority_file_name() {
+        Some(path) => get_auth_from(&path, family, address, display, names),
+        None => Ok(None)
+    }
+}
+
+/// Like [`get_auth`], but reads the `.Xauthority` file at `path`.
+fn get_auth_from(path: &Path, family: Family, address: &[u8], display: u16, names: &[&[u8]]) -> Result<Option<AuthInfo>, Error> {
+    let entries = match file::XAuthorityEntries::open(path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e)
+    };
+    let best = get_best_auth(entries, family, address, display.to_string().as_bytes(), names)?;
+    Ok(best.map(|entry| (entry.name, entry.data)))
+}
+
+/// The matching rules of `XauGetBestAuthByAddr`.
+///
+/// An entry matches when either family is `FamilyWild` or both family and address are equal, and
+/// when either display number is empty or both are equal. Entries for protocols not in `names`
+/// are skipped.
+fn get_best_auth<I>(entries: I, family: Family, address: &[u8], number: &[u8], names: &[&[u8]]) -> Result<Option<AuthEntry>, Error>
+where I: IntoIterator<Item = Result<AuthEntry, Error>> {
+    let mut best: Option<(usize, AuthEntry)> = None;
+
+    for entry in entries {
+        let entry = entry?;
+        let address_matches = family == Family::WILD || entry.family == Family::WILD
+            || (entry.family == family && entry.address == address);
+        let number_matches = number.is_empty() || entry.number.is_empty() || entry.number == number;
+        if !address_matches || !number_matches {
+            continue;
+        }
+
+        let Some(rank) = names.iter().position(|name| *name == entry.name) else { continue };
+        if best.as_ref().is_none_or(|(best_rank, _)| rank < *best_rank) {
+            best = Some((rank, entry));
+            if rank == 0 {
+                break;
+            }
+        }
+    }
+
+    Ok(best.map(|(_, entry)| entry))
+}
+
+/// Turns the data of an `.Xauthority` entry for protocol `name` into what is sent to the server.
+///
+/// `local` is the address of our end of the connection, `None` for local sockets. Cookies are sent
+/// as they are, XDM-AUTHORIZATION-1 encrypts the current time and our address with the key.
+pub(crate) fn authorization_data(name: &[u8], data: Vec<u8>, local: Option<SocketAddr>) -> Result<Vec<u8>, Error> {
+    if name != XDM_AUTHORIZATION_1 {
+        return Ok(data);
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or(0);
+    xdm_authorization(&data, xdm_client_address(local), now as u32)
+}
+
+/// Computes the XDM-AUTHORIZATION-1 block the way libxcb's `compute_auth` does.
+///
+/// `data` is the 16 byte entry, 8 bytes that are sent back encrypted followed by the DES key. The
+/// plain text is those 8 bytes, the 6 byte client address, the big-endian `time` and zero padding,
+/// which is then encrypted in CBC mode with an all zero IV.
+fn xdm_authorization(data: &[u8], address: [u8; 6], time: u32) -> Result<Vec<u8>, Error> {
+    if data.len() != 16 {
+        return Err(Error::new(ErrorKind::InvalidData, "XDM-AUTHORIZATION-1 data must be 16 bytes long"));
+    }
+
+    let mut plain = [0u8; XDM_AUTH_LEN];
+    plain[..8].copy_from_slice(&data[..8]);
+    plain[8..14].copy_from_slice(&address);
+    plain[14..18].copy_from_slice(&time.to_be_bytes());
+
+    let des = Des::new(xdm_des_key(data[8..16].try_into().unwrap()));
+    let mut previous = [0u8; 8];
+    let mut output = Vec::with_capacity(XDM_AUTH_LEN);
+    for block in plain.chunks_exact(8) {
+        let chained: [u8; 8] = std::array::from_fn(|i| block[i] ^ previous[i]);
+        previous = des.encrypt(chained);
+        output.extend_from_slice(&previous);
+    }
+    Ok(output)
+}
+
+/// Spreads the 56 key bits in the last 7 bytes of `key` over 8 bytes with odd parity, like
+/// `_XdmcpWrapperToOddParity` in libXdmcp. The first byte of an XDM key is unused.
+fn xdm_des_key(key: [u8; 8]) -> [u8; 8] {
+    let bits = u64::from_be_bytes(key);
+    std::array::from_fn(|i| {
+        let byte = (((bits >> (49 - 7 * i)) & 0x7F) << 1) as u8;
+        byte | byte.count_ones().is_multiple_of(2) as u8
+    })
+}
+
+/// The 4 byte address and 2 byte port that identify the client in XDM-AUTHORIZATION-1.
+///
+/// IPv6 cannot be expressed and is sent as zeroes, like Xlib and libxcb do. Local connections use
+/// a made up address that differs for each connection and the process id as port.
+fn xdm_client_address(local: Option<SocketAddr>) -> [u8; 6] {
+    static NONCE: AtomicU32 = AtomicU32::new(0);
+
+    let (address, port) = match local {
+        Some(SocketAddr::V4(v4)) => (v4.ip().octets(), v4.port()),
+        Some(SocketAddr::V6(v6)) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => (v4.octets(), v6.port()),
+            None => ([0; 4], 0)
+        },
+        None => ((u32::MAX - NONCE.fetch_add(1, Ordering::Relaxed)).to_be_bytes(), std::process::id() as u16)
+    };
+
+    let mut encoded = [0u8; 6];
+    encoded[..4].copy_from_slice(&address);
+    encoded[4..].copy_from_slice(&port.to_be_bytes());
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::path::PathBuf;
+    use file::XAuthorityEntries;
+
+    fn entry(family: Family, address: &[u8], number: &[u8], name: &[u8], data: &[u8]) -> AuthEntry {
+        AuthEntry {family, address: address.to_vec(), number: number.to_vec(), name: name.to_vec(), data: data.to_vec()}
+    }
+
+    /// Writes an `.Xauthority` file containing `entries`.
+    fn craft_file(test: &str, entries: &[AuthEntry]) -> PathBuf {
+        let file: Vec<u8> = entries.iter().flat_map(AuthEntry::to_bytes).collect();
+        let path = std::env::temp_dir().join(format!("xeagle-xauth-{}-{}", test, std::process::id()));
+        std::fs::write(&path, file).unwrap();
+        path
+    }
+
+    fn best(path: &Path, family: Family, address: &[u8], number: &[u8], names: &[&[u8]]) -> Option<Vec<u8>> {
+        let entries = XAuthorityEntries::open(path).unwrap();
+        get_best_auth(entries, family, address, number, names).unwrap().map(|entry| entry.data)
+    }
+
+    #[test]
+    fn matches_family_address_and_display_number() {
+        let path = craft_file("match", &[
+            entry(Family::LOCAL, b"otherhost", b"0", MIT_MAGIC_COOKIE_1, b"other host"),
+            entry(Family::LOCAL, b"myhost", b"1", MIT_MAGIC_COOKIE_1, b"display 1"),
+            entry(Family::LOCAL, b"myhost", b"0", MIT_MAGIC_COOKIE_1, b"display 0"),
+            entry(Family::INTERNET, &[10, 0, 0, 1], b"0", MIT_MAGIC_COOKIE_1, b"inet"),
+            entry(Family::INTERNET6, &Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1).octets(), b"0", MIT_MAGIC_COOKIE_1, b"inet6"),
+        ]);
+
+        assert_eq!(best(&path, Family::LOCAL, b"myhost", b"0", AUTH_NAMES), Some(b"display 0".to_vec()));
+        assert_eq!(best(&path, Family::LOCAL, b"myhost", b"1", AUTH_NAMES), Some(b"display 1".to_vec()));
+        assert_eq!(best(&path, Family::LOCAL, b"myhost", b"2", AUTH_NAMES), None);
+        assert_eq!(best(&path, Family::INTERNET, &[10, 0, 0, 1], b"0", AUTH_NAMES), Some(b"inet".to_vec()));
+        assert_eq!(best(&path, Family::INTERNET, &[10, 0, 0, 2], b"0", AUTH_NAMES), None);
+        let inet6 = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1).octets();
+        assert_eq!(best(&path, Family::INTERNET6, &inet6, b"0", AUTH_NAMES), Some(b"inet6".to_vec()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn wildcards_match_any_address_and_number() {
+        let path = craft_file("wild", &[
+            entry(Family::LOCAL, b"myhost", b"", MIT_MAGIC_COOKIE_1, b"any display"),
+            entry(Family::WILD, b"", b"7", MIT_MAGIC_COOKIE_1, b"any address"),
+        ]);
+
+        assert_eq!(best(&path, Family::LOCAL, b"myhost", b"3", AUTH_NAMES), Some(b"any display".to_vec()));
+        assert_eq!(best(&path, Family::INTERNET, &[192, 168, 0, 1], b"7", AUTH_NAMES), Some(b"any address".to_vec()));
+        assert_eq!(best(&path, Family::INTERNET, &[192, 168, 0, 1], b"8", AUTH_NAMES), None);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn prefers_protocols_in_the_given_order() {
+        let path = craft_file("order", &[
+            entry(Family::LOCAL, b"myhost", b"0", b"UNKNOWN-PROTOCOL", b"unknown"),
+            entry(Family::LOCAL, b"myhost", b"0", MIT_MAGIC_COOKIE_1, b"cookie"),
+            entry(Family::LOCAL, b"myhost", b"0", XDM_AUTHORIZATION_1, b"xdm"),
+        ]);
+
+        assert_eq!(best(&path, Family::LOCAL, b"myhost", b"0", &[XDM_AUTHORIZATION_1, MIT_MAGIC_COOKIE_1]), Some(b"xdm".to_vec()));
+        assert_eq!(best(&path, Family::LOCAL, b"myhost", b"0", &[MIT_MAGIC_COOKIE_1, XDM_AUTHORIZATION_1]), Some(b"cookie".to_vec()));
+        assert_eq!(best(&path, Family::LOCAL, b"myhost", b"0", &[b"UNSUPPORTED"]), None);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_files_are_an_error() {
+        let path = craft_file("truncated", &[entry(Family::LOCAL, b"myhost", b"0", MIT_MAGIC_COOKIE_1, b"cookie")]);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.truncate(bytes.len() - 3);
+        std::fs::write(&path, bytes).unwrap();
+
+        let entries = XAuthorityEntries::open(&path).unwrap();
+        assert!(get_best_auth(entries, Family::LOCAL, b"myhost", b"0", AUTH_NAMES).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_file_means_no_authorization() {
+        let path = std::env::temp_dir().join(format!("xeagle-xauth-missing-{}", std::process::id()));
+        assert!(matches!(get_auth_from(&path, Family::LOCAL, b"myhost", 0, AUTH_NAMES), Ok(None)));
+    }
+
+    #[test]
+    fn unreadable_file_is_an_error() {
+        //A directory can be opened, but reading from it fails.
+        let path = std::env::temp_dir().join(format!("xeagle-xauth-directory-{}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        assert!(get_auth_from(&path, Family::LOCAL, b"myhost", 0, AUTH_NAMES).is_err());
+        std::fs::remove_dir(path).unwrap();
+    }
+
+    #[test]
+    fn serializes_in_big_endian() {
+        let entry = entry(Family::LOCAL, b"host", b"12", MIT_MAGIC_COOKIE_1, &[0xAB, 0xCD]);
+        let mut expected = vec![1, 0, 0, 4];
+        expected.extend_from_slice(b"host");
+        expected.extend_from_slice(&[0, 2, b'1', b'2', 0, 18]);
+        expected.extend_from_slice(MIT_MAGIC_COOKIE_1);
+        expected.extend_from_slice(&[0, 2, 0xAB, 0xCD]);
+        assert_eq!(entry.to_bytes(), expected);
+    }
+
+    #[test]
+    fn generates_random_cookies() {
+        let first = AuthEntry::generate_cookie(Family::LOCAL, b"myhost", 5).unwrap();
+        let second = AuthEntry::generate_cookie(Family::LOCAL, b"myhost", 5).unwrap();
+        assert_eq!(first.name, MIT_MAGIC_COOKIE_1);
+        assert_eq!(first.number, b"5");
+        assert_eq!(first.data.len(), COOKIE_LEN);
+        assert_ne!(first.data, second.data);
+    }
+
+    #[test]
+    fn saves_and_loads_entries() {
+        let path = std::env::temp_dir().join(format!("xeagle-xauth-save-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(XAuthority::load(&path).unwrap(), XAuthority::default());
+
+        let authority = XAuthority {entries: vec![
+            entry(Family::LOCAL, b"myhost", b"0", MIT_MAGIC_COOKIE_1, b"first"),
+            entry(Family::WILD, b"", b"", b"OTHER", b""),
+        ]};
+        authority.save(&path).unwrap();
+        assert_eq!(XAuthority::load(&path).unwrap(), authority);
+        assert!(!with_suffix(&path, "-n").exists());
+        assert_eq!(best(&path, Family::LOCAL, b"myhost", b"0", AUTH_NAMES), Some(b"first".to_vec()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn adds_removes_and_merges() {
+        let mut authority = XAuthority::default();
+        authority.add(entry(Family::LOCAL, b"myhost", b"0", MIT_MAGIC_COOKIE_1, b"old"));
+        authority.add(entry(Family::LOCAL, b"myhost", b"1", MIT_MAGIC_COOKIE_1, b"one"));
+        authority.add(entry(Family::LOCAL, b"myhost", b"0", MIT_MAGIC_COOKIE_1, b"new"));
+        authority.add(entry(Family::LOCAL, b"myhost", b"0", XDM_AUTHORIZATION_1, b"xdm"));
+        let data: Vec<&[u8]> = authority.entries.iter().map(|entry| &entry.data[..]).collect();
+        assert_eq!(data, [&b"xdm"[..], b"one", b"new"]);
+
+        assert_eq!(authority.remove(Family::LOCAL, b"myhost", b"0"), 2);
+        assert_eq!(authority.remove(Family::LOCAL, b"myhost", b"0"), 0);
+
+        authority.merge(XAuthority {entries: vec![
+            entry(Family::LOCAL, b"myhost", b"1", MIT_MAGIC_COOKIE_1, b"merged one"),
+            entry(Family::LOCAL, b"myhost", b"2", MIT_MAGIC_COOKIE_1, b"two"),
+        ]});
+        let data: Vec<&[u8]> = authority.entries.iter().map(|entry| &entry.data[..]).collect();
+        assert_eq!(data, [&b"two"[..], b"merged one"]);
+    }
+
+    #[test]
+    fn update_holds_the_lock() {
+        let path = std::env::temp_dir().join(format!("xeagle-xauth-lock-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        XAuthority::update(&path, |authority| {
+            assert!(with_suffix(&path, "-c").exists() && with_suffix(&path, "-l").exists());
+            let error = XAuthorityLock::acquire(&path, 1, Duration::from_secs(60)).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::TimedOut);
+            authority.add(entry(Family::LOCAL, b"myhost", b"0", MIT_MAGIC_COOKIE_1, b"cookie"));
+            Ok(())
+        }).unwrap();
+
+        assert!(!with_suffix(&path, "-c").exists() && !with_suffix(&path, "-l").exists());
+        assert_eq!(XAuthority::load(&path).unwrap().entries.len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn removes_stale_locks() {
+        let path = std::env::temp_dir().join(format!("xeagle-xauth-stale-{}", std::process::id()));
+        std::fs::write(with_suffix(&path, "-c"), b"").unwrap();
+        std::fs::write(with_suffix(&path, "-l"), b"").unwrap();
+
+        let lock = XAuthorityLock::acquire(&path, 1, Duration::ZERO).unwrap();
+        drop(lock);
+        assert!(!with_suffix(&path, "-l").exists());
+    }
+
+    #[test]
+    fn expands_xdm_keys_to_odd_parity() {
+        assert_eq!(xdm_des_key([0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]), [0x10, 0x91, 0x8C, 0x68, 0x45, 0xAB, 0x98, 0xEF]);
+        assert_eq!(xdm_des_key([0xFF, 0, 0, 0, 0, 0, 0, 0]), [1; 8]);
+    }
+
+    #[test]
+    fn encrypts_xdm_authorization() {
+        //Checked against `openssl enc -des-cbc -iv 0` with the expanded key.
+        let data = [1, 2, 3, 4, 5, 6, 7, 8, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+        let address = xdm_client_address(Some((Ipv4Addr::new(192, 168, 1, 2), 0x1234).into()));
+        assert_eq!(address, [192, 168, 1, 2, 0x12, 0x34]);
+        assert_eq!(xdm_authorization(&data, address, 0x5F5E_1000).unwrap(), [
+            0x4C, 0x03, 0x13, 0xA3, 0x81, 0x84, 0x0E, 0x35, 0xBE, 0x6C, 0x12, 0xE6, 0x3F, 0x84, 0x29, 0x7B,
+            0xF5, 0x80, 0x76, 0xA4, 0xFA, 0x1C, 0x27, 0xEB
+        ]);
+        assert!(xdm_authorization(&data[..8], address, 0).is_err());
+    }
+
+    #[test]
+    fn encodes_xdm_client_addresses_per_family() {
+        let mapped = Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped();
+        assert_eq!(xdm_client_address(Some((mapped, 6001).into())), [10, 0, 0, 1, 0x17, 0x71]);
+        assert_eq!(xdm_client_address(Some((Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1), 6001).into())), [0; 6]);
+
+        let first = xdm_client_address(None);
+        let second = xdm_client_address(None);
+        assert_ne!(first[..4], second[..4]);
+        assert_eq!(first[4..], (std::process::id() as u16).to_be_bytes());
+    }
+
+    #[test]
+    fn cookies_are_sent_unchanged() {
+        assert_eq!(authorization_data(MIT_MAGIC_COOKIE_1, vec![1, 2, 3], None).unwrap(), [1, 2, 3]);
+        assert_eq!(authorization_data(XDM_AUTHORIZATION_1, vec![0; 16], None).unwrap().len(), XDM_AUTH_LEN);
+    }
+
+    #[test]
+    fn describes_peers_like_libxcb() {
+        let local = (Family::LOCAL, hostname());
+        assert_eq!(peer_family_address(None), local);
+        assert_eq!(peer_family_address(Some((Ipv4Addr::LOCALHOST, 6000).into())), local);
+        assert_eq!(peer_family_address(Some((Ipv6Addr::LOCALHOST, 6000).into())), local);
+        assert_eq!(peer_family_address(Some((Ipv4Addr::new(10, 1, 2, 3), 6000).into())), (Family::INTERNET, vec![10, 1, 2, 3]));
+        assert_eq!(peer_family_address(Some((Ipv4Addr::new(10, 1, 2, 3).to_ipv6_mapped(), 6000).into())), (Family::INTERNET, vec![10, 1, 2, 3]));
+        let v6 = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+        assert_eq!(peer_family_address(Some((v6, 6000).into())), (Family::INTERNET6, v6.octets().to_vec()));
+    }
+}
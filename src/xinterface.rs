@@ -1,19 +1,77 @@
 #[warn(unused_imports)]
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::error::Error;
 use std::fmt::{Display, Formatter};
-use crate::{sock_read, sock_write};
+use std::io::IoSlice;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::sync::{Condvar, Mutex, TryLockError};
+use crate::auth::{Authenticator, CookieAuthenticator};
+use crate::display::{ConnectAddress, DisplayError, DisplayName};
+use crate::buffer::ReadBuffer;
+use crate::cookie::{has_sequence, widen, Cookie, MAXIMUM_UNANSWERED};
+use crate::event::{Packet, RawEvent, REPLY};
+use crate::extension::{parse_query_extension_reply, query_extension_request, ExtensionInfo};
+use crate::protocol::xproto::GetInputFocusRequest;
+use crate::protocol::Request;
+use crate::request::{big_req_enable_request, frame, parse_big_req_enable_reply, BIG_REQUESTS};
+use crate::resource::{get_xid_range_request, parse_get_xid_range_reply, IdAllocator, XC_MISC};
+use crate::serializable::{padding_len, ByteOrder, ParseError, Serializable};
+use crate::setup::Setup;
 use crate::sock::{SockError, Socket};
+use crate::transport::Transport;
+use crate::xauth;
 
 #[derive(Debug)]
 pub enum XInterfaceError{
     SocketError(SockError),
+    DisplayError(DisplayError),
     AuthFailure(String),
     AuthRequested(String),
-    UnknownError
+    RequestError(RawEvent),
+    ///Every resource ID has been used and the server has no more to give.
+    ResourceIdsExhausted,
+    ///The `.Xauthority` file exists but could not be read.
+    AuthorityError(std::io::Error),
+    ///The server sent something the protocol does not allow at this point.
+    ProtocolViolation(String),
+    ///A request of `length` bytes is longer than the server accepts, `maximum` bytes.
+    RequestTooLong {length: usize, maximum: usize},
+    ///A request that cannot be sent as it is, such as one whose length is not a multiple of 4.
+    InvalidRequest(String),
+    ///A request belongs to an extension the server does not have.
+    MissingExtension(String),
+    ///An earlier request could not be written completely, which leaves the server and the sequence
+    ///numbers out of step for good.
+    ConnectionBroken
 }
 
 impl Display for XInterfaceError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "XInterfaceError[{:?}]", self)
+        match self {
+            XInterfaceError::SocketError(_) => write!(f, "connection to the X server failed"),
+            XInterfaceError::DisplayError(_) => write!(f, "invalid display"),
+            XInterfaceError::AuthFailure(reason) => write!(f, "X server refused the connection: {}", reason),
+            XInterfaceError::AuthRequested(reason) => write!(f, "X server requested further authentication: {}", reason),
+            XInterfaceError::RequestError(error) => write!(f, "request failed with X error {}", error.bytes()[1]),
+            XInterfaceError::ResourceIdsExhausted => write!(f, "no resource IDs left"),
+            XInterfaceError::AuthorityError(_) => write!(f, "failed to read the Xauthority file"),
+            XInterfaceError::ProtocolViolation(reason) => write!(f, "X protocol violation: {}", reason),
+            XInterfaceError::RequestTooLong {length, maximum} => write!(f, "request of {} bytes exceeds the maximum of {} bytes", length, maximum),
+            XInterfaceError::InvalidRequest(reason) => write!(f, "invalid request: {}", reason),
+            XInterfaceError::MissingExtension(name) => write!(f, "X server does not have the {} extension", name),
+            XInterfaceError::ConnectionBroken => write!(f, "connection broken by an incomplete request")
+        }
+    }
+}
+
+impl Error for XInterfaceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            XInterfaceError::SocketError(e) => Some(e),
+            XInterfaceError::DisplayError(e) => Some(e),
+            XInterfaceError::AuthorityError(e) => Some(e),
+            _ => None
+        }
     }
 }
 
@@ -23,11 +81,117 @@ impl From<SockError> for XInterfaceError{
     }
 }
 
-pub struct XInterface {
-    x_socket: Socket
+impl From<DisplayError> for XInterfaceError{
+    fn from(e: DisplayError) -> Self {
+        XInterfaceError::DisplayError(e)
+    }
+}
+
+impl From<ParseError> for XInterfaceError{
+    fn from(e: ParseError) -> Self {
+        XInterfaceError::ProtocolViolation(e.to_string())
+    }
+}
+
+///A reply together with the descriptors the server passed along with it.
+pub type ReplyWithFds = (Packet, Vec<OwnedFd>);
+
+///A connection to an X server.
+///
+///All methods take `&self`, so a single interface can be shared between threads through an `Arc`.
+///Requests are written atomically, and while one thread blocks on the socket any other thread can
+///wait for a reply or an event, which the reading thread hands over as it receives them.
+pub struct XInterface<T: Transport = Socket> {
+    display_name: DisplayName,
+    byte_order: ByteOrder,
+    setup: Setup,
+    ids: Mutex<IdAllocator>,
+    ///Held across the XC-MISC round trip, since the range the server reports is not reserved and
+    ///two threads asking at once would be told the same one.
+    id_refill: Mutex<()>,
+    ///Extensions that have been looked up, including the ones the server does not have.
+    extensions: Mutex<HashMap<Vec<u8>, Option<ExtensionInfo>>>,
+    ///The maximum request length in 4 byte units, once BIG-REQUESTS has been negotiated or found missing.
+    maximum_request_length: Mutex<Option<u32>>,
+    socket_fd: Option<RawFd>,
+    writer: Mutex<Writer<T>>,
+    reader: Mutex<Reader<T>>,
+    received: Mutex<Received>,
+    packet_received: Condvar
 }
 
-impl XInterface {
+///The writing half of the connection.
+struct Writer<T> {
+    x_socket: T,
+    ///The sequence number of the last request sent.
+    sequence: u64,
+    ///The sequence number of the last request sent that has a reply.
+    answered: u64,
+    ///Whether a write failed, possibly after part of a request went out.
+    broken: bool
+}
+
+///The reading half of the connection, owned by whichever thread is currently receiving.
+struct Reader<T> {
+    x_socket: T,
+    buffer: ReadBuffer,
+    ///A read that failed after packets had already been taken from the buffer, reported once
+    ///those have been handed out.
+    error: Option<SockError>
+}
+
+///Packets that have been received but not yet handed out.
+#[derive(Default)]
+struct Received {
+    events: VecDeque<RawEvent>,
+    ///Replies in the order they arrived, several for requests like `ListFontsWithInfo`.
+    replies: HashMap<u64, VecDeque<Result<ReplyWithFds, RawEvent>>>,
+    ///Sequence numbers of requests that have a reply, with the number of descriptors that come with
+    ///each.
+    ///
+    ///A request can be answered with any number of replies, so it is only known to be done once a
+    ///packet of a later request or an error in its place arrives.
+    awaiting_reply: BTreeMap<u64, usize>,
+    ///Requests whose replies or error nobody is going to pick up.
+    discarded: BTreeSet<u64>,
+    ///The sequence number of the last packet received, which the next one is widened from.
+    last_sequence: u64,
+    ///Descriptors received ahead of the reply they belong to.
+    fds: VecDeque<OwnedFd>
+}
+
+impl XInterface<Socket> {
+    ///Connects to the display named by the `DISPLAY` environment variable.
+    pub fn new() -> Result<Self, XInterfaceError> {
+        Self::connect(DisplayName::from_env()?)
+    }
+
+    ///Connects to the display with the given name, e.g. `:1` or `unix:99.0`.
+    pub fn with_display(display_name: &str) -> Result<Self, XInterfaceError> {
+        Self::connect(DisplayName::parse(display_name)?)
+    }
+
+    ///Connects to an already parsed display name.
+    pub fn connect(display_name: DisplayName) -> Result<Self, XInterfaceError> {
+        let sock_connector = Self::open_socket(&display_name)?;
+        Self::with_transport(sock_connector, display_name)
+    }
+
+    ///Connects to an already parsed display name, authorizing with `authenticator`.
+    pub fn connect_with_authenticator(display_name: DisplayName, authenticator: &mut dyn Authenticator) -> Result<Self, XInterfaceError> {
+        let sock_connector = Self::open_socket(&display_name)?;
+        Self::with_authenticator(sock_connector, display_name, authenticator)
+    }
+
+    fn open_socket(display_name: &DisplayName) -> Result<Socket, XInterfaceError> {
+        Ok(match display_name.address()? {
+            ConnectAddress::Unix(path) => Socket::new(&path.to_string_lossy())?,
+            ConnectAddress::Tcp{host, port, protocol} => Socket::connect_tcp(&host, port, protocol)?
+        })
+    }
+}
+
+impl<T: Transport> XInterface<T> {
     /*fn get_x_auth_name(){
         let x_os_var = var_os("XAUTHORITY");
         match x_os_var {
@@ -36,273 +200,1182 @@ impl XInterface {
         }
     }*/
 
-    fn auth_failure_read(&mut self) -> Result<String, XInterfaceError>{
-        let reason_len = self.x_socket.read_serializable::<u8>(1)?;
-        self.x_socket.read_serializable::<u16>(2)?; //Major version
-        self.x_socket.read_serializable::<u16>(2)?; //Minor version
-        let pad_size = self.x_socket.read_serializable::<u16>(2)?;
-        let reason = self.x_socket.read_serializable::<String>(reason_len as usize)?;
-        self.x_socket.read_discard_bytes((pad_size as usize * 4) - reason_len as usize)?;
+    fn auth_failure_read(x_socket: &mut T, order: ByteOrder) -> Result<String, XInterfaceError>{
+        let reason_len = x_socket.read_serializable::<u8>(1, order)?;
+        x_socket.read_serializable::<u16>(2, order)?; //Major version
+        x_socket.read_serializable::<u16>(2, order)?; //Minor version
+        let pad_size = x_socket.read_serializable::<u16>(2, order)?;
+        let reason = x_socket.read_serializable::<String>(reason_len as usize, order)?;
+        let padding = (pad_size as usize * 4).checked_sub(reason_len as usize)
+            .ok_or_else(|| XInterfaceError::ProtocolViolation("failure reason longer than the reply".to_string()))?;
+        x_socket.read_discard_bytes(padding)?;
 
         Ok(reason.trim().to_string())
     }
 
-    fn auth_requested_read(&mut self) -> Result<String, XInterfaceError>{ //I honestly have zero idea what, if anything, would lead to this path, but it is in the documentation.
-        self.x_socket.read_discard_bytes(5)?;
-        let reason_len = self.x_socket.read_serializable::<u16>(2)?;
-        let reason = self.x_socket.read_serializable::<String>(reason_len as usize)?;
+    ///Reads the data of a status 2 (Authenticate) reply, whose length is given in 4 byte units.
+    fn auth_requested_read(x_socket: &mut T, order: ByteOrder) -> Result<Vec<u8>, XInterfaceError>{
+        x_socket.read_discard_bytes(5)?;
+        let data_units = x_socket.read_serializable::<u16>(2, order)?;
+        Ok(x_socket.read_bytes(data_units as usize * 4)?)
+    }
 
-        Ok(reason.trim().to_string())
+    ///Reads the rest of a success reply, whose status byte has already been read, and parses it.
+    fn auth_success_read(x_socket: &mut T, order: ByteOrder) -> Result<Setup, XInterfaceError>{
+        let mut reply = vec![1];
+        reply.extend_from_slice(&x_socket.read_bytes(7)?);
+        let length = u16::from_bytes(&reply[6..8], order)?.0 as usize * 4;
+        reply.extend_from_slice(&x_socket.read_bytes(length)?);
+        Setup::parse(&reply, order)
+    }
+
+    fn send_authentication(x_socket: &mut T, order: ByteOrder, auth_name: &[u8], auth_data: Vec<u8>) -> Result<u8, XInterfaceError>{
+        x_socket.write_all(&[order.setup_byte(), 0])?;
+        x_socket.write_serializable(11u16, order)?; //Major version
+        x_socket.write_serializable(0u16, order)?;  //Minor version
+        x_socket.write_serializable(auth_name.len() as u16, order)?;
+        x_socket.write_serializable(auth_data.len() as u16, order)?;
+        x_socket.write_all(&[0, 0])?;
+        let (name_pad, data_pad) = (padding_len(auth_name.len()), padding_len(auth_data.len()));
+        x_socket.write_all(auth_name)?;
+        x_socket.write_discard_bytes(name_pad)?;
+        x_socket.write_all(&auth_data)?;
+        x_socket.write_discard_bytes(data_pad)?;
+        x_socket.flush_all()?;
+
+        Ok(x_socket.read_serializable::<u8>(1, order)?)
+    }
+
+    ///Performs the handshake over `transport`, which must already be connected to the server.
+    ///
+    ///`display_name` describes the display at the other end and is used to look up authorization.
+    ///Without a matching `.Xauthority` entry the connection is attempted without authorization.
+    pub fn with_transport(transport: T, display_name: DisplayName) -> Result<Self, XInterfaceError> {
+        let (family, address) = xauth::peer_family_address(transport.peer_address()?);
+        let (auth_name, auth_data) = xauth::get_auth(family, &address, display_name.display, xauth::AUTH_NAMES)
+            .map_err(XInterfaceError::AuthorityError)?
+            .unwrap_or_default();
+        let auth_data = xauth::authorization_data(&auth_name, auth_data, transport.local_address()?)
+            .map_err(XInterfaceError::AuthorityError)?;
+
+        Self::with_authenticator(transport, display_name, &mut CookieAuthenticator::new(auth_name, auth_data))
+    }
+
+    ///Performs the handshake over `transport` with an authorization protocol of the caller's choice.
+    ///
+    ///The `.Xauthority` file is not consulted.
+    pub fn with_authenticator(x_socket: T, display_name: DisplayName, authenticator: &mut dyn Authenticator) -> Result<Self, XInterfaceError> {
+        Self::with_byte_order(x_socket, display_name, authenticator, ByteOrder::native())
     }
 
-    #[allow(unused_variables)]
-    fn auth_success_read(mut self) -> Result<Self, XInterfaceError>{
-        //*****Preliminary Reads*****
-        //self.x_socket.read_discard_bytes(1)?;
-        sock_read!{self.x_socket; _:1, major_version[u16:2], minor_version[u16:2], pad_indicator[u16:2]};
-        println!("Major: {}, minor: {}, pad_indicator: {}", major_version, minor_version, pad_indicator);
-        //println!("Major Version from macro: {}", test_var);
-        /*self.x_socket.read_discard_bytes(1)?;
-        self.x_socket.read_serializable::<u16>(2)?; //Major version
-        self.x_socket.read_serializable::<u16>(2)?; //Minor version
-        let pad_indicator = self.x_socket.read_serializable::<u16>(2)?;              //8+2n+(v+p+m)/4
-        let release_number = self.x_socket.read_serializable::<u32>(4)?;
-        let resource_id_base = self.x_socket.read_serializable::<u32>(4)?;
-        let resource_id_mask = self.x_socket.read_serializable::<u32>(4)?;
-        let motion_buffer_size = self.x_socket.read_serializable::<u32>(4)?;
-        let vendor_len = self.x_socket.read_serializable::<u16>(2)?;                 //v
-        let maximum_request_length = self.x_socket.read_serializable::<u16>(2)?;
-        let screen_count = self.x_socket.read_serializable::<u8>(1)?;
-        let pixmap_format_count = self.x_socket.read_serializable::<u8>(1)?;         //n
-        let image_byte_order = self.x_socket.read_serializable::<u8>(1)?;
-        let bitmap_format_bit_order = self.x_socket.read_serializable::<u8>(1)?;
-        let bitmap_format_scanline_unit = self.x_socket.read_serializable::<u8>(1)?;
-        let bitmap_format_scanline_pad = self.x_socket.read_serializable::<u8>(1)?;
-        let min_keycode = self.x_socket.read_serializable::<u8>(1)?;
-        let max_keycode = self.x_socket.read_serializable::<u8>(1)?;
-        self.x_socket.read_discard_bytes(4)?;
-        let vendor = self.x_socket.read_serializable::<String>(vendor_len as usize)?;
-        let pad_len = self.x_socket.read_pad(4)?;                  //p
-        let mut pixmap_formats: Vec<Vec<u8>> = Vec::with_capacity(pixmap_format_count as usize);
-        for _ in 0..pixmap_format_count{
-            pixmap_formats.push(self.x_socket.read_bytes(8)?);
-        }
-        let _screens: Vec<Vec<u8>> = Vec::with_capacity(screen_count as usize);
-        for _ in 0..screen_count {
-
-        }*/
-        //let roots = ((((pad_indicator-8)-(2*pixmap_format_count as u16))*4) - vendor_len) - pad_len as u16;
-
-        Ok(self)
-    }
-
-    fn send_authentication(&mut self, auth_name: Vec<u8>, auth_data: Vec<u8>) -> Result<u8, XInterfaceError>{
-        self.x_socket.write_all(vec![0x6C, 0, 11, 0, 0, 0])?;
-        self.x_socket.write_serializable(auth_name.len() as u16)?;
-        self.x_socket.write_serializable(auth_data.len() as u16)?;
-        self.x_socket.write_all(vec![0, 0])?;
-        self.x_socket.write_all(auth_name)?;
-        self.x_socket.write_pad(4)?;
-        self.x_socket.write_all(auth_data)?;
-        self.x_socket.write_pad(4)?;
-        self.x_socket.flush_all()?;
-
-        Ok(self.x_socket.read_serializable::<u8>(1)?)
-    }
-
-    pub fn new(x_serv: &str) -> Result<Self, XInterfaceError> {
-        println!("Initializing X interface.");
-        let sock_connector = Socket::new(x_serv)?;
-        let mut x_interface = XInterface{x_socket: sock_connector};
-
-        let (auth_name, auth_data) = get_auth().unwrap();
-        //println!("Auth Name: {:?}, auth data: {:?}", u8_util::u8_to_str(&auth_name), u8_util::u8_to_str(&auth_data));
-
-        let auth_response = x_interface.send_authentication(auth_name, auth_data)?;
-
-        match auth_response{
-            0 => Err(XInterfaceError::AuthFailure(x_interface.auth_failure_read()?)),      //Failure
-            1 => Ok(x_interface.auth_success_read()?),                                     //Success
-            2 => Err(XInterfaceError::AuthRequested(x_interface.auth_requested_read()?)),  //Authentication Request
-            _ => Err(XInterfaceError::UnknownError)                                        //Cursed
+    ///Performs the handshake like [`XInterface::with_authenticator`], asking for a connection in `byte_order`.
+    ///
+    ///The other constructors use the order of this machine. Every request sent over the connection
+    ///has to be encoded in this order, and every reply and event comes back in it.
+    pub fn with_byte_order(mut x_socket: T, display_name: DisplayName, authenticator: &mut dyn Authenticator, byte_order: ByteOrder) -> Result<Self, XInterfaceError> {
+        let auth_data = authenticator.initial_data()?;
+        let mut auth_response = Self::send_authentication(&mut x_socket, byte_order, authenticator.name(), auth_data)?;
+
+        //Challenge based protocols go back and forth until the server makes up its mind.
+        while auth_response == 2 {
+            let challenge = Self::auth_requested_read(&mut x_socket, byte_order)?;
+            let Some(answer) = authenticator.respond(&challenge)? else {
+                let reason = String::from_utf8_lossy(&challenge);
+                return Err(XInterfaceError::AuthRequested(reason.trim_end_matches('\0').trim().to_string()));
+            };
+            x_socket.write_all(&answer)?;
+            x_socket.flush_all()?;
+            auth_response = x_socket.read_serializable::<u8>(1, byte_order)?;
         }
+
+        let setup = match auth_response{
+            0 => return Err(XInterfaceError::AuthFailure(Self::auth_failure_read(&mut x_socket, byte_order)?)),      //Failure
+            1 => Self::auth_success_read(&mut x_socket, byte_order)?,                                                //Success
+            status => return Err(XInterfaceError::ProtocolViolation(format!("unknown setup status {}", status)))
+        };
+
+        x_socket.clear_read_buf();
+        let write_socket = x_socket.try_clone()?;
+        Ok(XInterface{
+            display_name,
+            byte_order,
+            ids: Mutex::new(IdAllocator::new(setup.resource_id_base, setup.resource_id_mask)),
+            id_refill: Mutex::new(()),
+            setup,
+            extensions: Mutex::new(HashMap::new()),
+            maximum_request_length: Mutex::new(None),
+            socket_fd: x_socket.poll_fd(),
+            writer: Mutex::new(Writer{x_socket: write_socket, sequence: 0, answered: 0, broken: false}),
+            reader: Mutex::new(Reader{x_socket, buffer: ReadBuffer::new(byte_order), error: None}),
+            received: Mutex::new(Received::default()),
+            packet_received: Condvar::new()
+        })
     }
-}
 
-const MIT_MAGIC_COOKIE_1: &[u8] = b"MIT-MAGIC-COOKIE-1";
+    ///The display name this interface is connected to.
+    pub fn display_name(&self) -> &DisplayName {
+        &self.display_name
+    }
 
-/// A family describes how to interpret some bytes as an address in an `AuthEntry`.
-///
-/// Compared to [`super::protocol::xproto::Family`], this is a `u16` and not an `u8` since
-/// that's what is used in `~/.Xauthority` files.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct Family(u16);
-
-#[warn(dead_code)]
-impl Family {
-    /// IPv4 connection to the server
-    pub const INTERNET: Self = Self(0);
-    /// DECnet
-    pub const DEC_NET: Self = Self(1);
-    /// Chaosnet connection
-    pub const CHAOS: Self = Self(2);
-    /// Family without predefined meaning, but interpreted by the server, for example a user name
-    pub const SERVER_INTERPRETED: Self = Self(5);
-    /// IPv6 connection to the server
-    pub const INTERNET6: Self = Self(6);
-    /// Wildcard matching any protocol family
-    pub const WILD: Self = Self(65535);
-    /// For local non-net authentication
-    pub const LOCAL: Self = Self(256);
-    /// TODO: No idea what this means exactly
-    pub const NETNAME: Self = Self(254);
-    /// Kerberos 5 principal name
-    pub const KRB5_PRINCIPAL: Self = Self(253);
-    /// For local non-net authentication
-    pub const LOCAL_HOST: Self = Self(252);
-}
+    ///The byte order of the connection, in which requests have to be encoded.
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
 
-#[warn(dead_code)]
-impl From<u16> for Family {
-    fn from(value: u16) -> Self {
-        Self(value)
+    ///The descriptor an outside event loop should watch before calling
+    ///[`XInterface::poll_for_event`], `None` if the transport has none.
+    pub fn poll_fd(&self) -> Option<RawFd> {
+        self.socket_fd
     }
-}
 
-/// A single entry of an `.Xauthority` file.
-#[derive(Debug, Clone, PartialEq, Eq)]
-#[warn(dead_code)]
-pub(crate) struct AuthEntry {
-    /// The protocol family to which the entry applies
-    family: Family,
-    /// The address of the peer in a family-specific format
-    address: Vec<u8>,
-    /// The display number
-    number: Vec<u8>,
-    /// The name of the authentication method to use for the X11 server described by the previous
-    /// fields.
-    name: Vec<u8>,
-    /// Extra data for the authentication method.
-    data: Vec<u8>,
-}
+    ///What the server told about itself when it accepted the connection.
+    pub fn setup(&self) -> &Setup {
+        &self.setup
+    }
 
-#[warn(dead_code)]
-mod file {
-    //! Code for actually reading `~/.Xauthority`.
+    ///Looks up the extension called `name`, asking the server only the first time.
+    ///
+    ///Returns `None` if the server does not have the extension.
+    pub fn query_extension(&self, name: &[u8]) -> Result<Option<ExtensionInfo>, XInterfaceError> {
+        if let Some(info) = self.extensions.lock().unwrap().get(name) {
+            return Ok(*info);
+        }
+        let sequence = self.send_request(&query_extension_request(name, self.byte_order), true)?;
+        let info = parse_query_extension_reply(&self.wait_for_reply(sequence)?)?;
+        self.extensions.lock().unwrap().insert(name.to_vec(), info);
+        Ok(info)
+    }
 
-    // use alloc::{vec, vec::Vec};
-    use std::env::var_os;
-    use std::fs::File;
-    use std::io::{BufReader, Error, ErrorKind, Read};
-    use std::path::PathBuf;
+    ///The longest request the server accepts, in 4 byte units.
+    ///
+    ///The first call enables the BIG-REQUESTS extension if the server has it, which raises the
+    ///limit above the 65535 units of the setup. Requests that need it enable it on their own.
+    pub fn maximum_request_length(&self) -> Result<u32, XInterfaceError> {
+        let mut maximum = self.maximum_request_length.lock().unwrap();
+        if let Some(maximum) = *maximum {
+            return Ok(maximum);
+        }
 
-    use super::AuthEntry;
+        let negotiated = match self.query_extension(BIG_REQUESTS)? {
+            Some(big_requests) => {
+                let sequence = self.send_request(&big_req_enable_request(big_requests.major_opcode), true)?;
+                parse_big_req_enable_reply(&self.wait_for_reply(sequence)?, self.byte_order)
+                    .ok_or_else(|| XInterfaceError::ProtocolViolation("BigReqEnable reply too short".to_string()))?
+            }
+            None => self.setup.maximum_request_length as u32
+        };
+        *maximum = Some(negotiated);
+        Ok(negotiated)
+    }
 
-    /// Read a single `u16` from an `~/.Xauthority` file.
+    ///Picks an unused ID for a new window, pixmap, graphics context or other resource.
     ///
-    /// The file stores these entries in big endian.
-    fn read_u16<R: Read>(read: &mut R) -> Result<u16, Error> {
-        let mut buffer = [0; 2];
-        read.read_exact(&mut buffer)?;
-        Ok(u16::from_be_bytes(buffer))
+    ///When the range assigned in the setup is used up, the XC-MISC extension is asked for IDs
+    ///that have been freed on the server in the meantime.
+    pub fn generate_id(&self) -> Result<u32, XInterfaceError> {
+        if let Some(id) = self.ids.lock().unwrap().generate() {
+            return Ok(id);
+        }
+
+        //Other threads can keep freeing IDs while the server is asked, but only one thread asks.
+        let _refill = self.id_refill.lock().unwrap();
+        if let Some(id) = self.ids.lock().unwrap().generate() {
+            return Ok(id);
+        }
+        let xc_misc = self.query_extension(XC_MISC)?.ok_or(XInterfaceError::ResourceIdsExhausted)?;
+        let sequence = self.send_request(&get_xid_range_request(xc_misc.major_opcode), true)?;
+        let (start_id, count) = parse_get_xid_range_reply(&self.wait_for_reply(sequence)?, self.byte_order)
+            .ok_or_else(|| XInterfaceError::ProtocolViolation("GetXIDRange reply too short".to_string()))?;
+        let mut ids = self.ids.lock().unwrap();
+        if let Some(id) = ids.generate() {
+            return Ok(id);
+        }
+        if !ids.add_range(start_id, count) {
+            return Err(XInterfaceError::ResourceIdsExhausted);
+        }
+        ids.generate().ok_or(XInterfaceError::ResourceIdsExhausted)
+    }
+
+    ///Hands back the ID of a resource that has been destroyed, so it can be used again.
+    pub fn free_id(&self, id: u32) {
+        self.ids.lock().unwrap().free(id);
     }
 
-    /// Read a single "byte array" from an `~/.Xauthority` file.
+    ///The screen selected by the display name, used when the caller does not pick one.
+    pub fn default_screen(&self) -> usize {
+        self.display_name.screen as usize
+    }
+
+    ///Sends a request and returns the cookie to exchange for its reply.
+    ///
+    ///Requests of extensions look up the extension first, which fails with
+    ///[`XInterfaceError::MissingExtension`] if the server does not have it. Nothing is waited for
+    ///otherwise, so any number of requests can be sent before the first reply is asked for.
+    ///
+    ///Descriptors the request passes, as in MIT-SHM's `AttachFd`, are duplicated for the server,
+    ///the request keeps its own.
+    pub fn send<R: Request>(&self, request: &R) -> Result<Cookie<R::Reply>, XInterfaceError> {
+        let major_opcode = match R::EXTENSION {
+            Some(name) => self.query_extension(name)?
+                .ok_or_else(|| XInterfaceError::MissingExtension(String::from_utf8_lossy(name).into_owned()))?
+                .major_opcode,
+            None => R::OPCODE
+        };
+        let fds = request.fds().into_iter().map(|fd| fd.try_clone_to_owned().map_err(SockError::from)).collect::<Result<_, _>>()?;
+        let encoded = request.encode(major_opcode, self.byte_order);
+        let sequence = self.send_request_with_fds(&[IoSlice::new(&encoded)], fds, R::HAS_REPLY.then_some(R::REPLY_FDS))?;
+        Ok(Cookie::new(sequence, R::HAS_REPLY))
+    }
+
+    ///Blocks until the reply of the request behind `cookie` arrives and decodes it.
+    ///
+    ///Fails with [`XInterfaceError::RequestError`] if the server answered with an error instead.
+    ///Requests without a reply return at once, errors they cause arrive as events. Any further
+    ///replies are dropped, use [`XInterface::next_reply`] for requests answered with several.
+    pub fn reply<R: Serializable>(&self, cookie: Cookie<R>) -> Result<R, XInterfaceError> {
+        if !cookie.has_reply() {
+            return Ok(R::from_bytes(&[], self.byte_order)?.0);
+        }
+        let reply = self.wait_for_reply(cookie.sequence());
+        self.discard_reply(cookie);
+        Ok(R::from_bytes(&reply?, self.byte_order)?.0)
+    }
+
+    ///Like [`XInterface::reply`], but also returns the descriptors the server passed along with the
+    ///reply, as DRI3's `Open` does.
+    pub fn reply_with_fds<R: Serializable>(&self, cookie: Cookie<R>) -> Result<(R, Vec<OwnedFd>), XInterfaceError> {
+        if !cookie.has_reply() {
+            return Ok((R::from_bytes(&[], self.byte_order)?.0, Vec::new()));
+        }
+        let reply = self.wait_for_reply_with_fds(cookie.sequence());
+        self.discard_reply(cookie);
+        let (reply, fds) = reply?;
+        Ok((R::from_bytes(&reply, self.byte_order)?.0, fds))
+    }
+
+    ///Blocks until the next reply of a request that is answered with a series of them, such as
+    ///`ListFontsWithInfo` or RECORD's `EnableContext`, and decodes it.
     ///
-    /// The file stores these as a length field followed by a number of bytes that contain the
-    /// actual data.
-    fn read_string<R: Read>(read: &mut R) -> Result<Vec<u8>, Error> {
-        let length = read_u16(read)?;
-        let mut result = vec![0; length.into()];
-        read.read_exact(&mut result[..])?;
-        Ok(result)
+    ///The last reply of the series is marked in a way that depends on the request. Once it has been
+    ///taken the cookie can be dropped, or handed to [`XInterface::discard_reply`] to stop early.
+    pub fn next_reply<R: Serializable>(&self, cookie: &Cookie<R>) -> Result<R, XInterfaceError> {
+        let reply = self.wait_for_reply(cookie.sequence())?;
+        Ok(R::from_bytes(&reply, self.byte_order)?.0)
     }
 
-    /// Read a single entry from an `~/.Xauthority` file.
+    ///Gives up on the replies of the request behind `cookie`, which are dropped when they arrive.
     ///
-    /// This function tries to return `Ok(None)` when the end of the file is reached. However, the
-    /// code also treats a single byte as 'end of file', because things were simpler to implement
-    /// like this.
-    fn read_entry<R: Read>(read: &mut R) -> Result<Option<AuthEntry>, Error> {
-        let family = match read_u16(read) {
-            Ok(family) => family,
-            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
-            Err(e) => return Err(e),
-        }
-            .into();
-        let address = read_string(read)?;
-        let number = read_string(read)?;
-        let name = read_string(read)?;
-        let data = read_string(read)?;
-        Ok(Some(AuthEntry {
-            family,
-            address,
-            number,
-            name,
-            data,
-        }))
-    }
-
-    /// Get the file name for `~/.Xauthority` based on environment variables.
+    ///An error in their place is dropped as well.
+    pub fn discard_reply<R>(&self, cookie: Cookie<R>) {
+        let mut received = self.received.lock().unwrap();
+        received.replies.remove(&cookie.sequence());
+        if received.awaiting_reply.contains_key(&cookie.sequence()) {
+            received.discarded.insert(cookie.sequence());
+        }
+    }
+
+    ///Sends one complete request, including its header, and returns its sequence number.
     ///
-    /// The code in libXau contains a special case for Windows (looks like cygwin) that is not
-    /// handled here (yet?).
-    fn get_xauthority_file_name() -> Option<PathBuf> {
-        if let Some(name) = var_os("XAUTHORITY") {
-            return Some(name.into());
-        }
-        var_os("HOME").map(|prefix| {
-            let mut result = PathBuf::new();
-            result.push(prefix);
-            result.push(".Xauthority");
-            result
+    ///Multi-byte fields must be encoded in [`XInterface::byte_order`]. The length in the header is
+    ///filled in from the size of `request`, switching to the
+    ///BIG-REQUESTS encoding for requests over 65535 units. Requests longer than the server accepts
+    ///fail with [`XInterfaceError::RequestTooLong`] before anything is written.
+    ///
+    ///The request is written in one piece, so requests sent concurrently from several threads never
+    ///interleave. Set `has_reply` for requests the server answers, so that an error in place of the
+    ///reply is handed to [`XInterface::wait_for_reply`] instead of being queued as an event.
+    pub fn send_request(&self, request: &[u8], has_reply: bool) -> Result<u64, XInterfaceError> {
+        self.send_request_vectored(&[IoSlice::new(request)], has_reply)
+    }
+
+    ///Sends a request made up of several borrowed parts, typically header, payload and [`padding`].
+    ///
+    ///The parts are written straight from the caller's memory, so large payloads such as image
+    ///data are never copied.
+    pub fn send_request_vectored(&self, request: &[IoSlice<'_>], has_reply: bool) -> Result<u64, XInterfaceError> {
+        self.send_request_with_fds(request, Vec::new(), has_reply.then_some(0))
+    }
+
+    ///Sends a request that passes `fds` to the server, as used by MIT-SHM, Present and DRI3.
+    ///
+    ///`reply_fds` is `None` for requests without a reply, otherwise the number of descriptors the
+    ///server sends back with the reply. Descriptor passing only works over local sockets.
+    pub fn send_request_with_fds(&self, request: &[IoSlice<'_>], fds: Vec<OwnedFd>, reply_fds: Option<usize>) -> Result<u64, XInterfaceError> {
+        //Negotiating BIG-REQUESTS sends short requests of its own, so it has to happen before the writer is locked.
+        let units = request.iter().map(|slice| slice.len()).sum::<usize>() / 4;
+        let maximum = if units <= self.setup.maximum_request_length as usize {
+            self.setup.maximum_request_length as u32
+        }else {
+            self.maximum_request_length()?
+        };
+        let (header, rest) = frame(request, maximum, self.byte_order)?;
+        let request: Vec<IoSlice<'_>> = std::iter::once(IoSlice::new(&header)).chain(rest).collect();
+
+        let mut writer = self.writer.lock().unwrap();
+        if writer.broken {
+            return Err(XInterfaceError::ConnectionBroken);
+        }
+        if reply_fds.is_none() && writer.sequence - writer.answered >= MAXIMUM_UNANSWERED {
+            self.sync(&mut writer)?;
+        }
+        let sequence = writer.sequence + 1;
+        writer.x_socket.write_fds(fds)?;
+        //The reply can arrive before the write returns, so it has to be expected before.
+        if let Some(reply_fds) = reply_fds {
+            self.received.lock().unwrap().awaiting_reply.insert(sequence, reply_fds);
+        }
+        if let Err(e) = Self::write(&mut writer, &request) {
+            self.received.lock().unwrap().awaiting_reply.remove(&sequence);
+            return Err(e);
+        }
+
+        writer.sequence = sequence;
+        if reply_fds.is_some() {
+            writer.answered = sequence;
+        }
+        Ok(sequence)
+    }
+
+    ///Sends a request whose reply is dropped, so the server sends back a sequence number before
+    ///the 16 bits it has for it wrap around.
+    fn sync(&self, writer: &mut Writer<T>) -> Result<(), XInterfaceError> {
+        let mut request = GetInputFocusRequest.encode(GetInputFocusRequest::OPCODE, self.byte_order);
+        request[2..4].copy_from_slice(&1u16.bytes(self.byte_order));
+        let sequence = writer.sequence + 1;
+        self.received.lock().unwrap().discarded.insert(sequence);
+        if let Err(e) = Self::write(writer, &[IoSlice::new(&request)]) {
+            self.received.lock().unwrap().discarded.remove(&sequence);
+            return Err(e);
+        }
+
+        writer.sequence = sequence;
+        writer.answered = sequence;
+        Ok(())
+    }
+
+    ///Writes a framed request, marking the connection as broken if that fails.
+    ///
+    ///There is no telling how much of the request went out before the failure, so the server may
+    ///have counted it or be waiting for the rest of it, and no later request can be sent.
+    fn write(writer: &mut Writer<T>, request: &[IoSlice<'_>]) -> Result<(), XInterfaceError> {
+        writer.x_socket.write_vectored(request).map_err(|e| {
+            writer.broken = true;
+            XInterfaceError::from(e)
         })
     }
 
-    /// An iterator over the entries of an `.Xauthority` file
-    #[derive(Debug)]
-    pub(crate) struct XAuthorityEntries(BufReader<File>);
+    ///Blocks until the reply to the request with the given sequence number arrives.
+    ///
+    ///The request must have been sent with `has_reply` set, otherwise this waits forever. Descriptors
+    ///that came with the reply are closed, use [`XInterface::wait_for_reply_with_fds`] to keep them.
+    pub fn wait_for_reply(&self, sequence: u64) -> Result<Packet, XInterfaceError> {
+        Ok(self.wait_for_reply_with_fds(sequence)?.0)
+    }
+
+    ///Blocks until the reply to the request with the given sequence number arrives and returns it
+    ///together with the descriptors the server passed along.
+    pub fn wait_for_reply_with_fds(&self, sequence: u64) -> Result<ReplyWithFds, XInterfaceError> {
+        let reply = self.receive(true, |received| {
+            let queue = received.replies.get_mut(&sequence)?;
+            let reply = queue.pop_front();
+            if queue.is_empty() {
+                received.replies.remove(&sequence);
+            }
+            reply
+        })?;
+        match reply {
+            Some(Ok(reply)) => Ok(reply),
+            Some(Err(error)) => Err(XInterfaceError::RequestError(error)),
+            None => unreachable!("a blocking receive always produces a reply")
+        }
+    }
+
+    ///Blocks until the next event or error not claimed by [`XInterface::wait_for_reply`] arrives.
+    pub fn wait_for_event(&self) -> Result<RawEvent, XInterfaceError> {
+        let event = self.receive(true, |received| received.events.pop_front())?;
+        Ok(event.expect("a blocking receive always produces an event"))
+    }
+
+    ///Returns the next event or error the server has already sent, without waiting for one.
+    ///
+    ///Meant to be called when an outside event loop reports the connection's file descriptor as
+    ///readable. Everything available is drained from the socket, so keep calling until this returns
+    ///`None` before going back to sleep. If another thread is currently reading, this returns `None`
+    ///right away and that thread queues whatever it receives.
+    pub fn poll_for_event(&self) -> Result<Option<RawEvent>, XInterfaceError> {
+        self.receive(false, |received| received.events.pop_front())
+    }
+
+    ///Looks for a packet with `take`, reading from the socket until it shows up.
+    ///
+    ///Only one thread reads at a time. The others sleep until it has sorted what it read into
+    ///`received`, then look again and take over reading if the packet they want is still missing.
+    fn receive<R>(&self, block: bool, mut take: impl FnMut(&mut Received) -> Option<R>) -> Result<Option<R>, XInterfaceError> {
+        let mut received = self.received.lock().unwrap();
+        loop {
+            if let Some(found) = take(&mut received) {
+                return Ok(Some(found));
+            }
+
+            let mut reader = match self.reader.try_lock() {
+                Ok(reader) => reader,
+                Err(TryLockError::WouldBlock) if block => {
+                    received = self.packet_received.wait(received).unwrap();
+                    continue;
+                }
+                Err(TryLockError::WouldBlock) => return Ok(None),
+                Err(TryLockError::Poisoned(e)) => panic!("{}", e)
+            };
+
+            drop(received);
+            let packets = reader.read_packets(block);
+            let fds = reader.x_socket.take_fds();
+            received = self.received.lock().unwrap();
+            //Wake the others while still holding the reader, so none of them can miss the wakeup.
+            self.packet_received.notify_all();
+            drop(reader);
+
+            received.fds.extend(fds);
+            for packet in packets? {
+                received.sort(packet, self.byte_order);
+            }
+
+            if !block {
+                return Ok(take(&mut received));
+            }
+        }
+    }
+}
+
+impl<T: Transport> Reader<T> {
+    ///Reads everything available and returns the complete packets.
+    ///
+    ///When blocking, this waits until at least one packet is complete. If reading fails once there
+    ///are packets, such as when the server sends a last error and closes the connection, they are
+    ///returned and the failure is reported by the next call.
+    fn read_packets(&mut self, block: bool) -> Result<Vec<Packet>, SockError> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        let mut packets = Vec::new();
+        loop {
+            while let Some(packet) = self.buffer.next_packet() {
+                packets.push(packet);
+            }
 
-    impl XAuthorityEntries {
-        /// Open `~/.Xauthority` for reading.
-        ///
-        /// This function returns `Ok(None)` when the location of the `.Xauthority` file could not
-        /// be determined. If opening the file failed (for example, because it does not exist),
-        /// that error is returned.
-        pub(crate) fn new() -> Result<Option<XAuthorityEntries>, Error> {
-            get_xauthority_file_name()
-                .map(File::open)
-                .transpose()?
-                // At this point we have Option<File> and errors while opening the file were
-                // returned to the caller.
-                .map(|file| Ok(XAuthorityEntries(BufReader::new(file))))
-                .transpose()
+            let wait = block && packets.is_empty();
+            let bytes_read = match self.x_socket.read_into(self.buffer.free_space(), wait) {
+                Ok(bytes_read) => bytes_read,
+                Err(e) if packets.is_empty() => return Err(e),
+                Err(e) => {
+                    self.error = Some(e);
+                    return Ok(packets);
+                }
+            };
+            self.buffer.commit(bytes_read);
+
+            if bytes_read == 0 {
+                return Ok(packets);
+            }
         }
     }
+}
 
-    impl Iterator for XAuthorityEntries {
-        type Item = Result<AuthEntry, Error>;
+impl Received {
+    ///Files a packet under the reply it answers or in the event queue.
+    fn sort(&mut self, packet: Packet, order: ByteOrder) {
+        let event = RawEvent::new(packet, order);
+        if has_sequence(event.response_type()) {
+            self.last_sequence = widen(self.last_sequence, event.wire_sequence());
+        }
+        let event = event.with_sequence(self.last_sequence);
+        let sequence = event.sequence();
+        //The server answers in order, so every request before this one has been dealt with.
+        self.awaiting_reply = self.awaiting_reply.split_off(&sequence);
+        self.discarded = self.discarded.split_off(&sequence);
 
-        fn next(&mut self) -> Option<Self::Item> {
-            read_entry(&mut self.0).transpose()
+        if event.response_type() == REPLY {
+            let reply_fds = self.awaiting_reply.get(&sequence).copied().unwrap_or(0).min(self.fds.len());
+            let fds = self.fds.drain(..reply_fds).collect();
+            if !self.discarded.contains(&sequence) {
+                self.replies.entry(sequence).or_default().push_back(Ok((event.into_bytes(), fds)));
+            }
+        }else if event.is_error() && self.awaiting_reply.remove(&sequence).is_some() {
+            if !self.discarded.remove(&sequence) {
+                self.replies.entry(sequence).or_default().push_back(Err(event));
+            }
+        }else{
+            self.events.push_back(event);
         }
     }
 }
 
-pub(crate) fn get_auth() -> Result<(Vec<u8>,Vec<u8>), u32>
-{
-    use file::XAuthorityEntries;
-    let mut auth_proto_name = Vec::with_capacity(16);
-    let mut auth_proto_data = Vec::with_capacity(16);
-    let entries = XAuthorityEntries::new().unwrap().unwrap();
-    for entry in entries {
-        if entry.is_err() { continue; }
-        let entry = entry.unwrap();
-        if entry.name == MIT_MAGIC_COOKIE_1 {
-            auth_proto_name.extend_from_slice(&entry.name);
-            auth_proto_data.extend_from_slice(&entry.data);
-            break;
-        }
-    }
-    Ok((auth_proto_name,auth_proto_data))
+impl AsRawFd for XInterface<Socket> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket_fd.expect("sockets always report their descriptor")
+    }
+}
+
+impl AsFd for XInterface<Socket> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        //The reading transport owns this descriptor and lives exactly as long as the interface.
+        unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) }
+    }
+}
+
+///Zero bytes that pad a request part of `len` bytes to a multiple of 4.
+pub fn padding(len: usize) -> &'static [u8] {
+    &[0; 3][..padding_len(len)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension;
+    use crate::setup::tests::{MINIMAL_SETUP, XVFB_SETUP};
+    use std::io::{Read, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    const THREADS: u16 = 8;
+    const REQUESTS_PER_THREAD: u16 = 200;
+
+    ///Connects an interface to a fake server and returns the server's end after the handshake.
+    fn connect_fake_server(name: &str) -> (XInterface, UnixStream) {
+        let path = std::env::temp_dir().join(format!("xeagle-xinterface-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut setup_request = [0u8; 12];
+            stream.read_exact(&mut setup_request).unwrap();
+            assert_eq!(setup_request[0], 0x6C);
+            stream.write_all(MINIMAL_SETUP).unwrap();
+            stream
+        });
+
+        let socket = Socket::new(path.to_str().unwrap()).unwrap();
+        let x_interface = XInterface::with_byte_order(socket, DisplayName::parse(":0").unwrap(), &mut CookieAuthenticator::none(), ByteOrder::LsbFirst).unwrap();
+        let stream = server.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (x_interface, stream)
+    }
+
+    ///Answers every 8 byte request with an event and, if its opcode is 1, a reply echoing its body.
+    fn serve_echo(mut stream: UnixStream, requests: usize) -> UnixStream {
+        let mut sequence: u16 = 0;
+        for _ in 0..requests {
+            let mut request = [0u8; 8];
+            stream.read_exact(&mut request).unwrap();
+            sequence = sequence.wrapping_add(1);
+            let seq = sequence.to_le_bytes();
+
+            let mut event = [0u8; 32];
+            event[0] = 2;
+            event[2..4].copy_from_slice(&seq);
+            stream.write_all(&event).unwrap();
+
+            if request[0] == 1 {
+                let mut reply = [0u8; 36];
+                reply[0] = REPLY;
+                reply[2..4].copy_from_slice(&seq);
+                reply[4] = 1;
+                reply[32..36].copy_from_slice(&request[4..8]);
+                stream.write_all(&reply).unwrap();
+            }
+        }
+        stream
+    }
+
+    fn echo_request(thread: u16, i: u16) -> [u8; 8] {
+        let mut request = [1, 0, 2, 0, 0, 0, 0, 0];
+        request[4..6].copy_from_slice(&thread.to_le_bytes());
+        request[6..8].copy_from_slice(&i.to_le_bytes());
+        request
+    }
+
+    ///One direction of an in-memory pipe.
+    #[derive(Default)]
+    struct Channel {
+        bytes: Mutex<VecDeque<u8>>,
+        available: Condvar
+    }
+
+    ///A transport that never touches the operating system.
+    struct MemoryTransport {
+        incoming: Arc<Channel>,
+        outgoing: Arc<Channel>,
+        write_buf: Vec<u8>,
+        max_read: usize
+    }
+
+    impl MemoryTransport {
+        ///Returns the client and the server end of a new pipe.
+        fn pair() -> (Self, Self) {
+            let (up, down) = (Arc::new(Channel::default()), Arc::new(Channel::default()));
+            (MemoryTransport {incoming: Arc::clone(&down), outgoing: Arc::clone(&up), write_buf: Vec::new(), max_read: 5},
+             MemoryTransport {incoming: up, outgoing: down, write_buf: Vec::new(), max_read: usize::MAX})
+        }
+    }
+
+    impl Transport for MemoryTransport {
+        fn write_all(&mut self, data: &[u8]) -> Result<(), SockError> {
+            self.write_buf.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn flush_all(&mut self) -> Result<(), SockError> {
+            self.outgoing.bytes.lock().unwrap().extend(self.write_buf.drain(..));
+            self.outgoing.available.notify_all();
+            Ok(())
+        }
+
+        ///Hands out at most `max_read` bytes at a time, to exercise reassembly of short reads.
+        fn read_into(&mut self, buf: &mut [u8], block: bool) -> Result<usize, SockError> {
+            let mut bytes = self.incoming.bytes.lock().unwrap();
+            while block && bytes.is_empty() {
+                bytes = self.incoming.available.wait(bytes).unwrap();
+            }
+            let count = buf.len().min(bytes.len()).min(self.max_read);
+            for (dest, src) in buf.iter_mut().zip(bytes.drain(..count)) {
+                *dest = src;
+            }
+            Ok(count)
+        }
+
+        fn try_clone(&self) -> Result<Self, SockError> {
+            Ok(MemoryTransport {incoming: Arc::clone(&self.incoming), outgoing: Arc::clone(&self.outgoing), write_buf: Vec::new(), max_read: self.max_read})
+        }
+    }
+
+    #[test]
+    fn runs_over_an_in_memory_transport() {
+        let (client, mut server) = MemoryTransport::pair();
+        server.write_all(MINIMAL_SETUP).unwrap();
+        server.flush_all().unwrap();
+
+        let x_interface = XInterface::with_byte_order(client, DisplayName::parse(":0").unwrap(), &mut CookieAuthenticator::none(), ByteOrder::LsbFirst).unwrap();
+        assert_eq!(server.read_bytes(12).unwrap()[0], 0x6C);
+        assert_eq!(x_interface.poll_fd(), None);
+        assert!(x_interface.send_request_with_fds(&[IoSlice::new(&echo_request(0, 0))], vec![OwnedFd::from(UnixStream::pair().unwrap().0)], None).is_err());
+
+        let sequence = x_interface.send_request(&echo_request(3, 4), true).unwrap();
+        let request = server.read_bytes(8).unwrap();
+        let mut reply = vec![0u8; 36];
+        reply[0] = REPLY;
+        reply[2..4].copy_from_slice(&(sequence as u16).to_le_bytes());
+        reply[4] = 1;
+        reply[32..36].copy_from_slice(&request[4..8]);
+        server.write_all(&reply).unwrap();
+        server.flush_all().unwrap();
+
+        assert_eq!(&x_interface.wait_for_reply(sequence).unwrap()[32..36], &[3, 0, 4, 0]);
+        assert_eq!(x_interface.poll_for_event().unwrap(), None);
+    }
+
+    #[test]
+    fn talks_to_the_server_in_big_endian() {
+        let (client, mut server) = MemoryTransport::pair();
+        //MINIMAL_SETUP with every multi-byte field swapped.
+        server.write_all(&[
+            1, 0, 0, 11, 0, 0, 0, 8,
+            0, 0, 0, 0, 0, 0x20, 0, 0, 0, 0x1F, 0xFF, 0xFF, 0, 0, 0, 0,
+            0, 0, 0xFF, 0xFF, 0, 0, 1, 1, 32, 32, 8, 255, 0, 0, 0, 0
+        ]).unwrap();
+        server.flush_all().unwrap();
+
+        let x_interface = XInterface::with_byte_order(client, DisplayName::parse(":0").unwrap(), &mut CookieAuthenticator::new(b"A".to_vec(), vec![1; 5]), ByteOrder::MsbFirst).unwrap();
+        assert_eq!(server.read_bytes(12).unwrap(), [b'B', 0, 0, 11, 0, 0, 0, 1, 0, 5, 0, 0]);
+        server.read_bytes(12).unwrap();
+        assert_eq!(x_interface.byte_order(), ByteOrder::MsbFirst);
+        assert_eq!((x_interface.setup().resource_id_base, x_interface.setup().maximum_request_length), (0x0020_0000, 65535));
+
+        let sequence = x_interface.send_request(&[1, 0, 0, 0, 0, 0, 0, 0], true).unwrap();
+        assert_eq!(server.read_bytes(8).unwrap(), [1, 0, 0, 2, 0, 0, 0, 0]);
+        let mut reply = vec![0u8; 36];
+        reply[0] = REPLY;
+        reply[2..4].copy_from_slice(&(sequence as u16).to_be_bytes());
+        reply[4..8].copy_from_slice(&1u32.to_be_bytes());
+        let mut event = vec![0u8; 32];
+        event[0] = 12;
+        event[2..4].copy_from_slice(&(sequence as u16).to_be_bytes());
+        server.write_all(&[reply.clone(), event].concat()).unwrap();
+        server.flush_all().unwrap();
+
+        assert_eq!(x_interface.wait_for_reply(sequence).unwrap(), reply);
+        let event = x_interface.wait_for_event().unwrap();
+        assert_eq!((event.sequence(), event.byte_order()), (1, ByteOrder::MsbFirst));
+    }
+
+    ///Answers each challenge with its bytes reversed, then gives up after `rounds` answers.
+    struct ReversingAuthenticator {
+        rounds: usize,
+        challenges: Vec<Vec<u8>>
+    }
+
+    impl Authenticator for ReversingAuthenticator {
+        fn name(&self) -> &[u8] {
+            b"TEST-CHALLENGE"
+        }
+
+        fn initial_data(&mut self) -> Result<Vec<u8>, XInterfaceError> {
+            Ok(b"hello".to_vec())
+        }
+
+        fn respond(&mut self, challenge: &[u8]) -> Result<Option<Vec<u8>>, XInterfaceError> {
+            self.challenges.push(challenge.to_vec());
+            if self.challenges.len() > self.rounds {
+                return Ok(None);
+            }
+            Ok(Some(challenge.iter().rev().copied().collect()))
+        }
+    }
+
+    fn authenticate_reply(data: &[u8]) -> Vec<u8> {
+        let mut reply = vec![2, 0, 0, 0, 0, 0];
+        reply.extend_from_slice(&((data.len() / 4) as u16).to_le_bytes());
+        reply.extend_from_slice(data);
+        reply
+    }
+
+    #[test]
+    fn answers_authenticate_challenges() {
+        let (client, mut server) = MemoryTransport::pair();
+        server.write_all(&authenticate_reply(b"abcd")).unwrap();
+        server.write_all(&authenticate_reply(b"12345678")).unwrap();
+        server.write_all(MINIMAL_SETUP).unwrap();
+        server.flush_all().unwrap();
+
+        let mut authenticator = ReversingAuthenticator {rounds: 2, challenges: Vec::new()};
+        XInterface::with_byte_order(client, DisplayName::parse(":0").unwrap(), &mut authenticator, ByteOrder::LsbFirst).unwrap();
+        assert_eq!(authenticator.challenges, [b"abcd".to_vec(), b"12345678".to_vec()]);
+
+        let setup_request = server.read_bytes(12 + 16 + 8).unwrap();
+        assert_eq!(&setup_request[6..8], &14u16.to_le_bytes());
+        assert_eq!(&setup_request[8..10], &5u16.to_le_bytes());
+        assert_eq!(&setup_request[12..26], b"TEST-CHALLENGE");
+        assert_eq!(&setup_request[28..33], b"hello");
+        assert_eq!(server.read_bytes(12).unwrap(), b"dcba87654321");
+    }
+
+    #[test]
+    fn unanswered_challenges_fail_with_the_reason() {
+        let (client, mut server) = MemoryTransport::pair();
+        server.write_all(&authenticate_reply(b"go away\0")).unwrap();
+        server.flush_all().unwrap();
+
+        match XInterface::with_byte_order(client, DisplayName::parse(":0").unwrap(), &mut CookieAuthenticator::none(), ByteOrder::LsbFirst) {
+            Err(XInterfaceError::AuthRequested(reason)) => assert_eq!(reason, "go away"),
+            other => panic!("expected AuthRequested, got {:?}", other.err())
+        }
+    }
+
+    #[test]
+    fn exposes_the_parsed_setup() {
+        let (client, mut server) = MemoryTransport::pair();
+        server.write_all(XVFB_SETUP).unwrap();
+        server.write_all(&[2; 32]).unwrap();
+        server.flush_all().unwrap();
+
+        let x_interface = XInterface::with_byte_order(client, DisplayName::parse(":0").unwrap(), &mut CookieAuthenticator::none(), ByteOrder::LsbFirst).unwrap();
+        assert_eq!(x_interface.setup().vendor, "The X.Org Foundation");
+        assert_eq!(x_interface.setup().roots[x_interface.default_screen()].root, 0x3E8);
+        //Nothing of the setup is left over to be mistaken for an event.
+        assert_eq!(x_interface.wait_for_event().unwrap().bytes(), &[2; 32]);
+    }
+
+    ///A reply to request `sequence` whose first 8 bytes of data are `data`.
+    fn short_reply(sequence: u16, data: [u8; 8]) -> Vec<u8> {
+        let mut reply = vec![0u8; 32];
+        reply[0] = REPLY;
+        reply[2..4].copy_from_slice(&sequence.to_le_bytes());
+        reply[8..16].copy_from_slice(&data);
+        reply
+    }
+
+    #[test]
+    fn recovers_resource_ids_with_xc_misc() {
+        let (client, mut server) = MemoryTransport::pair();
+        //Only two IDs, 0x00200000 and 0x00200001.
+        let mut setup = MINIMAL_SETUP.to_vec();
+        setup[16..20].copy_from_slice(&1u32.to_le_bytes());
+        server.write_all(&setup).unwrap();
+        server.flush_all().unwrap();
+
+        let x_interface = XInterface::with_byte_order(client, DisplayName::parse(":0").unwrap(), &mut CookieAuthenticator::none(), ByteOrder::LsbFirst).unwrap();
+        server.read_bytes(12).unwrap();
+        let first = x_interface.generate_id().unwrap();
+        assert_eq!(first, 0x0020_0000);
+        assert_eq!(x_interface.generate_id().unwrap(), 0x0020_0001);
+        x_interface.free_id(first);
+        assert_eq!(x_interface.generate_id().unwrap(), first);
+
+        let server = thread::spawn(move || {
+            assert_eq!(server.read_bytes(16).unwrap(), extension::query_extension_request(b"XC-MISC", ByteOrder::LsbFirst));
+            server.write_all(&short_reply(1, [1, 130, 0, 0, 0, 0, 0, 0])).unwrap();
+            server.flush_all().unwrap();
+            assert_eq!(server.read_bytes(4).unwrap(), [130, 1, 1, 0]);
+            server.write_all(&short_reply(2, [0x01, 0, 0x20, 0, 1, 0, 0, 0])).unwrap();
+            server.flush_all().unwrap();
+            //No IDs left the second time.
+            assert_eq!(server.read_bytes(4).unwrap(), [130, 1, 1, 0]);
+            server.write_all(&short_reply(3, [0, 0, 0, 0, 1, 0, 0, 0])).unwrap();
+            server.flush_all().unwrap();
+        });
+
+        assert_eq!(x_interface.generate_id().unwrap(), 0x0020_0001);
+        assert!(matches!(x_interface.generate_id(), Err(XInterfaceError::ResourceIdsExhausted)));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn asks_xc_misc_for_one_thread_at_a_time() {
+        let (client, mut server) = MemoryTransport::pair();
+        let mut setup = MINIMAL_SETUP.to_vec();
+        setup[16..20].copy_from_slice(&1u32.to_le_bytes());
+        server.write_all(&setup).unwrap();
+        server.flush_all().unwrap();
+
+        let x_interface = Arc::new(XInterface::with_byte_order(client, DisplayName::parse(":0").unwrap(), &mut CookieAuthenticator::none(), ByteOrder::LsbFirst).unwrap());
+        server.read_bytes(12).unwrap();
+        x_interface.generate_id().unwrap();
+        x_interface.generate_id().unwrap();
+
+        let server = thread::spawn(move || {
+            assert_eq!(server.read_bytes(16).unwrap(), extension::query_extension_request(b"XC-MISC", ByteOrder::LsbFirst));
+            server.write_all(&short_reply(1, [1, 130, 0, 0, 0, 0, 0, 0])).unwrap();
+            server.flush_all().unwrap();
+            for (sequence, start) in [(2u16, 0u8), (3, 1)] {
+                assert_eq!(server.read_bytes(4).unwrap(), [130, 1, 1, 0]);
+                //The other thread waits for this range instead of asking as well.
+                thread::sleep(Duration::from_millis(50));
+                assert_eq!(server.read_into(&mut [0; 4], false).unwrap(), 0);
+                server.write_all(&short_reply(sequence, [start, 0, 0x20, 0, 1, 0, 0, 0])).unwrap();
+                server.flush_all().unwrap();
+            }
+        });
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for _ in 0..2 {
+            let (x_interface, sender) = (Arc::clone(&x_interface), sender.clone());
+            thread::spawn(move || sender.send(x_interface.generate_id().unwrap()).unwrap());
+        }
+        //A server that gave up leaves the callers waiting forever.
+        let mut ids: Vec<u32> = (0..2).map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+        ids.sort();
+        assert_eq!(ids, [0x0020_0000, 0x0020_0001]);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn sends_long_requests_with_big_requests() {
+        let (client, mut server) = MemoryTransport::pair();
+        server.write_all(MINIMAL_SETUP).unwrap();
+        server.flush_all().unwrap();
+        let x_interface = XInterface::with_byte_order(client, DisplayName::parse(":0").unwrap(), &mut CookieAuthenticator::none(), ByteOrder::LsbFirst).unwrap();
+        server.read_bytes(12).unwrap();
+
+        let payload = vec![7u8; 70_000 * 4];
+        let server = thread::spawn(move || {
+            assert_eq!(server.read_bytes(20).unwrap(), extension::query_extension_request(b"BIG-REQUESTS", ByteOrder::LsbFirst));
+            server.write_all(&short_reply(1, [1, 133, 0, 0, 0, 0, 0, 0])).unwrap();
+            server.flush_all().unwrap();
+            assert_eq!(server.read_bytes(4).unwrap(), [133, 0, 1, 0]);
+            server.write_all(&short_reply(2, [0xFF, 0xFF, 0x3F, 0, 0, 0, 0, 0])).unwrap();
+            server.flush_all().unwrap();
+            let request = server.read_bytes(70_000 * 4 + 4).unwrap();
+            assert_eq!(&request[..8], &[72, 2, 0, 0, 0x71, 0x11, 0x01, 0x00]);
+            assert!(request[8..].iter().all(|&b| b == 7));
+        });
+
+        let header = [72, 2, 0, 0];
+        let sequence = x_interface.send_request_vectored(&[IoSlice::new(&header), IoSlice::new(&payload[4..])], false).unwrap();
+        assert_eq!(sequence, 3);
+        assert_eq!(x_interface.maximum_request_length().unwrap(), 0x003F_FFFF);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_long_requests_without_big_requests() {
+        let (client, mut server) = MemoryTransport::pair();
+        server.write_all(MINIMAL_SETUP).unwrap();
+        server.flush_all().unwrap();
+        let x_interface = XInterface::with_byte_order(client, DisplayName::parse(":0").unwrap(), &mut CookieAuthenticator::none(), ByteOrder::LsbFirst).unwrap();
+        server.read_bytes(12).unwrap();
+
+        let server = thread::spawn(move || {
+            assert_eq!(server.read_bytes(20).unwrap(), extension::query_extension_request(b"BIG-REQUESTS", ByteOrder::LsbFirst));
+            server.write_all(&short_reply(1, [0; 8])).unwrap();
+            server.flush_all().unwrap();
+            server
+        });
+
+        let payload = vec![0u8; 65536 * 4];
+        match x_interface.send_request(&payload, false) {
+            Err(XInterfaceError::RequestTooLong {length, maximum}) => assert_eq!((length, maximum), (262_144, 262_140)),
+            other => panic!("expected RequestTooLong, got {:?}", other)
+        }
+        assert_eq!(x_interface.maximum_request_length().unwrap(), 65535);
+        let mut server = server.join().unwrap();
+
+        //Nothing of the rejected request was written, the next one follows right after.
+        assert_eq!(x_interface.send_request(&echo_request(0, 0), false).unwrap(), 2);
+        assert_eq!(server.read_bytes(8).unwrap(), echo_request(0, 0));
+    }
+
+    #[test]
+    fn interface_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<XInterface>();
+    }
+
+    #[test]
+    fn connect_errors_chain_to_the_os_error() {
+        let error = crate::application::Application::with_display(":65000").err().unwrap();
+        let x_interface_error = error.source().unwrap().downcast_ref::<XInterfaceError>().unwrap();
+        assert!(matches!(x_interface_error, XInterfaceError::SocketError(_)));
+        let sock_error = x_interface_error.source().unwrap().downcast_ref::<SockError>().unwrap();
+        assert!(matches!(sock_error, SockError::ConnectError(_) | SockError::ConnectionRefused(_)));
+        assert!(sock_error.source().unwrap().downcast_ref::<std::io::Error>().is_some());
+    }
+
+    #[test]
+    fn concurrent_requests_get_their_own_replies() {
+        let (x_interface, stream) = connect_fake_server("hammer");
+        let x_interface = Arc::new(x_interface);
+        let total = (THREADS * REQUESTS_PER_THREAD) as usize;
+        let server = thread::spawn(move || serve_echo(stream, total));
+
+        let events = {
+            let x_interface = Arc::clone(&x_interface);
+            thread::spawn(move || {
+                for _ in 0..total {
+                    assert_eq!(x_interface.wait_for_event().unwrap().response_type(), 2);
+                }
+            })
+        };
+
+        let workers: Vec<_> = (0..THREADS).map(|thread| {
+            let x_interface = Arc::clone(&x_interface);
+            thread::spawn(move || {
+                for i in 0..REQUESTS_PER_THREAD {
+                    let request = echo_request(thread, i);
+                    let sequence = x_interface.send_request(&request, true).unwrap();
+                    let reply = x_interface.wait_for_reply(sequence).unwrap();
+                    assert_eq!(&reply[32..36], &request[4..8]);
+                }
+            })
+        }).collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        events.join().unwrap();
+        let _stream = server.join().unwrap();
+        assert_eq!(x_interface.poll_for_event().unwrap(), None);
+    }
+
+    #[test]
+    fn reply_fds_come_with_their_reply() {
+        use std::os::fd::IntoRawFd;
+
+        let (x_interface, stream) = connect_fake_server("fds");
+        let mut server = Socket::from_fd(stream.into_raw_fd());
+        let (passed, _kept) = UnixStream::pair().unwrap();
+
+        let first = x_interface.send_request_with_fds(&[IoSlice::new(&echo_request(0, 0))], vec![OwnedFd::from(passed)], Some(2)).unwrap();
+        let second = x_interface.send_request(&echo_request(0, 1), true).unwrap();
+        assert_eq!(server.read_bytes(16).unwrap().len(), 16);
+        assert_eq!(server.take_fds().len(), 1);
+
+        let mut reply = vec![0u8; 32];
+        reply[0] = REPLY;
+        reply[2..4].copy_from_slice(&(first as u16).to_le_bytes());
+        let (a, b) = UnixStream::pair().unwrap();
+        server.write_fds(vec![OwnedFd::from(a), OwnedFd::from(b)]);
+        server.write_all(&reply).unwrap();
+        reply[2..4].copy_from_slice(&(second as u16).to_le_bytes());
+        server.write_all(&reply).unwrap();
+        server.flush_all().unwrap();
+
+        assert_eq!(x_interface.wait_for_reply_with_fds(second).unwrap().1.len(), 0);
+        assert_eq!(x_interface.wait_for_reply_with_fds(first).unwrap().1.len(), 2);
+    }
+
+    #[test]
+    fn generated_requests_pass_their_descriptors() {
+        use crate::protocol::dri3;
+        use std::os::fd::IntoRawFd;
+
+        let (x_interface, stream) = connect_fake_server("request-fds");
+        x_interface.extensions.lock().unwrap().insert(dri3::EXTENSION_NAME.to_vec(), Some(ExtensionInfo {major_opcode: 140, first_event: 0, first_error: 0}));
+        let mut server = Socket::from_fd(stream.into_raw_fd());
+
+        let (fence, _kept) = UnixStream::pair().unwrap();
+        let request = dri3::FenceFromFDRequest {drawable: 0x100, fence: 0x200001, initially_triggered: false, fence_fd: OwnedFd::from(fence)};
+        let fenced = x_interface.send(&request).unwrap();
+        let open = x_interface.send(&dri3::OpenRequest {drawable: 0x100, provider: 0}).unwrap();
+        assert_eq!(server.read_bytes(28).unwrap()[..2], [140, 4]);
+        assert_eq!(server.take_fds().len(), 1);
+
+        let mut reply = vec![0u8; 32];
+        reply[0] = REPLY;
+        reply[1] = 1;
+        reply[2..4].copy_from_slice(&(open.sequence() as u16).to_le_bytes());
+        let (device, _) = UnixStream::pair().unwrap();
+        server.write_fds(vec![OwnedFd::from(device)]);
+        server.write_all(&reply).unwrap();
+        server.flush_all().unwrap();
+
+        let (reply, fds) = x_interface.reply_with_fds(open).unwrap();
+        assert_eq!((reply.nfd, fds.len()), (1, 1));
+        assert_eq!(x_interface.reply(fenced).unwrap(), ());
+    }
+
+    #[test]
+    fn errors_go_to_the_waiting_request() {
+        let (x_interface, mut stream) = connect_fake_server("errors");
+        let sequence = x_interface.send_request(&echo_request(0, 0), true).unwrap();
+
+        let mut error = [0u8; 32];
+        error[1] = 8;
+        error[2..4].copy_from_slice(&(sequence as u16).to_le_bytes());
+        stream.write_all(&error).unwrap();
+        //An error for a request nobody waits on is an ordinary event.
+        error[2..4].copy_from_slice(&(sequence as u16 + 1).to_le_bytes());
+        stream.write_all(&error).unwrap();
+
+        match x_interface.wait_for_reply(sequence) {
+            Err(XInterfaceError::RequestError(error)) => assert_eq!(error.bytes()[1], 8),
+            other => panic!("expected an error, got {:?}", other)
+        }
+        assert!(x_interface.wait_for_event().unwrap().is_error());
+    }
+
+    ///A `GetInputFocus` reply whose wire sequence number is `sequence`, naming `focus`.
+    fn focus_reply(sequence: u16, focus: u32) -> Vec<u8> {
+        let mut reply = short_reply(sequence, [0; 8]);
+        reply[8..12].copy_from_slice(&focus.to_le_bytes());
+        reply
+    }
+
+    #[test]
+    fn pipelined_cookies_are_redeemed_in_any_order() {
+        use crate::protocol::xproto::{GetInputFocusRequest, NoOperationRequest};
+
+        let (x_interface, mut stream) = connect_fake_server("cookies");
+        let first = x_interface.send(&GetInputFocusRequest).unwrap();
+        let nothing = x_interface.send(&NoOperationRequest).unwrap();
+        let third = x_interface.send(&GetInputFocusRequest).unwrap();
+        let fourth = x_interface.send(&GetInputFocusRequest).unwrap();
+        assert_eq!((first.sequence(), nothing.sequence(), third.sequence(), fourth.sequence()), (1, 2, 3, 4));
+        let mut requests = [0u8; 16];
+        stream.read_exact(&mut requests).unwrap();
+        assert_eq!(requests, [43, 0, 1, 0, 127, 0, 1, 0, 43, 0, 1, 0, 43, 0, 1, 0]);
+
+        for (sequence, focus) in [(1, 0x100), (3, 0x300), (4, 0x400)] {
+            stream.write_all(&focus_reply(sequence, focus)).unwrap();
+        }
+        x_interface.discard_reply(third);
+        assert_eq!(x_interface.reply(fourth).unwrap().focus, 0x400);
+        assert_eq!(x_interface.reply(nothing).unwrap(), ());
+        assert_eq!(x_interface.reply(first).unwrap().focus, 0x100);
+        assert!(x_interface.received.lock().unwrap().replies.is_empty());
+    }
+
+    #[test]
+    fn requests_answered_with_several_replies_keep_them_apart() {
+        use crate::protocol::xproto::GetInputFocusRequest;
+
+        let (x_interface, mut stream) = connect_fake_server("multi");
+        let series = x_interface.send(&GetInputFocusRequest).unwrap();
+        let dropped = x_interface.send(&GetInputFocusRequest).unwrap();
+        let first_only = x_interface.send(&GetInputFocusRequest).unwrap();
+        let last = x_interface.send(&GetInputFocusRequest).unwrap();
+        x_interface.discard_reply(dropped);
+        for (sequence, focus) in [(1, 0x100), (1, 0x101), (2, 0x200), (2, 0x201), (3, 0x300), (3, 0x301), (4, 0x400)] {
+            stream.write_all(&focus_reply(sequence, focus)).unwrap();
+        }
+
+        assert_eq!(x_interface.next_reply(&series).unwrap().focus, 0x100);
+        assert_eq!(x_interface.next_reply(&series).unwrap().focus, 0x101);
+        assert_eq!(x_interface.reply(first_only).unwrap().focus, 0x300);
+        assert_eq!(x_interface.reply(last).unwrap().focus, 0x400);
+        //Nothing is left behind for the dropped cookies, nor for the request still to be completed.
+        let received = x_interface.received.lock().unwrap();
+        assert!(received.replies.is_empty());
+        assert_eq!(received.discarded.iter().copied().collect::<Vec<_>>(), [4]);
+    }
+
+    #[test]
+    fn widens_sequences_past_wraparound() {
+        let (x_interface, mut stream) = connect_fake_server("wraparound");
+        x_interface.writer.lock().unwrap().sequence = 0xFFFE;
+        x_interface.received.lock().unwrap().last_sequence = 0xFFFE;
+
+        let before = x_interface.send_request(&[43, 0, 1, 0], true).unwrap();
+        let after = x_interface.send_request(&[43, 0, 1, 0], true).unwrap();
+        assert_eq!((before, after), (0xFFFF, 0x10000));
+        stream.write_all(&focus_reply(0xFFFF, 1)).unwrap();
+        stream.write_all(&focus_reply(0, 2)).unwrap();
+
+        assert_eq!(&x_interface.wait_for_reply(after).unwrap()[8..12], &[2, 0, 0, 0]);
+        assert_eq!(&x_interface.wait_for_reply(before).unwrap()[8..12], &[1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn syncs_before_the_wire_sequence_becomes_ambiguous() {
+        let (x_interface, mut stream) = connect_fake_server("sync");
+        //As after 0xFFFE requests without a reply, none of which caused a packet.
+        x_interface.writer.lock().unwrap().sequence = 0xFFFE;
+
+        let sequence = x_interface.send_request(&[127, 0, 1, 0], false).unwrap();
+        assert_eq!(sequence, 0x10000);
+        let mut requests = [0u8; 8];
+        stream.read_exact(&mut requests).unwrap();
+        assert_eq!(requests, [43, 0, 1, 0, 127, 0, 1, 0]);
+
+        //The reply to the inserted request is dropped, the error of the one after it is an event.
+        stream.write_all(&focus_reply(0xFFFF, 0)).unwrap();
+        let mut error = [0u8; 32];
+        error[1] = 1;
+        stream.write_all(&error).unwrap();
+        assert_eq!(x_interface.wait_for_event().unwrap().sequence(), 0x10000);
+        let received = x_interface.received.lock().unwrap();
+        assert!(received.replies.is_empty());
+        assert!(received.discarded.is_empty());
+    }
+
+    #[test]
+    fn packets_before_a_disconnect_are_handed_out() {
+        let (x_interface, mut stream) = connect_fake_server("disconnect");
+        let mut error = [0u8; 32];
+        error[1] = 10;
+        stream.write_all(&error).unwrap();
+        drop(stream);
+
+        assert_eq!(x_interface.wait_for_event().unwrap().bytes()[1], 10);
+        assert!(matches!(x_interface.poll_for_event(), Err(XInterfaceError::SocketError(SockError::ConnectionClosed(_)))));
+    }
+
+    #[test]
+    fn failed_writes_leave_no_bookkeeping_and_break_the_connection() {
+        let (x_interface, stream) = connect_fake_server("broken");
+        drop(stream);
+
+        assert!(matches!(x_interface.send_request(&echo_request(0, 0), true), Err(XInterfaceError::SocketError(_))));
+        assert!(x_interface.received.lock().unwrap().awaiting_reply.is_empty());
+        assert_eq!(x_interface.writer.lock().unwrap().sequence, 0);
+        assert!(matches!(x_interface.send_request(&echo_request(0, 1), true), Err(XInterfaceError::ConnectionBroken)));
+    }
+
+    #[test]
+    fn sockets_expose_their_descriptor() {
+        let (x_interface, _stream) = connect_fake_server("fd");
+        assert_eq!(x_interface.poll_fd(), Some(x_interface.as_raw_fd()));
+        assert_eq!(x_interface.as_fd().as_raw_fd(), x_interface.as_raw_fd());
+    }
 }
@@ -0,0 +1,1221 @@
+//!Resolving the types of the protocol descriptions and writing Rust code for them.
+//!
+//!Every struct, request, reply, event and error is first lowered into a list of [`Slot`]s, the
+//!pieces of its wire representation in order, including the header the XML leaves implicit. The
+//!encoding and decoding code is then written slot by slot against the crate's `Serializable`.
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+use crate::model::{BitCase, Container, Event, Expr, Item, Member, Module, Request, Switch};
+use crate::names::{const_name, field_name, type_name};
+
+///Types the generated code refers to by their plain name, which a module must not define.
+const RESERVED: [&str; 8] = ["Option", "Result", "Vec", "Default", "ByteOrder", "ParseError", "Serializable", "Request"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Prim {
+    U8, U16, U32, U64, I8, I16, I32, I64, Bool, F32, F64
+}
+
+impl Prim {
+    fn builtin(name: &str) -> Option<Prim> {
+        Some(match name {
+            "CARD8" | "BYTE" | "char" | "void" => Prim::U8,
+            "CARD16" => Prim::U16,
+            "CARD32" => Prim::U32,
+            "CARD64" => Prim::U64,
+            "INT8" => Prim::I8,
+            "INT16" => Prim::I16,
+            "INT32" => Prim::I32,
+            "INT64" => Prim::I64,
+            "BOOL" => Prim::Bool,
+            "float" => Prim::F32,
+            "double" => Prim::F64,
+            _ => return None
+        })
+    }
+
+    fn rust(self) -> &'static str {
+        match self {
+            Prim::U8 => "u8",
+            Prim::U16 => "u16",
+            Prim::U32 => "u32",
+            Prim::U64 => "u64",
+            Prim::I8 => "i8",
+            Prim::I16 => "i16",
+            Prim::I32 => "i32",
+            Prim::I64 => "i64",
+            Prim::Bool => "bool",
+            Prim::F32 => "f32",
+            Prim::F64 => "f64"
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Prim::U8 | Prim::I8 | Prim::Bool => 1,
+            Prim::U16 | Prim::I16 => 2,
+            Prim::U32 | Prim::I32 | Prim::F32 => 4,
+            Prim::U64 | Prim::I64 | Prim::F64 => 8
+        }
+    }
+
+    ///The unsigned integer of the same size, which is what an enum stored in this type is made of.
+    fn unsigned(self) -> Option<Prim> {
+        match self {
+            Prim::U8 | Prim::I8 | Prim::Bool => Some(Prim::U8),
+            Prim::U16 | Prim::I16 => Some(Prim::U16),
+            Prim::U32 | Prim::I32 => Some(Prim::U32),
+            Prim::U64 | Prim::I64 => Some(Prim::U64),
+            Prim::F32 | Prim::F64 => None
+        }
+    }
+
+    ///The smallest unsigned integer that holds `value`.
+    fn fitting(value: u64) -> Prim {
+        match value {
+            0..=0xFF => Prim::U8,
+            0x100..=0xFFFF => Prim::U16,
+            0x1_0000..=0xFFFF_FFFF => Prim::U32,
+            _ => Prim::U64
+        }
+    }
+}
+
+///A type as it is written in a generated module.
+#[derive(Clone)]
+struct Ty {
+    path: String,
+    ///The number behind the type, unless it is a struct or union.
+    prim: Option<Prim>,
+    ///Whether this is one of the generated enums, a newtype around `prim`.
+    is_enum: bool,
+    ///The size on the wire, `None` for types that contain lists of varying length.
+    size: Option<usize>,
+    eq: bool,
+    default: bool
+}
+
+impl Ty {
+    fn prim(prim: Prim) -> Ty {
+        let float = matches!(prim, Prim::F32 | Prim::F64);
+        Ty {path: prim.rust().to_string(), prim: Some(prim), is_enum: false, size: Some(prim.size()), eq: !float, default: true}
+    }
+
+    fn is_byte(&self) -> bool {
+        self.prim == Some(Prim::U8) && !self.is_enum
+    }
+}
+
+///A type defined in one of the modules.
+#[derive(Clone)]
+struct TypeDef {
+    module: String,
+    name: String,
+    prim: Option<Prim>,
+    size: Option<usize>,
+    eq: bool,
+    default: bool
+}
+
+impl TypeDef {
+    ///The type as used from `module`.
+    fn ty(&self, module: &str) -> Ty {
+        Ty {path: path(&self.module, &self.name, module), prim: self.prim, is_enum: false, size: self.size, eq: self.eq, default: self.default}
+    }
+}
+
+struct EnumDef {
+    module: String,
+    name: String,
+    repr: Prim,
+    values: HashMap<String, u64>
+}
+
+///How `name`, defined in `defined_in`, is written in `module`.
+fn path(defined_in: &str, name: &str, module: &str) -> String {
+    if defined_in == module { name.to_string() } else { format!("super::{}::{}", defined_in, name) }
+}
+
+///One piece of the wire representation.
+#[derive(Clone)]
+enum Slot {
+    ///A byte that is always the same, such as the 1 that starts a reply.
+    Byte(u8),
+    Pad(usize),
+    Align(usize),
+    Value {xml: String, name: String, ty: Ty, source: Source},
+    List {xml: String, name: String, item: Ty, len: Len},
+    Switch {name: String, aux: Aux},
+    ///A file descriptor of a request, or a list of them, which is passed next to the bytes.
+    Fd {name: String, list: bool}
+}
+
+///Where the value of a [`Slot::Value`] comes from when encoding.
+#[derive(Clone)]
+enum Source {
+    ///A field of the struct.
+    Stored,
+    ///The length of the list with the given name, which is not stored separately.
+    Length(String),
+    ///The mask of the switch with the given name.
+    Mask(String),
+    ///An expression over the other fields.
+    Expr(Expr),
+    ///The length of a reply or generic event beyond its first 32 bytes, stored but computed when
+    ///encoding.
+    ReplyLength
+}
+
+#[derive(Clone)]
+enum Len {
+    Fixed(usize),
+    Count(Expr),
+    ///The rest of a request.
+    Rest
+}
+
+///The optional values of a request, present according to a mask.
+#[derive(Clone)]
+struct Aux {
+    name: String,
+    mask: Prim,
+    cases: Vec<(u64, String, Ty)>
+}
+
+impl Slot {
+    fn size(&self, offset: usize) -> Option<usize> {
+        match self {
+            Slot::Byte(_) => Some(1),
+            Slot::Pad(bytes) => Some(*bytes),
+            Slot::Align(align) => Some(offset.next_multiple_of(*align) - offset),
+            Slot::Value {ty, ..} => ty.size,
+            Slot::List {item, len: Len::Fixed(count), ..} => item.size.map(|size| size * count),
+            Slot::List {..} | Slot::Switch {..} => None,
+            Slot::Fd {..} => Some(0)
+        }
+    }
+
+    fn is_one_byte(&self) -> bool {
+        self.size(0) == Some(1) && !matches!(self, Slot::Align(_))
+    }
+
+    ///The name of the struct field this slot is stored in, if any.
+    fn stored(&self) -> Option<(&str, String)> {
+        match self {
+            Slot::Value {name, ty, source: Source::Stored | Source::ReplyLength, ..} => Some((name, ty.path.clone())),
+            Slot::List {name, item, len: Len::Fixed(count), ..} => Some((name, format!("[{}; {}]", item.path, count))),
+            Slot::List {name, item, ..} => Some((name, format!("Vec<{}>", item.path))),
+            Slot::Switch {name, aux} => Some((name, aux.name.clone())),
+            Slot::Fd {name, list: false} => Some((name, "std::os::fd::OwnedFd".to_string())),
+            Slot::Fd {name, list: true} => Some((name, "Vec<std::os::fd::OwnedFd>".to_string())),
+            _ => None
+        }
+    }
+
+    fn eq(&self) -> bool {
+        match self {
+            Slot::Value {ty, ..} | Slot::List {item: ty, ..} => ty.eq,
+            Slot::Switch {aux, ..} => aux.cases.iter().all(|(_, _, ty)| ty.eq),
+            Slot::Fd {..} => false,
+            _ => true
+        }
+    }
+
+    fn default(&self) -> bool {
+        match self {
+            Slot::Value {ty, ..} => ty.default,
+            Slot::List {item, len: Len::Fixed(count), ..} => item.default && *count <= 32,
+            Slot::Fd {..} => false,
+            _ => true
+        }
+    }
+}
+
+fn size(slots: &[Slot]) -> Option<usize> {
+    slots.iter().try_fold(0, |offset, slot| Some(offset + slot.size(offset)?))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Context {
+    Struct,
+    Request,
+    Reply,
+    Event {sequence: bool},
+    ///An event sent as a `GenericEvent`, which is identified by its extension and event type.
+    GenericEvent,
+    Error
+}
+
+pub(crate) struct Registry<'a> {
+    modules: HashMap<&'a str, &'a Module>,
+    types: HashMap<(String, String), TypeDef>,
+    enums: HashMap<(String, String), EnumDef>,
+    ///The events and errors that are generated, by module and struct name, which copies refer to,
+    ///with whether they are generic events.
+    records: HashMap<(String, String), bool>
+}
+
+impl<'a> Registry<'a> {
+    ///Registers the types of `modules`, which must come after the modules they import.
+    pub(crate) fn new(modules: &'a [Module]) -> Registry<'a> {
+        let mut registry = Registry {
+            modules: modules.iter().map(|module| (module.header.as_str(), module)).collect(),
+            types: HashMap::new(),
+            enums: HashMap::new(),
+            records: HashMap::new()
+        };
+        for module in modules {
+            registry.register(module);
+        }
+        registry.infer_enum_sizes(modules);
+        for module in modules {
+            registry.register_records(module);
+        }
+        registry
+    }
+
+    ///Registers the events and errors of `module` that can be generated, including copies of them.
+    fn register_records(&mut self, module: &Module) {
+        let header = &module.header;
+        for item in &module.items {
+            let record = match item {
+                Item::Event(event) => self.object(header, &event.members, event_context(event)).ok()
+                    .map(|_| (format!("{}Event", type_name(&event.name)), event.xge)),
+                Item::Error(error) => self.object(header, &error.members, Context::Error).ok().map(|_| (format!("{}Error", type_name(&error.name)), false)),
+                Item::EventCopy(copy) => self.resolve_record(header, &copy.reference, "Event").map(|(_, generic)| (format!("{}Event", type_name(&copy.name)), generic)),
+                Item::ErrorCopy(copy) => self.resolve_record(header, &copy.reference, "Error").map(|(_, generic)| (format!("{}Error", type_name(&copy.name)), generic)),
+                _ => None
+            };
+            if let Some((name, generic)) = record {
+                self.records.insert((header.clone(), name), generic);
+            }
+        }
+    }
+
+    ///The path of the event or error struct an `eventcopy` or `errorcopy` refers to, if it is
+    ///generated, and whether it is a generic event.
+    fn resolve_record(&self, module: &str, reference: &str, kind: &str) -> Option<(String, bool)> {
+        let key = match reference.split_once(':') {
+            Some((namespace, name)) => (namespace.to_string(), format!("{}{}", type_name(name), kind)),
+            //The first module that declares it, whether or not it could be generated there.
+            None => std::iter::once(module).chain(self.imports(module))
+                .find(|scope| self.declares(scope, reference, kind))
+                .map(|scope| (scope.to_string(), format!("{}{}", type_name(reference), kind)))?
+        };
+        self.records.get_key_value(&key).map(|((defined_in, name), generic)| (path(defined_in, name, module), *generic))
+    }
+
+    ///Whether `module` has an event or error, depending on `kind`, or a copy of one named `name`.
+    fn declares(&self, module: &str, name: &str, kind: &str) -> bool {
+        self.modules.get(module).is_some_and(|module| module.items.iter().any(|item| match item {
+            Item::Event(event) if kind == "Event" => event.name == name,
+            Item::Error(error) if kind == "Error" => error.name == name,
+            Item::EventCopy(copy) if kind == "Event" => copy.name == name,
+            Item::ErrorCopy(copy) if kind == "Error" => copy.name == name,
+            _ => false
+        }))
+    }
+
+    fn register(&mut self, module: &Module) {
+        let header = &module.header;
+        let type_names: HashSet<String> = module.items.iter().filter_map(|item| match item {
+            Item::XidType(name) | Item::Typedef {new: name, ..} => Some(type_name(name)),
+            Item::Struct(container) | Item::Union(container) => Some(type_name(&container.name)),
+            _ => None
+        }).collect();
+
+        for item in &module.items {
+            if let Item::Enum(values) = item {
+                //Some enums, like `Window` with its `None`, share the name of a type, others, like
+                //`Option` of Present, that of a type the generated code uses.
+                let mut name = type_name(&values.name);
+                if type_names.contains(&name) || RESERVED.contains(&name.as_str()) {
+                    name.push_str("Enum");
+                }
+                let items = values.items.iter().map(|item| (item.name.clone(), item.value)).collect();
+                let repr = Prim::fitting(values.items.iter().map(|item| item.value).max().unwrap_or(0));
+                self.enums.insert((header.clone(), values.name.clone()), EnumDef {module: header.clone(), name, repr, values: items});
+            }
+        }
+
+        for item in &module.items {
+            let (xml, def) = match item {
+                Item::XidType(name) => (name, TypeDef {module: header.clone(), name: type_name(name), prim: Some(Prim::U32), size: Some(4), eq: true, default: true}),
+                Item::Typedef {old, new} => match self.resolve(header, old) {
+                    Some(ty) => (new, TypeDef {module: header.clone(), name: type_name(new), prim: ty.prim, size: ty.size, eq: ty.eq, default: ty.default}),
+                    None => continue
+                },
+                Item::Struct(container) => match self.lower(header, &container.members, Context::Struct) {
+                    Ok(slots) => {
+                        let def = TypeDef {
+                            module: header.clone(),
+                            name: type_name(&container.name),
+                            prim: None,
+                            size: size(&slots),
+                            eq: slots.iter().all(Slot::eq),
+                            default: slots.iter().all(Slot::default)
+                        };
+                        (&container.name, def)
+                    }
+                    Err(_) => continue
+                },
+                Item::Union(container) => match self.union_members(header, container) {
+                    Ok((size, members)) => {
+                        let eq = members.iter().all(|(_, ty)| ty.eq);
+                        (&container.name, TypeDef {module: header.clone(), name: type_name(&container.name), prim: None, size: Some(size), eq, default: true})
+                    }
+                    Err(_) => continue
+                },
+                _ => continue
+            };
+            self.types.insert((header.clone(), xml.clone()), def);
+        }
+    }
+
+    ///Widens every enum to the largest field that refers to it, so it can be that field's type.
+    fn infer_enum_sizes(&mut self, modules: &[Module]) {
+        fn fields<'m>(members: &'m [Member], found: &mut Vec<&'m crate::model::Field>) {
+            for member in members {
+                match member {
+                    Member::Field(field) => found.push(field),
+                    Member::Switch(switch) => switch.cases.iter().for_each(|case| fields(&case.members, found)),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut widths: HashMap<(String, String), Prim> = HashMap::new();
+        for module in modules {
+            let mut found = Vec::new();
+            for item in &module.items {
+                match item {
+                    Item::Struct(container) | Item::Union(container) => fields(&container.members, &mut found),
+                    Item::Request(request) => {
+                        fields(&request.members, &mut found);
+                        fields(request.reply.as_deref().unwrap_or_default(), &mut found);
+                    }
+                    Item::Event(event) | Item::Error(event) => fields(&event.members, &mut found),
+                    _ => {}
+                }
+            }
+            for field in found {
+                let Some(values) = &field.values else { continue };
+                let (Some(key), Some(prim)) = (self.enum_key(&module.header, values), self.resolve(&module.header, &field.ty).and_then(|ty| ty.prim?.unsigned())) else { continue };
+                let width = widths.entry(key).or_insert(prim);
+                *width = (*width).max(prim);
+            }
+        }
+
+        for (key, def) in &mut self.enums {
+            def.repr = match widths.get(key) {
+                Some(&width) => width.max(def.repr),
+                None => def.repr.max(Prim::U32)
+            };
+        }
+    }
+
+    ///Where the type called `name` is looked for from `module`: the module itself, the built in
+    ///types, then the imported modules, of which the core protocol is always one.
+    fn resolve(&self, module: &str, name: &str) -> Option<Ty> {
+        if let Some((namespace, name)) = name.split_once(':') {
+            return self.types.get(&(namespace.to_string(), name.to_string())).map(|def| def.ty(module));
+        }
+        if let Some(def) = self.types.get(&(module.to_string(), name.to_string())) {
+            return Some(def.ty(module));
+        }
+        if let Some(prim) = Prim::builtin(name) {
+            return Some(Ty::prim(prim));
+        }
+        self.imports(module).into_iter()
+            .find_map(|import| self.types.get(&(import.to_string(), name.to_string())))
+            .map(|def| def.ty(module))
+    }
+
+    fn enum_key(&self, module: &str, name: &str) -> Option<(String, String)> {
+        let key = match name.split_once(':') {
+            Some((namespace, name)) => (namespace.to_string(), name.to_string()),
+            None => std::iter::once(module).chain(self.imports(module))
+                .map(|scope| (scope.to_string(), name.to_string()))
+                .find(|key| self.enums.contains_key(key))?
+        };
+        self.enums.contains_key(&key).then_some(key)
+    }
+
+    fn resolve_enum(&self, module: &str, name: &str) -> Option<&EnumDef> {
+        self.enum_key(module, name).and_then(|key| self.enums.get(&key))
+    }
+
+    fn imports(&self, module: &str) -> Vec<&str> {
+        let mut imports: Vec<&str> = self.modules.get(module).map(|module| module.imports.iter().map(String::as_str).collect()).unwrap_or_default();
+        if module != "xproto" && !imports.contains(&"xproto") {
+            imports.push("xproto");
+        }
+        imports
+    }
+
+    ///The type of a field, which is its enum if the enum is made of the same number.
+    fn field_type(&self, module: &str, ty: &str, values: Option<&str>) -> Result<Ty, String> {
+        let ty = self.resolve(module, ty).ok_or_else(|| format!("unknown type `{}`", ty))?;
+        let Some(values) = values.and_then(|values| self.resolve_enum(module, values)) else { return Ok(ty) };
+        if ty.prim.is_some() && ty.prim == Some(values.repr) {
+            return Ok(Ty {path: path(&values.module, &values.name, module), is_enum: true, ..ty});
+        }
+        Ok(ty)
+    }
+
+    ///Turns the members of a struct, request, reply, event or error into slots, without the header.
+    fn lower(&self, module: &str, members: &[Member], context: Context) -> Result<Vec<Slot>, String> {
+        //Fields that only give the length of one list, or the mask of a switch, are not stored.
+        let mut references: HashMap<&str, usize> = HashMap::new();
+        let mut sole_references: HashMap<&str, Source> = HashMap::new();
+        for member in members {
+            match member {
+                Member::List {name, len: Some(len), ..} => {
+                    count_references(len, &mut references);
+                    if let Expr::FieldRef(field) = len {
+                        sole_references.insert(field, Source::Length(field_name(name)));
+                    }
+                }
+                Member::ExprField {expr, ..} => count_references(expr, &mut references),
+                Member::Switch(switch) => {
+                    count_references(&switch.expr, &mut references);
+                    if let Expr::FieldRef(field) = &switch.expr {
+                        sole_references.insert(field, Source::Mask(field_name(&switch.name)));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut slots = Vec::new();
+        for (i, member) in members.iter().enumerate() {
+            let last = i + 1 == members.len();
+            let slot = match member {
+                Member::Pad(bytes) => Slot::Pad(*bytes),
+                Member::Align(align) => Slot::Align(*align),
+                Member::Field(field) => {
+                    let ty = self.field_type(module, &field.ty, field.values.as_deref())?;
+                    let source = match sole_references.get(field.name.as_str()) {
+                        Some(Source::Mask(switch)) if references.get(field.name.as_str()) == Some(&1) => Source::Mask(switch.clone()),
+                        Some(source) if references.get(field.name.as_str()) == Some(&1) && ty.prim.is_some() && !ty.is_enum => source.clone(),
+                        _ => Source::Stored
+                    };
+                    Slot::Value {xml: field.name.clone(), name: field_name(&field.name), ty, source}
+                }
+                Member::List {name, ty, len} if ty == "fd" => match context {
+                    Context::Request => Slot::Fd {name: field_name(name), list: true},
+                    //The server sends a number of descriptors that is only known once the reply is read.
+                    Context::Reply => return Err(format!("sends back a list of file descriptors `{}`", name)),
+                    _ => return Err(format!("list of file descriptors `{}`", name))
+                },
+                Member::List {name, ty, len} => {
+                    let item = self.resolve(module, ty).ok_or_else(|| format!("unknown type `{}`", ty))?;
+                    let len = match len {
+                        Some(Expr::Value(count)) => Len::Fixed(*count as usize),
+                        Some(len) => {
+                            self.check(module, len)?;
+                            Len::Count(len.clone())
+                        }
+                        None if context == Context::Request && last => Len::Rest,
+                        None => return Err(format!("list `{}` has no length", name))
+                    };
+                    Slot::List {xml: name.clone(), name: field_name(name), item, len}
+                }
+                Member::ExprField {name, ty, expr} if context == Context::Request => {
+                    self.check(module, expr)?;
+                    let ty = self.resolve(module, ty).ok_or_else(|| format!("unknown type `{}`", ty))?;
+                    Slot::Value {xml: name.clone(), name: field_name(name), ty, source: Source::Expr(expr.clone())}
+                }
+                Member::Switch(switch) if context == Context::Request && last => Slot::Switch {name: field_name(&switch.name), aux: self.aux(module, switch, members)?},
+                Member::ExprField {..} | Member::Switch(_) => return Err("switches and computed fields are only supported at the end of requests".to_string()),
+                Member::Fd(name) if context == Context::Request => Slot::Fd {name: field_name(name), list: false},
+                //They are not part of the bytes of the reply, but handed out next to it.
+                Member::Fd(_) if context == Context::Reply => continue,
+                Member::Fd(name) => return Err(format!("file descriptor `{}` outside of a request or reply", name))
+            };
+            slots.push(slot);
+        }
+        Ok(slots)
+    }
+
+    ///The struct of optional values for a switch over a mask.
+    fn aux(&self, module: &str, switch: &Switch, members: &[Member]) -> Result<Aux, String> {
+        let Expr::FieldRef(mask) = &switch.expr else { return Err("switch over an expression".to_string()) };
+        let mask = members.iter().find_map(|member| match member {
+            Member::Field(field) if &field.name == mask => self.resolve(module, &field.ty)?.prim,
+            _ => None
+        }).ok_or_else(|| format!("switch over unknown field `{}`", mask))?;
+        if switch.has_cases {
+            return Err("switch with `case`".to_string());
+        }
+
+        let mut cases = Vec::new();
+        for BitCase {bits, members} in &switch.cases {
+            let [Member::Field(field)] = members.as_slice() else { return Err("bitcase with more than one field".to_string()) };
+            let mut value = 0;
+            for bit in bits {
+                value |= self.constant(module, bit)?;
+            }
+            let ty = self.field_type(module, &field.ty, field.values.as_deref())?;
+            cases.push((value, field_name(&field.name), ty));
+        }
+        Ok(Aux {name: String::new(), mask, cases})
+    }
+
+    ///Rejects expressions that cannot be turned into code.
+    fn check(&self, module: &str, expr: &Expr) -> Result<(), String> {
+        match expr {
+            Expr::FieldRef(_) | Expr::Value(_) => Ok(()),
+            Expr::Op(op, left, right) if ["+", "-", "*", "/", "&", "<<"].contains(&op.as_str()) => {
+                self.check(module, left)?;
+                self.check(module, right)
+            }
+            Expr::Unop(op, operand) if op == "~" => self.check(module, operand),
+            Expr::Popcount(operand) => self.check(module, operand),
+            Expr::EnumRef {..} => self.constant(module, expr).map(|_| ()),
+            Expr::Op(op, ..) | Expr::Unop(op, _) => Err(format!("operator `{}` is not supported", op)),
+            Expr::Unsupported(what) => Err(format!("{} is not supported", what))
+        }
+    }
+
+    ///The value of an expression that refers to no fields.
+    fn constant(&self, module: &str, expr: &Expr) -> Result<u64, String> {
+        match expr {
+            Expr::Value(value) => Ok(*value),
+            Expr::EnumRef {values, item} => self.resolve_enum(module, values)
+                .and_then(|def| def.values.get(item).copied())
+                .ok_or_else(|| format!("unknown enum item `{}::{}`", values, item)),
+            _ => Err("non-constant bitcase".to_string())
+        }
+    }
+
+    ///The size of a union and the types of its members.
+    fn union_members(&self, module: &str, container: &Container) -> Result<(usize, Vec<(String, Ty)>), String> {
+        let mut members = Vec::new();
+        for slot in self.lower(module, &container.members, Context::Struct)? {
+            let member = match slot {
+                Slot::Value {name, ty, ..} => (name, ty),
+                Slot::List {name, item, len: Len::Fixed(count), ..} => {
+                    let ty = Ty {
+                        path: format!("[{}; {}]", item.path, count),
+                        size: item.size.map(|size| size * count),
+                        default: item.default && count <= 32,
+                        ..item
+                    };
+                    (name, ty)
+                }
+                _ => return Err("union member of varying size".to_string())
+            };
+            members.push(member);
+        }
+        let size = members.iter().map(|(_, ty)| ty.size.ok_or("union member of varying size")).try_fold(0, |max, size| size.map(|size| max.max(size)))?;
+        Ok((size, members))
+    }
+
+    ///The slots of a struct, request, reply, event or error including its header.
+    fn object(&self, module: &str, members: &[Member], context: Context) -> Result<Vec<Slot>, String> {
+        let mut slots = self.lower(module, members, context)?;
+        let header = |xml: &str, prim: Prim, source: Source| Slot::Value {xml: xml.to_string(), name: xml.to_string(), ty: Ty::prim(prim), source};
+        //Replies and events put their first field into the byte before the sequence number if it fits.
+        let gap = |slots: &mut Vec<Slot>| if slots.first().is_some_and(Slot::is_one_byte) { slots.remove(0) } else { Slot::Pad(1) };
+        let slots = match context {
+            Context::Struct | Context::Request => slots,
+            Context::Reply => {
+                let first = gap(&mut slots);
+                [vec![Slot::Byte(1), first, header("sequence", Prim::U16, Source::Stored), header("length", Prim::U32, Source::ReplyLength)], slots].concat()
+            }
+            Context::Event {sequence: false} => [vec![header("response_type", Prim::U8, Source::Stored)], slots].concat(),
+            Context::Event {sequence: true} => {
+                let first = gap(&mut slots);
+                [vec![header("response_type", Prim::U8, Source::Stored), first, header("sequence", Prim::U16, Source::Stored)], slots].concat()
+            }
+            Context::GenericEvent => {
+                let fixed = vec![
+                    header("response_type", Prim::U8, Source::Stored),
+                    header("extension", Prim::U8, Source::Stored),
+                    header("sequence", Prim::U16, Source::Stored),
+                    header("length", Prim::U32, Source::ReplyLength),
+                    header("event_type", Prim::U16, Source::Stored)
+                ];
+                [fixed, slots].concat()
+            }
+            Context::Error => [vec![Slot::Byte(0), header("error_code", Prim::U8, Source::Stored), header("sequence", Prim::U16, Source::Stored)], slots].concat()
+        };
+
+        //Lengths are decoded from fields before them, computed fields of requests may use any field.
+        let mut names = HashSet::new();
+        for slot in &slots {
+            let refs = match slot {
+                Slot::List {len: Len::Count(len), ..} if context != Context::Request => references_of(len),
+                Slot::Value {source: Source::Expr(expr), ..} => {
+                    if let Some(missing) = references_of(expr).into_iter().find(|name| encoded_field(&slots, name).is_none()) {
+                        return Err(format!("computed field refers to unknown `{}`", missing));
+                    }
+                    Vec::new()
+                }
+                _ => Vec::new()
+            };
+            if let Some(missing) = refs.iter().find(|name| !names.contains(**name)) {
+                return Err(format!("length refers to `{}`, which is not a field before it", missing));
+            }
+            if let Some((name, _)) = slot.stored() {
+                if !names.insert(name.to_string()) {
+                    return Err(format!("field `{}` appears twice", name));
+                }
+            }
+            if let Slot::Value {xml, ..} = slot {
+                names.insert(xml.clone());
+            }
+        }
+        Ok(slots)
+    }
+}
+
+fn count_references<'m>(expr: &'m Expr, references: &mut HashMap<&'m str, usize>) {
+    match expr {
+        Expr::FieldRef(name) => *references.entry(name).or_default() += 1,
+        Expr::Op(_, left, right) => {
+            count_references(left, references);
+            count_references(right, references);
+        }
+        Expr::Unop(_, operand) | Expr::Popcount(operand) => count_references(operand, references),
+        _ => {}
+    }
+}
+
+fn references_of(expr: &Expr) -> Vec<&str> {
+    let mut references = HashMap::new();
+    count_references(expr, &mut references);
+    references.into_keys().collect()
+}
+
+///Writes the code of one module.
+pub(crate) struct Emitter<'r, 'a> {
+    registry: &'r Registry<'a>,
+    module: &'a Module,
+    out: String,
+    ///The helpers of the parent module that the code uses.
+    uses: BTreeSet<&'static str>,
+    ///The type names defined so far, to catch collisions.
+    defined: HashSet<String>,
+    ///What could not be generated, with the reason.
+    pub(crate) skipped: Vec<String>
+}
+
+impl<'r, 'a> Emitter<'r, 'a> {
+    pub(crate) fn new(registry: &'r Registry<'a>, module: &'a Module) -> Self {
+        Emitter {registry, module, out: String::new(), uses: BTreeSet::new(), defined: HashSet::new(), skipped: Vec::new()}
+    }
+
+    ///The code of the module, to be included into a `mod` item.
+    pub(crate) fn emit(mut self) -> (String, Vec<String>) {
+        if let Some(extension) = &self.module.extension {
+            writeln!(self.out, "///The name of the extension, to look it up with `QueryExtension`.").unwrap();
+            writeln!(self.out, "pub const EXTENSION_NAME: &[u8] = b\"{}\";", extension.xname).unwrap();
+            writeln!(self.out, "///The version of the extension these bindings describe.").unwrap();
+            writeln!(self.out, "pub const MAJOR_VERSION: u32 = {};", extension.major_version).unwrap();
+            writeln!(self.out, "///The minor version of the extension these bindings describe.").unwrap();
+            writeln!(self.out, "pub const MINOR_VERSION: u32 = {};\n", extension.minor_version).unwrap();
+        }
+
+        for item in &self.module.items {
+            let result = match item {
+                Item::XidType(name) => self.alias(name, "u32"),
+                Item::Typedef {old, new} => match self.registry.resolve(&self.module.header, old) {
+                    Some(ty) => self.alias(new, &ty.path),
+                    None => Err(format!("unknown type `{}`", old))
+                },
+                Item::Enum(values) => self.enumeration(values),
+                Item::Struct(container) => self.structure(container),
+                Item::Union(container) => self.union(container),
+                Item::Request(request) => self.request(request),
+                Item::Event(event) => self.event(event, "Event"),
+                Item::Error(error) => self.event(error, "Error"),
+                Item::EventCopy(copy) => self.copy(copy, "Event"),
+                Item::ErrorCopy(copy) => self.copy(copy, "Error"),
+                Item::Unsupported {reason, ..} => Err(reason.clone())
+            };
+            if let Err(reason) = result {
+                self.skipped.push(format!("`{}`: {}", item_name(item), reason));
+            }
+        }
+
+        let mut header = String::from("#[allow(unused_imports)]\nuse crate::serializable::{list_bytes, ByteOrder, ParseError, Serializable};\n");
+        if !self.uses.is_empty() {
+            let uses: Vec<&str> = self.uses.iter().copied().collect();
+            writeln!(header, "use super::{{{}}};", uses.join(", ")).unwrap();
+        }
+        header.push('\n');
+        (header + &self.out, self.skipped)
+    }
+
+    fn define(&mut self, name: &str) -> Result<(), String> {
+        if RESERVED.contains(&name) {
+            return Err(format!("`{}` would hide a type the generated code uses", name));
+        }
+        if self.defined.insert(name.to_string()) { Ok(()) } else { Err(format!("`{}` is already defined", name)) }
+    }
+
+    fn doc(&mut self, doc: &Option<String>) {
+        if let Some(doc) = doc {
+            let mut chars = doc.chars();
+            let first: String = chars.next().map(|c| c.to_uppercase().collect()).unwrap_or_default();
+            writeln!(self.out, "///{}{}", first, chars.as_str()).unwrap();
+        }
+    }
+
+    fn alias(&mut self, name: &str, target: &str) -> Result<(), String> {
+        let name = type_name(name);
+        self.define(&name)?;
+        writeln!(self.out, "pub type {} = {};\n", name, target).unwrap();
+        Ok(())
+    }
+
+    fn enumeration(&mut self, values: &crate::model::Enum) -> Result<(), String> {
+        let def = self.registry.resolve_enum(&self.module.header, &values.name).expect("every enum is registered");
+        let (name, repr) = (def.name.clone(), def.repr.rust());
+        self.define(&name)?;
+        let mut consts = HashSet::new();
+        let mut items = String::new();
+        for item in &values.items {
+            let item_name = const_name(&item.name);
+            if !consts.insert(item_name.clone()) {
+                return Err(format!("two items are called `{}`", item_name));
+            }
+            let value = match item.value.trailing_zeros() {
+                bit if item.bit && bit > 0 => format!("1 << {}", bit),
+                _ => item.value.to_string()
+            };
+            writeln!(items, "    pub const {}: Self = Self({});", item_name, value).unwrap();
+        }
+        let is_mask = values.items.iter().any(|item| item.bit);
+
+        self.doc(&values.doc);
+        let o = &mut self.out;
+        writeln!(o, "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]").unwrap();
+        writeln!(o, "pub struct {}(pub {});\n", name, repr).unwrap();
+        writeln!(o, "impl {} {{\n{}", name, items).unwrap();
+        if is_mask {
+            writeln!(o, "    ///Whether all bits of `other` are set.").unwrap();
+            writeln!(o, "    pub fn contains(self, other: Self) -> bool {{\n        self.0 & other.0 == other.0\n    }}").unwrap();
+        }
+        writeln!(o, "}}\n").unwrap();
+        writeln!(o, "impl From<{0}> for {1} {{\n    fn from(value: {0}) -> Self {{\n        value.0\n    }}\n}}\n", name, repr).unwrap();
+        writeln!(o, "impl From<{1}> for {0} {{\n    fn from(value: {1}) -> Self {{\n        Self(value)\n    }}\n}}\n", name, repr).unwrap();
+        writeln!(o, "impl Serializable for {} {{", name).unwrap();
+        writeln!(o, "    fn bytes(&self, order: ByteOrder) -> Vec<u8> {{\n        self.0.bytes(order)\n    }}\n").unwrap();
+        writeln!(o, "    fn from_bytes(bytes: &[u8], order: ByteOrder) -> Result<(Self, usize), ParseError> {{").unwrap();
+        writeln!(o, "        let (value, consumed) = {}::from_bytes(bytes, order)?;\n        Ok((Self(value), consumed))\n    }}\n}}\n", repr).unwrap();
+        if is_mask {
+            writeln!(o, "impl std::ops::BitOr for {} {{\n    type Output = Self;\n\n    fn bitor(self, other: Self) -> Self {{\n        Self(self.0 | other.0)\n    }}\n}}\n", name).unwrap();
+            writeln!(o, "impl std::ops::BitOrAssign for {} {{\n    fn bitor_assign(&mut self, other: Self) {{\n        self.0 |= other.0;\n    }}\n}}\n", name).unwrap();
+        }
+        Ok(())
+    }
+
+    fn structure(&mut self, container: &Container) -> Result<(), String> {
+        let slots = self.registry.object(&self.module.header, &container.members, Context::Struct)?;
+        let name = type_name(&container.name);
+        self.define(&name)?;
+        self.record(&name, &container.doc, &slots, Context::Struct);
+        Ok(())
+    }
+
+    fn union(&mut self, container: &Container) -> Result<(), String> {
+        let (size, members) = self.registry.union_members(&self.module.header, container)?;
+        let name = type_name(&container.name);
+        self.define(&name)?;
+        self.uses.insert("read");
+        self.doc(&container.doc);
+        let o = &mut self.out;
+        writeln!(o, "///The raw bytes of the union, read as one of its members with the methods named after them.").unwrap();
+        //The standard library only implements `Default` for arrays of up to 32 elements.
+        if size <= 32 {
+            writeln!(o, "#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]\npub struct {}(pub [u8; {}]);\n", name, size).unwrap();
+        }else {
+            writeln!(o, "#[derive(Debug, Clone, Copy, PartialEq, Eq)]\npub struct {}(pub [u8; {}]);\n", name, size).unwrap();
+            writeln!(o, "impl Default for {} {{\n    fn default() -> Self {{\n        Self([0; {}])\n    }}\n}}\n", name, size).unwrap();
+        }
+        writeln!(o, "impl Serializable for {} {{", name).unwrap();
+        writeln!(o, "    fn bytes(&self, _: ByteOrder) -> Vec<u8> {{\n        self.0.to_vec()\n    }}\n").unwrap();
+        writeln!(o, "    fn from_bytes(bytes: &[u8], order: ByteOrder) -> Result<(Self, usize), ParseError> {{").unwrap();
+        writeln!(o, "        let mut offset = 0;\n        Ok((Self(read(bytes, &mut offset, order)?), offset))\n    }}\n}}\n").unwrap();
+        writeln!(o, "impl {} {{", name).unwrap();
+        for (i, (member, ty)) in members.iter().enumerate() {
+            if i > 0 {
+                writeln!(o).unwrap();
+            }
+            writeln!(o, "    ///Reads the union as `{}`.", member).unwrap();
+            writeln!(o, "    pub fn {}(&self, order: ByteOrder) -> {} {{", member, ty.path).unwrap();
+            writeln!(o, "        read(&self.0, &mut 0, order).expect(\"every member fits into the union\")\n    }}\n").unwrap();
+            writeln!(o, "    ///A union holding `{}`.", member).unwrap();
+            writeln!(o, "    pub fn from_{0}({0}: &{1}, order: ByteOrder) -> Self {{", member, ty.path).unwrap();
+            writeln!(o, "        let mut bytes = [0; {}];\n        let encoded = {}.bytes(order);", size, member).unwrap();
+            writeln!(o, "        bytes[..encoded.len()].copy_from_slice(&encoded);\n        Self(bytes)\n    }}").unwrap();
+        }
+        writeln!(o, "}}\n").unwrap();
+        Ok(())
+    }
+
+    fn event(&mut self, event: &Event, kind: &str) -> Result<(), String> {
+        let context = if kind == "Event" { event_context(event) } else { Context::Error };
+        let slots = self.registry.object(&self.module.header, &event.members, context)?;
+        let name = format!("{}{}", type_name(&event.name), kind);
+        self.define(&name)?;
+        self.number(&event.name, kind, event.number, event.xge);
+        self.record(&name, &event.doc, &slots, context);
+        Ok(())
+    }
+
+    fn copy(&mut self, copy: &crate::model::Copy, kind: &str) -> Result<(), String> {
+        let (original, generic) = self.registry.resolve_record(&self.module.header, &copy.reference, kind)
+            .ok_or_else(|| format!("copy of `{}`, which was not generated", copy.reference))?;
+        let name = format!("{}{}", type_name(&copy.name), kind);
+        self.define(&name)?;
+        self.number(&copy.name, kind, copy.number, generic);
+        writeln!(self.out, "pub type {} = {};\n", name, original).unwrap();
+        Ok(())
+    }
+
+    ///The constant with the number of an event or error, which is the `event_type` of the generic
+    ///events of extensions. The core protocol's `GeGeneric` has its response type instead.
+    fn number(&mut self, name: &str, kind: &str, number: u8, generic: bool) {
+        let ty = if generic && self.module.extension.is_some() { "u16" } else { "u8" };
+        writeln!(self.out, "pub const {}_{}: {} = {};\n", const_name(name), kind.to_ascii_uppercase(), ty, number).unwrap();
+    }
+
+    fn request(&mut self, request: &Request) -> Result<(), String> {
+        let header = &self.module.header;
+        let core = self.module.extension.is_none();
+        let mut slots = self.registry.object(header, &request.members, Context::Request)?;
+        //Core requests put their first field into the byte that holds the minor opcode of extension requests.
+        if core && slots.first().is_some_and(|first| !first.is_one_byte()) {
+            return Err("the first field of a core request does not fit into one byte".to_string());
+        }
+        let reply = match &request.reply {
+            Some(reply) => Some(self.registry.object(header, reply, Context::Reply)?),
+            None => None
+        };
+        let name = format!("{}Request", type_name(&request.name));
+        let reply_name = format!("{}Reply", type_name(&request.name));
+        let aux_name = format!("{}Aux", type_name(&request.name));
+        self.define(&name)?;
+        if reply.is_some() {
+            self.define(&reply_name)?;
+        }
+
+        if let Some(Slot::Switch {aux, ..}) = slots.last_mut() {
+            self.define(&aux_name)?;
+            aux.name = aux_name;
+            self.aux(aux, &request.name);
+        }
+
+        self.doc(&request.doc);
+        self.definition(&name, &slots);
+        let mut body = String::new();
+        if core {
+            body.push_str("        let mut out = vec![major_opcode];\n");
+            if let Some(first) = slots.first() {
+                self.encode(&mut body, first, &slots);
+            }
+            body.push_str("        out.extend([0, 0]);\n");
+        }else {
+            body.push_str("        let mut out = vec![major_opcode, Self::OPCODE, 0, 0];\n");
+        }
+        for slot in slots.iter().skip(core as usize) {
+            self.encode(&mut body, slot, &slots);
+        }
+        body.push_str("        out.resize(out.len().next_multiple_of(4), 0);\n        out\n");
+        let reply_fds = request.reply.iter().flatten().filter(|member| matches!(member, Member::Fd(_))).count();
+
+        self.uses.insert("Request");
+        let o = &mut self.out;
+        writeln!(o, "impl Request for {} {{", name).unwrap();
+        writeln!(o, "    const EXTENSION: Option<&'static [u8]> = {};", if core { "None" } else { "Some(EXTENSION_NAME)" }).unwrap();
+        writeln!(o, "    const OPCODE: u8 = {};", request.opcode).unwrap();
+        writeln!(o, "    const HAS_REPLY: bool = {};", reply.is_some()).unwrap();
+        if reply_fds > 0 {
+            writeln!(o, "    const REPLY_FDS: usize = {};", reply_fds).unwrap();
+        }
+        writeln!(o, "    type Reply = {};\n", if reply.is_some() { reply_name.as_str() } else { "()" }).unwrap();
+        writeln!(o, "    fn encode(&self, major_opcode: u8, {}: ByteOrder) -> Vec<u8> {{\n{}    }}", order_param(&body), body).unwrap();
+        let fds: Vec<(&str, bool)> = slots.iter().filter_map(|slot| match slot {
+            Slot::Fd {name, list} => Some((name.as_str(), *list)),
+            _ => None
+        }).collect();
+        if !fds.is_empty() {
+            let fds = if fds.iter().all(|(_, list)| !list) {
+                let fds: Vec<String> = fds.iter().map(|(name, _)| format!("std::os::fd::AsFd::as_fd(&self.{})", name)).collect();
+                format!("vec![{}]", fds.join(", "))
+            }else {
+                let fds: Vec<String> = fds.iter().map(|(name, list)| match list {
+                    true => format!("self.{}.iter().map(std::os::fd::AsFd::as_fd)", name),
+                    false => format!("std::iter::once(std::os::fd::AsFd::as_fd(&self.{}))", name)
+                }).collect();
+                format!("{}.collect()", fds.join(".chain(") + &")".repeat(fds.len() - 1))
+            };
+            writeln!(o, "\n    fn fds(&self) -> Vec<std::os::fd::BorrowedFd<'_>> {{\n        {}\n    }}", fds).unwrap();
+        }
+        writeln!(o, "}}\n").unwrap();
+
+        if let Some(reply) = reply {
+            writeln!(self.out, "///The reply to a [`{}`].", name).unwrap();
+            if reply_fds > 0 {
+                writeln!(self.out, "///").unwrap();
+                writeln!(self.out, "///The descriptors the server passes along with it are handed out by").unwrap();
+                writeln!(self.out, "///[`XInterface::reply_with_fds`](crate::xinterface::XInterface::reply_with_fds).").unwrap();
+            }
+            self.record(&reply_name, &None, &reply, Context::Reply);
+        }
+        Ok(())
+    }
+
+    fn aux(&mut self, aux: &Aux, request: &str) {
+        let mask = aux.mask.rust();
+        let o = &mut self.out;
+        writeln!(o, "///The optional values of a `{}` request, each sent only if it is set.", request).unwrap();
+        writeln!(o, "#[derive(Debug, Clone, PartialEq{}, Default)]", if aux.cases.iter().all(|(_, _, ty)| ty.eq) { ", Eq" } else { "" }).unwrap();
+        writeln!(o, "pub struct {} {{", aux.name).unwrap();
+        for (_, name, ty) in &aux.cases {
+            writeln!(o, "    pub {}: Option<{}>,", name, ty.path).unwrap();
+        }
+        writeln!(o, "}}\n\nimpl {} {{", aux.name).unwrap();
+        writeln!(o, "    ///The bits of the values that are set, sent in front of them.").unwrap();
+        writeln!(o, "    pub fn mask(&self) -> {} {{\n        let mut mask = 0;", mask).unwrap();
+        for (bits, name, _) in &aux.cases {
+            writeln!(o, "        if self.{}.is_some() {{\n            mask |= {};\n        }}", name, bits).unwrap();
+        }
+        writeln!(o, "        mask\n    }}\n").unwrap();
+        writeln!(o, "    fn bytes(&self, order: ByteOrder) -> Vec<u8> {{\n        let mut out = Vec::new();").unwrap();
+        for (_, name, _) in &aux.cases {
+            writeln!(o, "        if let Some(value) = &self.{} {{\n            out.extend(value.bytes(order));\n        }}", name).unwrap();
+        }
+        writeln!(o, "        out\n    }}\n}}\n").unwrap();
+    }
+
+    ///The struct with the stored fields of `slots`.
+    fn definition(&mut self, name: &str, slots: &[Slot]) {
+        let eq = if slots.iter().all(Slot::eq) { ", Eq" } else { "" };
+        let default = if slots.iter().all(Slot::default) { ", Default" } else { "" };
+        //Descriptors can be neither copied nor compared.
+        if slots.iter().any(|slot| matches!(slot, Slot::Fd {..})) {
+            writeln!(self.out, "#[derive(Debug)]").unwrap();
+        }else {
+            writeln!(self.out, "#[derive(Debug, Clone, PartialEq{}{})]", eq, default).unwrap();
+        }
+        let fields: Vec<(&str, String)> = slots.iter().filter_map(Slot::stored).collect();
+        if fields.is_empty() {
+            writeln!(self.out, "pub struct {};\n", name).unwrap();
+            return;
+        }
+        writeln!(self.out, "pub struct {} {{", name).unwrap();
+        for (field, ty) in fields {
+            writeln!(self.out, "    pub {}: {},", field, ty).unwrap();
+        }
+        writeln!(self.out, "}}\n").unwrap();
+    }
+
+    ///A struct, reply, event or error with its `Serializable` implementation.
+    fn record(&mut self, name: &str, doc: &Option<String>, slots: &[Slot], context: Context) {
+        self.doc(doc);
+        self.definition(name, slots);
+
+        let mut encode = String::from("        let mut out = Vec::new();\n");
+        let mut decode = String::from("        let mut offset = 0;\n");
+        let values = values(slots);
+        for slot in slots {
+            self.encode(&mut encode, slot, slots);
+            self.decode(&mut decode, slot, &values);
+        }
+        match context {
+            Context::Reply | Context::GenericEvent => {
+                encode.push_str("        out.resize(out.len().max(32).next_multiple_of(4), 0);\n");
+                encode.push_str("        let length = ((out.len() - 32) / 4) as u32;\n        out[4..8].copy_from_slice(&length.bytes(order));\n");
+                decode.push_str("        let offset = end(bytes, offset, 32 + 4 * length as usize)?;\n");
+                self.uses.insert("end");
+            }
+            Context::Event {..} | Context::Error => {
+                encode.push_str("        out.resize(out.len().max(32), 0);\n");
+                decode.push_str("        let offset = end(bytes, offset, 32)?;\n");
+                self.uses.insert("end");
+            }
+            Context::Struct | Context::Request => {}
+        }
+        encode.push_str("        out\n");
+        let fields: Vec<String> = slots.iter().filter_map(Slot::stored).map(|(name, _)| match local(name) {
+            renamed if renamed != name => format!("{}: {}", name, renamed),
+            _ => name.to_string()
+        }).collect();
+        if fields.is_empty() {
+            writeln!(decode, "        Ok(({}, offset))", name).unwrap();
+        }else {
+            writeln!(decode, "        Ok(({} {{{}}}, offset))", name, fields.join(", ")).unwrap();
+        }
+
+        let o = &mut self.out;
+        writeln!(o, "impl Serializable for {} {{", name).unwrap();
+        writeln!(o, "    fn bytes(&self, {}: ByteOrder) -> Vec<u8> {{\n{}    }}\n", order_param(&encode), encode).unwrap();
+        writeln!(o, "    fn from_bytes(bytes: &[u8], {}: ByteOrder) -> Result<(Self, usize), ParseError> {{\n{}    }}\n}}\n", order_param(&decode), decode).unwrap();
+    }
+
+    fn encode(&mut self, out: &mut String, slot: &Slot, slots: &[Slot]) {
+        let line = match slot {
+            Slot::Byte(value) => format!("out.push({});", value),
+            Slot::Pad(1) => "out.push(0);".to_string(),
+            Slot::Pad(bytes) => format!("out.extend([0; {}]);", bytes),
+            Slot::Align(align) => format!("out.resize(out.len().next_multiple_of({}), 0);", align),
+            Slot::Value {name, source: Source::Stored, ..} => format!("out.extend(self.{}.bytes(order));", name),
+            Slot::Value {ty, source: Source::Length(list), ..} => format!("out.extend((self.{}.len() as {}).bytes(order));", list, ty.prim.map_or("u32", Prim::rust)),
+            Slot::Value {source: Source::Mask(switch), ..} => format!("out.extend(self.{}.mask().bytes(order));", switch),
+            Slot::Value {ty, source: Source::Expr(expr), ..} => {
+                let value = self.expr(expr, &|field| encoded_field(slots, field));
+                match ty.prim {
+                    Some(Prim::Bool) => format!("out.extend(({} != 0).bytes(order));", value),
+                    prim => format!("out.extend(({} as {}).bytes(order));", value, prim.map_or("u32", Prim::rust))
+                }
+            }
+            Slot::Value {source: Source::ReplyLength, ..} => "out.extend(0u32.bytes(order));".to_string(),
+            Slot::List {name, item, len: Len::Count(_) | Len::Rest, ..} if item.is_byte() => format!("out.extend_from_slice(&self.{});", name),
+            Slot::List {name, len: Len::Fixed(_), ..} => format!("out.extend(self.{}.bytes(order));", name),
+            Slot::List {name, ..} => format!("out.extend(list_bytes(&self.{}, order));", name),
+            Slot::Switch {name, ..} => format!("out.extend(self.{}.bytes(order));", name),
+            Slot::Fd {..} => return
+        };
+        writeln!(out, "        {}", line).unwrap();
+    }
+
+    fn decode(&mut self, out: &mut String, slot: &Slot, values: &HashMap<&str, (&str, &Ty)>) {
+        let line = match slot {
+            Slot::Byte(_) => {
+                self.uses.insert("skip");
+                "skip(bytes, &mut offset, 1)?;".to_string()
+            }
+            Slot::Pad(bytes) => {
+                self.uses.insert("skip");
+                format!("skip(bytes, &mut offset, {})?;", bytes)
+            }
+            Slot::Align(align) => {
+                self.uses.insert("align");
+                format!("align(bytes, &mut offset, {})?;", align)
+            }
+            Slot::Value {name, ty, ..} => {
+                self.uses.insert("read");
+                format!("let {}: {} = read(bytes, &mut offset, order)?;", local(name), ty.path)
+            }
+            Slot::List {name, item, len: Len::Fixed(count), ..} => {
+                self.uses.insert("read");
+                format!("let {}: [{}; {}] = read(bytes, &mut offset, order)?;", local(name), item.path, count)
+            }
+            Slot::List {name, item, len: Len::Count(len), ..} => {
+                let count = self.expr(len, &|field| {
+                    let (name, ty) = values.get(field)?;
+                    Some(cast(&local(name), ty))
+                });
+                if item.is_byte() {
+                    self.uses.insert("read_bytes");
+                    format!("let {} = read_bytes(bytes, &mut offset, {})?.to_vec();", local(name), count)
+                }else {
+                    self.uses.insert("read_list");
+                    format!("let {}: Vec<{}> = read_list(bytes, &mut offset, {}, order)?;", local(name), item.path, count)
+                }
+            }
+            Slot::List {len: Len::Rest, ..} | Slot::Switch {..} | Slot::Fd {..} => unreachable!("only requests have these, and they are not decoded")
+        };
+        writeln!(out, "        {}", line).unwrap();
+    }
+
+    ///An expression as a `usize`, with `field` giving the code for the fields it refers to.
+    fn expr(&self, expr: &Expr, field: &dyn Fn(&str) -> Option<String>) -> String {
+        let operand = |expr: &Expr| {
+            let code = self.expr(expr, field);
+            if code.contains(' ') { format!("({})", code) } else { code }
+        };
+        if let Some((dividend, divisor)) = rounded_up(expr) {
+            return format!("{}.div_ceil({})", operand(dividend), divisor);
+        }
+        match expr {
+            Expr::FieldRef(name) => field(name).unwrap_or_else(|| panic!("`{}` was checked to be a field", name)),
+            Expr::Value(value) => value.to_string(),
+            //A literal needs its type to have methods called on it.
+            Expr::Op(op, left, right) if op == "-" => match **left {
+                Expr::Value(value) => format!("{}usize.saturating_sub({})", value, self.expr(right, field)),
+                _ => format!("{}.saturating_sub({})", operand(left), self.expr(right, field))
+            },
+            Expr::Op(op, left, right) => format!("{} {} {}", operand(left), op, operand(right)),
+            Expr::Unop(_, operand_expr) => format!("!{}", operand(operand_expr)),
+            Expr::Popcount(counted) => format!("{}.count_ones() as usize", operand(counted)),
+            Expr::EnumRef {..} => self.registry.constant(&self.module.header, expr).expect("enum references were checked").to_string(),
+            Expr::Unsupported(what) => unreachable!("{} was rejected when lowering", what)
+        }
+    }
+}
+
+///The context of an event, whose header depends on whether it is generic and has a sequence number.
+fn event_context(event: &Event) -> Context {
+    if event.xge {
+        Context::GenericEvent
+    }else {
+        Context::Event {sequence: !event.no_sequence_number}
+    }
+}
+
+///The dividend and divisor of a division that rounds up, as in `(num_buttons + 31) / 32`.
+fn rounded_up(expr: &Expr) -> Option<(&Expr, u64)> {
+    let Expr::Op(op, left, right) = expr else { return None };
+    let (Expr::Op(plus, dividend, added), Expr::Value(divisor)) = (&**left, &**right) else { return None };
+    let rounds_up = op == "/" && plus == "+" && matches!(**added, Expr::Value(added) if added + 1 == *divisor);
+    rounds_up.then_some((dividend, *divisor))
+}
+
+///The value fields of `slots` by their name in the XML, with their name in Rust and their type.
+fn values(slots: &[Slot]) -> HashMap<&str, (&str, &Ty)> {
+    slots.iter().filter_map(|slot| match slot {
+        Slot::Value {xml, name, ty, ..} => Some((xml.as_str(), (name.as_str(), ty))),
+        _ => None
+    }).collect()
+}
+
+///A field of a request as a `usize`, for the fields computed from it.
+///
+///The length of a list that is not sent, as in `QueryTextExtents`, goes by the name of the list
+///followed by `_len`.
+fn encoded_field(slots: &[Slot], field: &str) -> Option<String> {
+    slots.iter().find_map(|slot| match slot {
+        Slot::Value {xml, name, ty, source: Source::Stored} if xml == field => Some(cast(&format!("self.{}", name), ty)),
+        Slot::Value {xml, source: Source::Length(list), ..} if xml == field => Some(format!("self.{}.len()", list)),
+        Slot::List {xml, name, ..} if field.strip_suffix("_len") == Some(xml) => Some(format!("self.{}.len()", name)),
+        _ => None
+    })
+}
+
+///The variable a field is decoded into, which must not hide the parameters of `from_bytes`.
+fn local(name: &str) -> String {
+    if ["bytes", "offset", "order"].contains(&name) { format!("{}_", name) } else { name.to_string() }
+}
+
+///A field as a `usize`.
+fn cast(name: &str, ty: &Ty) -> String {
+    if ty.is_enum {
+        format!("{}.0 as usize", name)
+    }else if ty.prim == Some(Prim::Bool) {
+        format!("usize::from({})", name)
+    }else {
+        format!("{} as usize", name)
+    }
+}
+
+///The name of the `order` parameter of a function with `body`, `_` if the body does not need it.
+fn order_param(body: &str) -> &'static str {
+    if body.contains("order") { "order" } else { "_" }
+}
+
+fn item_name(item: &Item) -> &str {
+    match item {
+        Item::XidType(name) | Item::Typedef {new: name, ..} | Item::Unsupported {name, ..} => name,
+        Item::Enum(values) => &values.name,
+        Item::Struct(container) | Item::Union(container) => &container.name,
+        Item::Request(request) => &request.name,
+        Item::Event(event) | Item::Error(event) => &event.name,
+        Item::EventCopy(copy) | Item::ErrorCopy(copy) => &copy.name
+    }
+}
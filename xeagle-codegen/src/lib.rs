@@ -0,0 +1,129 @@
+//!Generates the protocol bindings of `xeagle` from the XML protocol descriptions of xcb-proto.
+//!
+//!Every XML file becomes a module named after it. For each description it contains:
+//!
+//!- `xidtype`, `xidunion` and `typedef` become type aliases.
+//!- An `enum` becomes a newtype around the integer of the fields that use it, with a constant per
+//!  item. Enums that are masks also get `|` and `contains`.
+//!- A `struct` becomes a struct implementing `Serializable`. Lengths of lists that are given by
+//!  another field are not stored, they are written from the list when encoding.
+//!- A `union` becomes a newtype around its bytes, with methods to read and write each member.
+//!- A `request` becomes a `...Request` struct implementing `Request`, and its reply a `...Reply`
+//!  struct implementing `Serializable`. The optional values after a mask, as in `CreateWindow`,
+//!  are collected in a `...Aux` struct whose mask is computed from the values that are set.
+//!- An `event` or `error` becomes a `...Event` or `...Error` struct implementing `Serializable`,
+//!  together with a constant for its number. Generic events start with the header of
+//!  `GenericEvent`, and their constant is the `event_type` in it.
+//!- Descriptors a request passes become `OwnedFd` fields, handed to the connection by
+//!  `Request::fds`. Those of a reply are not part of its struct, `Request::REPLY_FDS` tells the
+//!  connection how many come with it.
+//!
+//!Descriptions the generator cannot handle, such as lengths computed with `sumof` or replies
+//!passing a list of descriptors whose length is only known from the reply, are left out and listed
+//!in the documentation of their module.
+use std::error;
+use std::fmt::{Display, Formatter};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::{fs, io};
+use crate::emit::{Emitter, Registry};
+use crate::model::Module;
+
+mod emit;
+mod model;
+mod names;
+
+///Why the bindings could not be generated.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    ///A file is not well formed XML.
+    Xml(String),
+    ///A file does not follow the format of the protocol descriptions.
+    Invalid(String)
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Xml(e) => write!(f, "malformed XML: {}", e),
+            Error::Invalid(e) => write!(f, "invalid protocol description: {}", e)
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+///Generates a module for every XML file in `xml_dir` into `out_dir`.
+///
+///Each module goes into a file named after it. `protocol.rs` declares all of them and is what
+///the crate includes. The modules refer to the `Request` trait and the decoding helpers of the
+///module that includes them, and to `crate::serializable`.
+pub fn generate(xml_dir: &Path, out_dir: &Path) -> Result<(), Error> {
+    let mut paths: Vec<_> = fs::read_dir(xml_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    paths.retain(|path| path.extension().is_some_and(|extension| extension == "xml"));
+    paths.sort();
+
+    let mut modules = Vec::new();
+    for path in paths {
+        let module = Module::parse(&fs::read_to_string(&path)?).map_err(|e| match e {
+            Error::Xml(e) => Error::Xml(format!("{}: {}", path.display(), e)),
+            Error::Invalid(e) => Error::Invalid(format!("{}: {}", path.display(), e)),
+            e => e
+        })?;
+        modules.push(module);
+    }
+    let modules = in_import_order(modules)?;
+
+    let registry = Registry::new(&modules);
+    let mut index = String::new();
+    for module in &modules {
+        let (code, skipped) = Emitter::new(&registry, module).emit();
+        fs::write(out_dir.join(format!("{}.rs", module.header)), code)?;
+
+        match &module.extension {
+            Some(extension) => writeln!(index, "///The `{}` extension.", extension.xname).unwrap(),
+            None => writeln!(index, "///The core protocol.").unwrap()
+        }
+        if !skipped.is_empty() {
+            writeln!(index, "///\n///Not generated:\n///").unwrap();
+            for reason in skipped {
+                writeln!(index, "///- {}", reason).unwrap();
+            }
+        }
+        writeln!(index, "pub mod {0} {{\n    include!(concat!(env!(\"OUT_DIR\"), \"/{0}.rs\"));\n}}\n", module.header).unwrap();
+    }
+    fs::write(out_dir.join("protocol.rs"), index)?;
+    Ok(())
+}
+
+///Sorts the modules so every module comes after the ones it imports, starting with the core
+///protocol, which every module imports implicitly.
+fn in_import_order(mut modules: Vec<Module>) -> Result<Vec<Module>, Error> {
+    let mut sorted: Vec<Module> = Vec::new();
+    if let Some(core) = modules.iter().position(|module| module.header == "xproto") {
+        sorted.push(modules.remove(core));
+    }
+    while !modules.is_empty() {
+        let ready = modules.iter().position(|module| {
+            module.imports.iter().all(|name| sorted.iter().any(|done| &done.header == name))
+        });
+        match ready {
+            Some(ready) => sorted.push(modules.remove(ready)),
+            None => {
+                let names: Vec<&str> = modules.iter().map(|module| module.header.as_str()).collect();
+                return Err(Error::Invalid(format!("missing or circular imports among {}", names.join(", "))));
+            }
+        }
+    }
+    Ok(sorted)
+}
@@ -0,0 +1,307 @@
+//!The protocol descriptions as read from the XML, before any names or types are resolved.
+use roxmltree::Node;
+use crate::Error;
+
+///One XML file, the core protocol or an extension.
+pub(crate) struct Module {
+    ///The base name of the file, which becomes the name of the Rust module.
+    pub header: String,
+    pub extension: Option<Extension>,
+    pub imports: Vec<String>,
+    pub items: Vec<Item>
+}
+
+///What identifies an extension to the server.
+pub(crate) struct Extension {
+    ///The name passed to `QueryExtension`.
+    pub xname: String,
+    pub major_version: u32,
+    pub minor_version: u32
+}
+
+pub(crate) enum Item {
+    ///An `xidtype` or `xidunion`, a 32 bit resource ID.
+    XidType(String),
+    Typedef {old: String, new: String},
+    Enum(Enum),
+    Struct(Container),
+    Union(Container),
+    Request(Request),
+    Event(Event),
+    EventCopy(Copy),
+    Error(Event),
+    ErrorCopy(Copy),
+    ///Something the generator cannot describe at all, kept to be reported.
+    Unsupported {name: String, reason: String}
+}
+
+pub(crate) struct Enum {
+    pub name: String,
+    pub items: Vec<EnumItem>,
+    pub doc: Option<String>
+}
+
+pub(crate) struct EnumItem {
+    pub name: String,
+    pub value: u64,
+    ///Whether the value was given as a bit number, which makes the enum a mask.
+    pub bit: bool
+}
+
+///A struct or union.
+pub(crate) struct Container {
+    pub name: String,
+    pub members: Vec<Member>,
+    pub doc: Option<String>
+}
+
+pub(crate) struct Request {
+    pub name: String,
+    pub opcode: u8,
+    pub members: Vec<Member>,
+    pub reply: Option<Vec<Member>>,
+    pub doc: Option<String>
+}
+
+///An event or an error, which share their layout apart from the header.
+pub(crate) struct Event {
+    pub name: String,
+    pub number: u8,
+    pub no_sequence_number: bool,
+    pub xge: bool,
+    pub members: Vec<Member>,
+    pub doc: Option<String>
+}
+
+///An `eventcopy` or `errorcopy`, the same layout under another name and number.
+pub(crate) struct Copy {
+    pub name: String,
+    pub number: u8,
+    pub reference: String
+}
+
+pub(crate) enum Member {
+    Pad(usize),
+    ///Padding up to a multiple of the given number of bytes.
+    Align(usize),
+    Field(Field),
+    List {name: String, ty: String, len: Option<Expr>},
+    ExprField {name: String, ty: String, expr: Expr},
+    Switch(Switch),
+    Fd(String)
+}
+
+pub(crate) struct Field {
+    pub name: String,
+    pub ty: String,
+    ///The enum the value is restricted to, from `enum` or `mask`.
+    pub values: Option<String>
+}
+
+pub(crate) struct Switch {
+    pub name: String,
+    pub expr: Expr,
+    pub cases: Vec<BitCase>,
+    ///Whether there are `case` elements, which compare instead of testing bits.
+    pub has_cases: bool
+}
+
+pub(crate) struct BitCase {
+    pub bits: Vec<Expr>,
+    pub members: Vec<Member>
+}
+
+#[derive(Clone)]
+pub(crate) enum Expr {
+    FieldRef(String),
+    Value(u64),
+    Op(String, Box<Expr>, Box<Expr>),
+    Unop(String, Box<Expr>),
+    Popcount(Box<Expr>),
+    EnumRef {values: String, item: String},
+    ///An expression the generator cannot evaluate, such as `sumof`.
+    Unsupported(String)
+}
+
+impl Module {
+    pub(crate) fn parse(text: &str) -> Result<Module, Error> {
+        let document = roxmltree::Document::parse(text).map_err(|e| Error::Xml(e.to_string()))?;
+        let root = document.root_element();
+        let header = required(root, "header")?.to_string();
+        let extension = match root.attribute("extension-xname") {
+            Some(xname) => Some(Extension {
+                xname: xname.to_string(),
+                major_version: number(root, "major-version")? as u32,
+                minor_version: number(root, "minor-version")? as u32
+            }),
+            None => None
+        };
+
+        let mut imports = Vec::new();
+        let mut items = Vec::new();
+        for node in root.children().filter(Node::is_element) {
+            let name = || required(node, "name").map(str::to_string);
+            let item = match node.tag_name().name() {
+                "import" => {
+                    imports.push(text_of(node));
+                    continue;
+                }
+                "xidtype" | "xidunion" => Item::XidType(name()?),
+                "typedef" => Item::Typedef {old: required(node, "oldname")?.to_string(), new: required(node, "newname")?.to_string()},
+                "enum" => Item::Enum(parse_enum(node)?),
+                "struct" => Item::Struct(Container {name: name()?, members: members(node)?, doc: doc(node)}),
+                "union" => Item::Union(Container {name: name()?, members: members(node)?, doc: doc(node)}),
+                "request" => {
+                    let reply = match node.children().find(|child| child.has_tag_name("reply")) {
+                        Some(reply) => Some(members(reply)?),
+                        None => None
+                    };
+                    Item::Request(Request {name: name()?, opcode: number(node, "opcode")? as u8, members: members(node)?, reply, doc: doc(node)})
+                }
+                "event" | "error" if required(node, "number")?.starts_with('-') => {
+                    //GLX numbers an error that stands for the core errors relative to its first error.
+                    Item::Unsupported {name: name()?, reason: "negative numbers are not supported".to_string()}
+                }
+                "event" | "error" => {
+                    let event = Event {
+                        name: name()?,
+                        number: number(node, "number")? as u8,
+                        no_sequence_number: node.attribute("no-sequence-number") == Some("true"),
+                        xge: node.attribute("xge") == Some("true"),
+                        members: members(node)?,
+                        doc: doc(node)
+                    };
+                    if node.has_tag_name("event") { Item::Event(event) } else { Item::Error(event) }
+                }
+                "eventcopy" | "errorcopy" => {
+                    let copy = Copy {name: name()?, number: number(node, "number")? as u8, reference: required(node, "ref")?.to_string()};
+                    if node.has_tag_name("eventcopy") { Item::EventCopy(copy) } else { Item::ErrorCopy(copy) }
+                }
+                other => Item::Unsupported {name: node.attribute("name").unwrap_or(other).to_string(), reason: format!("`{}` is not supported", other)}
+            };
+            items.push(item);
+        }
+        Ok(Module {header, extension, imports, items})
+    }
+}
+
+fn parse_enum(node: Node) -> Result<Enum, Error> {
+    let mut items: Vec<EnumItem> = Vec::new();
+    for item in node.children().filter(|child| child.has_tag_name("item")) {
+        let name = required(item, "name")?.to_string();
+        let given = item.children().find(Node::is_element);
+        let (value, bit) = match given {
+            Some(bit) if bit.has_tag_name("bit") => (1u64 << parse_number(&text_of(bit))?, true),
+            Some(value) => match expr(value) {
+                Expr::Value(value) => (value, false),
+                _ => return Err(Error::Invalid(format!("the value of {} is not a number", name)))
+            },
+            //Items without a value continue from the one before.
+            None => (items.last().map_or(0, |last| last.value + 1), false)
+        };
+        items.push(EnumItem {name, value, bit});
+    }
+    Ok(Enum {name: required(node, "name")?.to_string(), items, doc: doc(node)})
+}
+
+fn members(node: Node) -> Result<Vec<Member>, Error> {
+    let mut members = Vec::new();
+    for child in node.children().filter(Node::is_element) {
+        let member = match child.tag_name().name() {
+            "pad" => match child.attribute("align") {
+                Some(align) => Member::Align(parse_number(align)? as usize),
+                None => Member::Pad(number(child, "bytes")? as usize)
+            },
+            "field" => Member::Field(Field {
+                name: required(child, "name")?.to_string(),
+                ty: required(child, "type")?.to_string(),
+                values: child.attribute("enum").or(child.attribute("mask")).map(str::to_string)
+            }),
+            "list" => Member::List {
+                name: required(child, "name")?.to_string(),
+                ty: required(child, "type")?.to_string(),
+                len: child.children().find(Node::is_element).map(expr)
+            },
+            "exprfield" => Member::ExprField {
+                name: required(child, "name")?.to_string(),
+                ty: required(child, "type")?.to_string(),
+                expr: child.children().find(Node::is_element).map_or(Expr::Unsupported("empty exprfield".to_string()), expr)
+            },
+            "switch" => Member::Switch(switch(child)?),
+            "fd" => Member::Fd(required(child, "name")?.to_string()),
+            //Documentation, the alignment a request or reply needs at its start, and the bits of a bitcase.
+            "doc" | "reply" | "required_start_align" | "enumref" => continue,
+            other => return Err(Error::Invalid(format!("unknown member `{}`", other)))
+        };
+        members.push(member);
+    }
+    Ok(members)
+}
+
+fn switch(node: Node) -> Result<Switch, Error> {
+    let mut children = node.children().filter(Node::is_element);
+    let selector = children.next().map_or(Expr::Unsupported("empty switch".to_string()), expr);
+    let mut cases = Vec::new();
+    let mut has_cases = false;
+    for case in children {
+        has_cases |= case.has_tag_name("case");
+        let bits = case.children().filter(|child| child.has_tag_name("enumref")).map(expr).collect();
+        let members = members(case)?;
+        cases.push(BitCase {bits, members});
+    }
+    Ok(Switch {name: required(node, "name")?.to_string(), expr: selector, cases, has_cases})
+}
+
+fn expr(node: Node) -> Expr {
+    let mut operands = node.children().filter(Node::is_element).map(expr);
+    match node.tag_name().name() {
+        "fieldref" => Expr::FieldRef(text_of(node)),
+        "value" => parse_number(&text_of(node)).map_or_else(|e| Expr::Unsupported(e.to_string()), Expr::Value),
+        "bit" => parse_number(&text_of(node)).map_or_else(|e| Expr::Unsupported(e.to_string()), |bit| Expr::Value(1 << bit)),
+        "op" => match (node.attribute("op"), operands.next(), operands.next()) {
+            (Some(op), Some(left), Some(right)) => Expr::Op(op.to_string(), Box::new(left), Box::new(right)),
+            _ => Expr::Unsupported("incomplete op".to_string())
+        },
+        "unop" => match (node.attribute("op"), operands.next()) {
+            (Some(op), Some(operand)) => Expr::Unop(op.to_string(), Box::new(operand)),
+            _ => Expr::Unsupported("incomplete unop".to_string())
+        },
+        "popcount" => match operands.next() {
+            Some(operand) => Expr::Popcount(Box::new(operand)),
+            None => Expr::Unsupported("empty popcount".to_string())
+        },
+        "enumref" => Expr::EnumRef {values: node.attribute("ref").unwrap_or_default().to_string(), item: text_of(node)},
+        other => Expr::Unsupported(format!("`{}`", other))
+    }
+}
+
+///The brief description in the `doc` element.
+fn doc(node: Node) -> Option<String> {
+    let brief = node.children().find(|child| child.has_tag_name("doc"))?
+        .children().find(|child| child.has_tag_name("brief"))?;
+    let text = text_of(brief);
+    (!text.is_empty()).then_some(text)
+}
+
+///The text inside `node` with its whitespace collapsed.
+fn text_of(node: Node) -> String {
+    node.text().unwrap_or_default().split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn required<'a>(node: Node<'a, '_>, attribute: &str) -> Result<&'a str, Error> {
+    node.attribute(attribute).ok_or_else(|| Error::Invalid(format!("`{}` without `{}`", node.tag_name().name(), attribute)))
+}
+
+fn number(node: Node, attribute: &str) -> Result<u64, Error> {
+    parse_number(required(node, attribute)?)
+}
+
+///Parses a decimal or `0x` hexadecimal number.
+fn parse_number(text: &str) -> Result<u64, Error> {
+    let text = text.trim();
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse()
+    };
+    parsed.map_err(|_| Error::Invalid(format!("`{}` is not a number", text)))
+}
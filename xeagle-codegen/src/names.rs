@@ -0,0 +1,91 @@
+//!Turning the names of the XML into Rust identifiers.
+
+///Words Rust reserves, which get a `_` appended when the XML uses them as names.
+const KEYWORDS: [&str; 38] = [
+    "abstract", "as", "async", "await", "box", "break", "const", "continue", "crate", "do", "dyn", "else", "enum",
+    "extern", "final", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "override",
+    "priv", "pub", "ref", "return", "self", "static", "struct", "super", "trait", "type", "where"
+];
+
+///The name of a type, in CamelCase.
+///
+///All caps names such as `VISUALID` or `CHAR2B` are turned into `Visualid` and `Char2b`, names that
+///already mix cases only get their first letter capitalized.
+pub(crate) fn type_name(name: &str) -> String {
+    if name.chars().any(|c| c.is_ascii_lowercase()) {
+        let mut chars = name.chars();
+        return chars.next().map(|first| first.to_ascii_uppercase()).into_iter().chain(chars).filter(|&c| c != '_').collect();
+    }
+    name.split('_').map(|part| {
+        let mut chars = part.chars();
+        chars.next().into_iter().chain(chars.flat_map(char::to_lowercase)).collect::<String>()
+    }).collect()
+}
+
+///The name of a field, in snake_case.
+pub(crate) fn field_name(name: &str) -> String {
+    let name = words(name).join("_").to_ascii_lowercase();
+    if KEYWORDS.contains(&name.as_str()) { format!("{}_", name) } else { name }
+}
+
+///The name of a constant, in UPPER_SNAKE_CASE.
+///
+///Names that start with a digit, like some of the RandR rotations, get a leading `_`.
+pub(crate) fn const_name(name: &str) -> String {
+    let name = words(name).join("_").to_ascii_uppercase();
+    if name.starts_with(|c: char| c.is_ascii_digit()) { format!("_{}", name) } else { name }
+}
+
+///Splits a name at underscores and where its case changes, keeping acronyms together.
+fn words(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' {
+            words.push(std::mem::take(&mut word));
+            continue;
+        }
+        if c.is_ascii_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(char::is_ascii_lowercase);
+            if previous.is_ascii_lowercase() || previous.is_ascii_digit() || (previous.is_ascii_uppercase() && next_lower) {
+                words.push(std::mem::take(&mut word));
+            }
+        }
+        word.push(c);
+    }
+    words.push(word);
+    words.retain(|word| !word.is_empty());
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_type_names() {
+        assert_eq!(type_name("WINDOW"), "Window");
+        assert_eq!(type_name("CHAR2B"), "Char2b");
+        assert_eq!(type_name("ClientMessageData"), "ClientMessageData");
+        assert_eq!(type_name("BUTTON_MASK"), "ButtonMask");
+    }
+
+    #[test]
+    fn converts_field_names() {
+        assert_eq!(field_name("border_width"), "border_width");
+        assert_eq!(field_name("sizeID"), "size_id");
+        assert_eq!(field_name("type"), "type_");
+    }
+
+    #[test]
+    fn converts_constant_names() {
+        assert_eq!(const_name("InputOutput"), "INPUT_OUTPUT");
+        assert_eq!(const_name("XYPixmap"), "XY_PIXMAP");
+        assert_eq!(const_name("Button1Motion"), "BUTTON1_MOTION");
+        assert_eq!(const_name("WM_NAME"), "WM_NAME");
+        assert_eq!(const_name("Rotate_90"), "ROTATE_90");
+        assert_eq!(const_name("1"), "_1");
+    }
+}
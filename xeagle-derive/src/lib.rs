@@ -0,0 +1,260 @@
+//!`#[derive(Serializable)]` for the protocol structures of `xeagle`.
+//!
+//!Structs with named fields are encoded field after field in declaration order. Field attributes
+//!describe what lies between and inside the fields:
+//!
+//!- `#[serializable(pad = N)]` skips `N` unused bytes before the field.
+//!- `#[serializable(align = N)]` skips unused bytes before the field up to a multiple of `N`,
+//!  counted from the start of the struct.
+//!- `#[serializable(len = field)]` on a `Vec<T>` or `String` takes the number of items from
+//!  `field`, declared before it. When encoding, `field` is written as the actual length.
+//!
+//!`#[serializable(align = N)]` on the struct itself pads its end to a multiple of `N`, such as 4
+//!for requests ending in a list, or 32 for events.
+//!
+//!Enums must consist of unit variants with explicit discriminants and a `#[repr]` of an unsigned
+//!or signed integer, which is what goes on the wire. Unknown values fail to decode.
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Expr, Fields, Ident, LitInt, Type};
+
+#[proc_macro_derive(Serializable, attributes(serializable))]
+pub fn derive_serializable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let result = match &input.data {
+        Data::Struct(_) => derive_struct(&input),
+        Data::Enum(_) => derive_enum(&input),
+        Data::Union(_) => Err(Error::new_spanned(&input.ident, "Serializable cannot be derived for unions"))
+    };
+    result.unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+///What the attributes of a field or struct ask for.
+#[derive(Default)]
+struct Options {
+    pad: Option<usize>,
+    align: Option<usize>,
+    len: Option<Ident>
+}
+
+fn options(attrs: &[syn::Attribute]) -> syn::Result<Options> {
+    let mut options = Options::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serializable")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("pad") {
+                options.pad = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            }else if meta.path.is_ident("align") {
+                let align: usize = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                if align == 0 {
+                    return Err(meta.error("alignment must be at least 1"));
+                }
+                options.align = Some(align);
+            }else if meta.path.is_ident("len") {
+                options.len = Some(meta.value()?.parse()?);
+            }else {
+                return Err(meta.error("expected `pad`, `align` or `len`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(options)
+}
+
+///How a field with `len` holds its items.
+enum Counted {
+    List(Box<Type>),
+    String
+}
+
+///Sorts out `Vec<T>` and `String`, the only types a length can be given for.
+fn counted(ty: &Type) -> Option<Counted> {
+    let Type::Path(path) = ty else { return None };
+    let segment = path.path.segments.last()?;
+    if segment.ident == "String" {
+        return Some(Counted::String);
+    }
+    if segment.ident != "Vec" {
+        return None;
+    }
+    let syn::PathArguments::AngleBracketed(arguments) = &segment.arguments else { return None };
+    match arguments.args.first()? {
+        syn::GenericArgument::Type(item) => Some(Counted::List(Box::new(item.clone()))),
+        _ => None
+    }
+}
+
+fn derive_struct(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let Data::Struct(data) = &input.data else { unreachable!() };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(name, "Serializable can only be derived for structs with named fields"));
+    };
+    let struct_options = options(&input.attrs)?;
+    if struct_options.pad.is_some() || struct_options.len.is_some() {
+        return Err(Error::new_spanned(name, "only `align` applies to the struct itself"));
+    }
+
+    //The fields that give the length of another, so encoding can write the actual length.
+    let mut lengths = Vec::new();
+    for field in &fields.named {
+        if let Some(len) = options(&field.attrs)?.len {
+            let declared_before = fields.named.iter()
+                .take_while(|other| other.ident != field.ident)
+                .any(|other| other.ident.as_ref() == Some(&len));
+            if !declared_before {
+                return Err(Error::new_spanned(&len, "the length must be a field declared before the list"));
+            }
+            lengths.push((len, field.ident.clone().unwrap(), counted(&field.ty)));
+        }
+    }
+
+    let mut encode = Vec::new();
+    let mut decode = Vec::new();
+    let mut names = Vec::new();
+    for field in &fields.named {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let field_options = options(&field.attrs)?;
+        names.push(ident);
+
+        if let Some(pad) = field_options.pad {
+            encode.push(quote! { out.resize(out.len() + #pad, 0); });
+            decode.push(quote! { offset += #pad; });
+        }
+        if let Some(align) = field_options.align {
+            encode.push(quote! { out.resize(out.len().next_multiple_of(#align), 0); });
+            decode.push(quote! { offset = offset.next_multiple_of(#align); });
+        }
+        if field_options.pad.is_some() || field_options.align.is_some() {
+            decode.push(quote! {
+                if bytes.len() < offset {
+                    return Err(::xeagle::serializable::ParseError::InsufficientData {needed: offset, available: bytes.len()});
+                }
+            });
+        }
+
+        let rest = quote! { bytes.get(offset..).unwrap_or_default() };
+        match (&field_options.len, counted(ty)) {
+            (Some(len), Some(Counted::List(item))) => {
+                encode.push(quote! { out.extend(::xeagle::serializable::list_bytes(&self.#ident, order)); });
+                decode.push(quote! {
+                    let (#ident, consumed) = ::xeagle::serializable::list_from_bytes::<#item>(#rest, #len as usize, order)?;
+                    offset += consumed;
+                });
+            }
+            (Some(len), Some(Counted::String)) => {
+                encode.push(quote! { out.extend(::xeagle::serializable::Serializable::bytes(&self.#ident, order)); });
+                decode.push(quote! {
+                    let count = #len as usize;
+                    let rest = #rest;
+                    let Some(rest) = rest.get(..count) else {
+                        return Err(::xeagle::serializable::ParseError::InsufficientData {needed: count, available: rest.len()});
+                    };
+                    let (#ident, consumed) = <String as ::xeagle::serializable::Serializable>::from_bytes(rest, order)?;
+                    offset += consumed;
+                });
+            }
+            (Some(_), None) => return Err(Error::new_spanned(ty, "`len` needs a `Vec<T>` or `String` field")),
+            (None, _) => {
+                let length_of = lengths.iter().find(|(len, _, _)| len == ident);
+                let value = match length_of {
+                    Some((_, list, Some(Counted::String))) => quote! { (self.#list.chars().count() as #ty) },
+                    Some((_, list, _)) => quote! { (self.#list.len() as #ty) },
+                    None => quote! { self.#ident }
+                };
+                encode.push(quote! { out.extend(::xeagle::serializable::Serializable::bytes(&#value, order)); });
+                decode.push(quote! {
+                    let (#ident, consumed) = <#ty as ::xeagle::serializable::Serializable>::from_bytes(#rest, order)?;
+                    offset += consumed;
+                });
+            }
+        }
+    }
+
+    if let Some(align) = struct_options.align {
+        encode.push(quote! { out.resize(out.len().next_multiple_of(#align), 0); });
+        decode.push(quote! {
+            offset = offset.next_multiple_of(#align);
+            if bytes.len() < offset {
+                return Err(::xeagle::serializable::ParseError::InsufficientData {needed: offset, available: bytes.len()});
+            }
+        });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::xeagle::serializable::Serializable for #name #ty_generics #where_clause {
+            fn bytes(&self, order: ::xeagle::serializable::ByteOrder) -> Vec<u8> {
+                let mut out = Vec::new();
+                #(#encode)*
+                out
+            }
+
+            #[allow(unused_mut)]
+            fn from_bytes(bytes: &[u8], order: ::xeagle::serializable::ByteOrder) -> Result<(Self, usize), ::xeagle::serializable::ParseError> {
+                let mut offset = 0usize;
+                #(#decode)*
+                Ok((Self {#(#names),*}, offset))
+            }
+        }
+    })
+}
+
+///The integer type given in `#[repr(...)]`.
+fn repr(input: &DeriveInput) -> syn::Result<Ident> {
+    const INTEGERS: [&str; 8] = ["u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64"];
+    let mut found = None;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("repr")) {
+        attr.parse_nested_meta(|meta| {
+            if let Some(ident) = meta.path.get_ident().filter(|ident| INTEGERS.contains(&ident.to_string().as_str())) {
+                found = Some(ident.clone());
+            }
+            Ok(())
+        })?;
+    }
+    found.ok_or_else(|| Error::new(Span::call_site(), "Serializable enums need a `#[repr]` of an integer type"))
+}
+
+fn derive_enum(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let Data::Enum(data) = &input.data else { unreachable!() };
+    let repr = repr(input)?;
+
+    let mut variants = Vec::new();
+    let mut discriminants: Vec<&Expr> = Vec::new();
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(Error::new_spanned(variant, "Serializable enums can only have unit variants"));
+        }
+        let Some((_, discriminant)) = &variant.discriminant else {
+            return Err(Error::new_spanned(variant, "every variant needs an explicit discriminant"));
+        };
+        variants.push(&variant.ident);
+        discriminants.push(discriminant);
+    }
+
+    let unknown = format!("unknown {} {{}}", name);
+    let constants: Vec<Ident> = (0..variants.len()).map(|i| format_ident!("VARIANT_{}", i)).collect();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::xeagle::serializable::Serializable for #name #ty_generics #where_clause {
+            fn bytes(&self, order: ::xeagle::serializable::ByteOrder) -> Vec<u8> {
+                let value: #repr = match self {
+                    #(Self::#variants => #discriminants),*
+                };
+                ::xeagle::serializable::Serializable::bytes(&value, order)
+            }
+
+            fn from_bytes(bytes: &[u8], order: ::xeagle::serializable::ByteOrder) -> Result<(Self, usize), ::xeagle::serializable::ParseError> {
+                #(const #constants: #repr = #discriminants;)*
+                let (value, consumed) = <#repr as ::xeagle::serializable::Serializable>::from_bytes(bytes, order)?;
+                let variant = match value {
+                    #(#constants => Self::#variants,)*
+                    _ => return Err(::xeagle::serializable::ParseError::InvalidValue(format!(#unknown, value)))
+                };
+                Ok((variant, consumed))
+            }
+        }
+    })
+}